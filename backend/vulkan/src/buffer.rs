@@ -1,18 +1,45 @@
-use super::Context;
+use super::{CommandBuffer, Context};
 use ash::{
     version::{DeviceV1_0, InstanceV1_0},
     vk,
 };
 use std::ptr;
-use tephra::buffer::{BufferApi, BufferError, BufferHandle, BufferUsage, MappingError, Property};
+use tephra::buffer::{
+    AllocationError, BufferApi, BufferError, BufferHandle, BufferUsage, MappingError, Property,
+};
 
 impl BufferApi for Context {
     fn destroy(&self, buffer: BufferHandle) {
-        let data = self.buffers.get(buffer);
+        let data = self.buffers.remove(buffer).expect("Invalid buffer handle");
         unsafe {
-            self.device.destroy_buffer(data.buffer, None);
+            data.destroy(self);
         }
     }
+    fn destroy_deferred(&self, buffer: BufferHandle) {
+        self.destroy_after_use(buffer);
+    }
+    fn copy_buffer(
+        &self,
+        src: BufferHandle,
+        src_offset: u64,
+        dst: BufferHandle,
+        dst_offset: u64,
+        size: u64,
+    ) {
+        let src_data = self.buffers.get(src);
+        let dst_data = self.buffers.get(dst);
+        let region = vk::BufferCopy {
+            src_offset,
+            dst_offset,
+            size,
+        };
+        let command_buffer = CommandBuffer::record(self, "BufferCopy", |command_buffer| unsafe {
+            self.device
+                .cmd_copy_buffer(command_buffer, src_data.buffer, dst_data.buffer, &[region]);
+        });
+        self.present_queue
+            .submit(self, &[], &[], &[], command_buffer);
+    }
     fn allocate(
         &self,
         property: Property,
@@ -45,12 +72,22 @@ impl BufferApi for Context {
             let vertex_input_buffer_memory_req = context
                 .device
                 .get_buffer_memory_requirements(vertex_input_buffer);
-            let vertex_input_buffer_memory_index = find_memorytype_index(
+            let vertex_input_buffer_memory_index = match find_memorytype_index_for_property(
                 &vertex_input_buffer_memory_req,
                 &device_memory_properties,
-                property_to_vk_property(property),
-            )
-            .expect("Unable to find suitable memorytype for the vertex buffer.");
+                property,
+            ) {
+                Some(index) => index,
+                None => {
+                    context.device.destroy_buffer(vertex_input_buffer, None);
+                    return Err(BufferError::AllocationError(
+                        AllocationError::UnsupportedMemorytype,
+                    ));
+                }
+            };
+            let memory_flags = device_memory_properties.memory_types
+                [vertex_input_buffer_memory_index as usize]
+                .property_flags;
 
             let vertex_buffer_allocate_info = vk::MemoryAllocateInfo {
                 s_type: vk::StructureType::MEMORY_ALLOCATE_INFO,
@@ -70,6 +107,7 @@ impl BufferApi for Context {
                 buffer: vertex_input_buffer,
                 memory: vertex_input_buffer_memory,
                 size,
+                coherent: memory_flags.contains(vk::MemoryPropertyFlags::HOST_COHERENT),
             };
             Ok(self.buffers.insert(inner_buffer))
         }
@@ -89,6 +127,38 @@ impl BufferApi for Context {
         self.device.unmap_memory(data.memory);
     }
 
+    unsafe fn flush(&self, buffer: BufferHandle) -> Result<(), MappingError> {
+        let data = self.buffers.get(buffer);
+        if data.coherent {
+            return Ok(());
+        }
+        let range = vk::MappedMemoryRange {
+            memory: data.memory,
+            offset: 0,
+            size: vk::WHOLE_SIZE,
+            ..Default::default()
+        };
+        self.device
+            .flush_mapped_memory_ranges(&[range])
+            .map_err(|_| MappingError::SyncFailed)
+    }
+
+    unsafe fn invalidate(&self, buffer: BufferHandle) -> Result<(), MappingError> {
+        let data = self.buffers.get(buffer);
+        if data.coherent {
+            return Ok(());
+        }
+        let range = vk::MappedMemoryRange {
+            memory: data.memory,
+            offset: 0,
+            size: vk::WHOLE_SIZE,
+            ..Default::default()
+        };
+        self.device
+            .invalidate_mapped_memory_ranges(&[range])
+            .map_err(|_| MappingError::SyncFailed)
+    }
+
     unsafe fn size(&self, buffer: BufferHandle) -> u64 {
         let data = self.buffers.get(buffer);
         data.size
@@ -99,24 +169,150 @@ pub struct BufferData {
     pub buffer: vk::Buffer,
     pub memory: vk::DeviceMemory,
     pub size: u64,
+    /// Non coherent memory needs explicit flushes and invalidates
+    pub coherent: bool,
+}
+
+impl BufferData {
+    /// # Safety
+    ///
+    /// The device must not use the buffer anymore.
+    pub unsafe fn destroy(self, ctx: &Context) {
+        ctx.device.destroy_buffer(self.buffer, None);
+        ctx.device.free_memory(self.memory, None);
+    }
+}
+
+/// Records a fill of `size` bytes at `offset`, or of the rest of the buffer without a
+/// size. Buffers are not tracked, so the fill waits for and is visible to every access.
+pub(crate) unsafe fn record_fill(
+    ctx: &Context,
+    command_buffer: vk::CommandBuffer,
+    buffer: BufferHandle,
+    offset: u64,
+    size: Option<u64>,
+    data: u32,
+) {
+    let buffer_data = ctx.buffers.get(buffer);
+    assert!(
+        offset + size.unwrap_or(0) <= buffer_data.size,
+        "Fill exceeds the buffer size of {} bytes",
+        buffer_data.size
+    );
+    let size = size.unwrap_or(vk::WHOLE_SIZE);
+    let barrier = |src_access_mask, dst_access_mask| {
+        vk::BufferMemoryBarrier {
+            src_access_mask,
+            dst_access_mask,
+            src_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
+            dst_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
+            buffer: buffer_data.buffer,
+            offset,
+            size,
+            ..Default::default()
+        }
+    };
+    ctx.device.cmd_pipeline_barrier(
+        command_buffer,
+        vk::PipelineStageFlags::ALL_COMMANDS,
+        vk::PipelineStageFlags::TRANSFER,
+        vk::DependencyFlags::empty(),
+        &[],
+        &[barrier(
+            vk::AccessFlags::MEMORY_WRITE,
+            vk::AccessFlags::TRANSFER_WRITE,
+        )],
+        &[],
+    );
+    ctx.device
+        .cmd_fill_buffer(command_buffer, buffer_data.buffer, offset, size, data);
+    ctx.device.cmd_pipeline_barrier(
+        command_buffer,
+        vk::PipelineStageFlags::TRANSFER,
+        vk::PipelineStageFlags::ALL_COMMANDS,
+        vk::DependencyFlags::empty(),
+        &[],
+        &[barrier(
+            vk::AccessFlags::TRANSFER_WRITE,
+            vk::AccessFlags::MEMORY_READ | vk::AccessFlags::MEMORY_WRITE,
+        )],
+        &[],
+    );
 }
 
 fn bitflag_to_bufferflags(usage: BufferUsage) -> vk::BufferUsageFlags {
-    match usage {
-        BufferUsage::Vertex => vk::BufferUsageFlags::VERTEX_BUFFER,
-        BufferUsage::Index => vk::BufferUsageFlags::INDEX_BUFFER,
-        BufferUsage::Uniform => vk::BufferUsageFlags::UNIFORM_BUFFER,
-        BufferUsage::Storage => vk::BufferUsageFlags::STORAGE_BUFFER,
+    let mut flags = vk::BufferUsageFlags::empty();
+    if usage.contains(BufferUsage::VERTEX) {
+        flags |= vk::BufferUsageFlags::VERTEX_BUFFER;
+    }
+    if usage.contains(BufferUsage::INDEX) {
+        flags |= vk::BufferUsageFlags::INDEX_BUFFER;
+    }
+    if usage.contains(BufferUsage::UNIFORM) {
+        flags |= vk::BufferUsageFlags::UNIFORM_BUFFER;
+    }
+    if usage.contains(BufferUsage::STORAGE) {
+        flags |= vk::BufferUsageFlags::STORAGE_BUFFER;
+    }
+    if usage.contains(BufferUsage::INDIRECT) {
+        flags |= vk::BufferUsageFlags::INDIRECT_BUFFER;
+    }
+    if usage.contains(BufferUsage::UNIFORM_TEXEL) {
+        flags |= vk::BufferUsageFlags::UNIFORM_TEXEL_BUFFER;
     }
+    if usage.contains(BufferUsage::STORAGE_TEXEL) {
+        flags |= vk::BufferUsageFlags::STORAGE_TEXEL_BUFFER;
+    }
+    flags
 }
 
-fn property_to_vk_property(property: Property) -> vk::MemoryPropertyFlags {
+/// Memory flags for each property, ordered from the most to the least preferred
+fn property_fallback_chain(property: Property) -> Vec<vk::MemoryPropertyFlags> {
+    use ash::vk::MemoryPropertyFlags as Flags;
     match property {
-        Property::HostVisible => vk::MemoryPropertyFlags::HOST_VISIBLE,
-        Property::DeviceLocal => vk::MemoryPropertyFlags::DEVICE_LOCAL,
+        Property::HostVisible => {
+            vec![
+                Flags::HOST_VISIBLE | Flags::HOST_COHERENT,
+                Flags::HOST_VISIBLE,
+            ]
+        }
+        Property::DeviceLocal => vec![Flags::DEVICE_LOCAL, Flags::empty()],
+        Property::Upload => {
+            vec![
+                Flags::DEVICE_LOCAL | Flags::HOST_VISIBLE | Flags::HOST_COHERENT,
+                Flags::HOST_VISIBLE | Flags::HOST_COHERENT,
+                Flags::HOST_VISIBLE,
+            ]
+        }
+        Property::Readback => {
+            vec![
+                Flags::HOST_VISIBLE | Flags::HOST_CACHED | Flags::HOST_COHERENT,
+                Flags::HOST_VISIBLE | Flags::HOST_CACHED,
+                Flags::HOST_VISIBLE,
+            ]
+        }
+        Property::Transient => {
+            vec![
+                Flags::DEVICE_LOCAL | Flags::LAZILY_ALLOCATED,
+                Flags::DEVICE_LOCAL,
+                Flags::empty(),
+            ]
+        }
     }
 }
 
+/// Walks the fallback chain of `property` and returns the first memory type that
+/// satisfies the requirements
+pub fn find_memorytype_index_for_property(
+    memory_req: &vk::MemoryRequirements,
+    memory_prop: &vk::PhysicalDeviceMemoryProperties,
+    property: Property,
+) -> Option<u32> {
+    property_fallback_chain(property)
+        .into_iter()
+        .find_map(|flags| find_memorytype_index(memory_req, memory_prop, flags))
+}
+
 /// helper function to find the correct memory index
 pub fn find_memorytype_index(
     memory_req: &vk::MemoryRequirements,
@@ -154,3 +350,93 @@ fn find_memorytype_index_f<F: Fn(vk::MemoryPropertyFlags, vk::MemoryPropertyFlag
     }
     None
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ash::vk::MemoryPropertyFlags as Flags;
+
+    fn memory_properties(types: &[Flags]) -> vk::PhysicalDeviceMemoryProperties {
+        let mut properties = vk::PhysicalDeviceMemoryProperties {
+            memory_type_count: types.len() as u32,
+            ..Default::default()
+        };
+        for (memory_type, &property_flags) in properties.memory_types.iter_mut().zip(types) {
+            memory_type.property_flags = property_flags;
+        }
+        properties
+    }
+
+    fn requirements(memory_type_bits: u32) -> vk::MemoryRequirements {
+        vk::MemoryRequirements {
+            size: 256,
+            alignment: 16,
+            memory_type_bits,
+        }
+    }
+
+    #[test]
+    fn discrete_gpus_use_the_preferred_memory() {
+        let properties = memory_properties(&[
+            Flags::DEVICE_LOCAL,
+            Flags::HOST_VISIBLE | Flags::HOST_COHERENT,
+            Flags::HOST_VISIBLE | Flags::HOST_CACHED | Flags::HOST_COHERENT,
+            Flags::DEVICE_LOCAL | Flags::HOST_VISIBLE | Flags::HOST_COHERENT,
+        ]);
+        let find = |property| {
+            find_memorytype_index_for_property(&requirements(!0), &properties, property)
+        };
+        assert_eq!(find(Property::DeviceLocal), Some(0));
+        assert_eq!(find(Property::HostVisible), Some(1));
+        assert_eq!(find(Property::Readback), Some(2));
+        assert_eq!(find(Property::Upload), Some(3));
+        assert_eq!(find(Property::Transient), Some(0));
+    }
+
+    #[test]
+    fn missing_memory_types_fall_back() {
+        // Integrated GPUs often only expose one host visible type
+        let properties = memory_properties(&[
+            Flags::DEVICE_LOCAL,
+            Flags::DEVICE_LOCAL | Flags::HOST_VISIBLE | Flags::HOST_COHERENT,
+        ]);
+        let find = |property| {
+            find_memorytype_index_for_property(&requirements(!0), &properties, property)
+        };
+        assert_eq!(find(Property::Upload), Some(1));
+        assert_eq!(find(Property::Readback), Some(1));
+        assert_eq!(find(Property::HostVisible), Some(1));
+        assert_eq!(find(Property::Transient), Some(0));
+    }
+
+    #[test]
+    fn fallbacks_respect_the_allowed_memory_types() {
+        let properties = memory_properties(&[
+            Flags::DEVICE_LOCAL | Flags::HOST_VISIBLE | Flags::HOST_COHERENT,
+            Flags::HOST_VISIBLE | Flags::HOST_COHERENT,
+            Flags::DEVICE_LOCAL,
+        ]);
+        let find = |memory_type_bits, property| {
+            find_memorytype_index_for_property(
+                &requirements(memory_type_bits),
+                &properties,
+                property,
+            )
+        };
+        assert_eq!(find(0b111, Property::Upload), Some(0));
+        assert_eq!(find(0b110, Property::Upload), Some(1));
+        // Device local memory falls back to any allowed type
+        assert_eq!(find(0b010, Property::DeviceLocal), Some(1));
+        assert_eq!(find(0b100, Property::HostVisible), None);
+    }
+
+    #[test]
+    fn every_chain_ends_in_its_minimal_requirement() {
+        let last = |property| *property_fallback_chain(property).last().unwrap();
+        assert_eq!(last(Property::DeviceLocal), Flags::empty());
+        assert_eq!(last(Property::Transient), Flags::empty());
+        assert_eq!(last(Property::HostVisible), Flags::HOST_VISIBLE);
+        assert_eq!(last(Property::Upload), Flags::HOST_VISIBLE);
+        assert_eq!(last(Property::Readback), Flags::HOST_VISIBLE);
+    }
+}
@@ -1,10 +1,18 @@
+use super::image::resolve_image_resource;
+use super::layout::{SubresourceState, Transition};
+use super::renderpass::{attachment_states, FramebufferData, RenderpassData};
 use super::Context;
 use ash::{version::DeviceV1_0, vk};
 
 use std::ptr;
 use tephra::{
-    commandbuffer::{Command, CommandList, SubmitApi},
-    descriptor::Pool,
+    commandbuffer::{
+        Command, CommandList, DescriptorSet, RenderCommand, RenderpassCommand, ShaderResource,
+        QueueType, SubmitApi, SubmitError,
+    },
+    descriptor::{DescriptorType, Pool},
+    pipeline::{DynamicState, GraphicsPipeline},
+    Rect, Viewport,
 };
 
 
@@ -27,10 +35,441 @@ enum Sync {
     Semaphore,
 }
 
+/// Transitions every image that `shader_arguments` reference into the state the
+/// descriptor accesses it with in `stage`
+unsafe fn transition_shader_images(
+    ctx: &Context,
+    command_buffer: vk::CommandBuffer,
+    shader_arguments: &DescriptorSet,
+    stage: vk::PipelineStageFlags,
+) {
+    let mut transition = Transition::default();
+    for (_, descriptor) in shader_arguments.iter() {
+        for (view, resource) in descriptor.views.iter().zip(descriptor.resources.iter()) {
+            let resource = match resource {
+                ShaderResource::Image(resource)
+                | ShaderResource::CombinedImageSampler(resource, _) => *resource,
+                _ => continue,
+            };
+            let (_, image, range) = resolve_image_resource(ctx, resource);
+            let data = ctx.images.get(image);
+            let state = match view.ty {
+                DescriptorType::StorageImage => SubresourceState::storage(view.access, stage),
+                // The renderpass transitions its attachments itself
+                DescriptorType::InputAttachment => continue,
+                _ => SubresourceState::sampled(data.desc.format, stage),
+            };
+            transition.merge(data.transition(&range, state));
+        }
+    }
+    transition.record(ctx, command_buffer);
+}
+
+/// Transitions the attachments of `framebuffer` into the initial states of the
+/// renderpass attachments
+unsafe fn transition_attachments(
+    ctx: &Context,
+    command_buffer: vk::CommandBuffer,
+    renderpass: &RenderpassData,
+    framebuffer: &FramebufferData,
+) {
+    let mut transition = Transition::default();
+    let attachments = renderpass.attachments.iter().zip(&framebuffer.attachments);
+    for (attachment, (image, range)) in attachments {
+        let (initial, _) = attachment_states(attachment);
+        let data = ctx.images.get(*image);
+        transition.merge(data.transition(range, initial));
+    }
+    transition.record(ctx, command_buffer);
+}
+
+/// Records the final layouts that the renderpass left the attachments in
+fn finish_attachments(ctx: &Context, renderpass: &RenderpassData, framebuffer: &FramebufferData) {
+    let attachments = renderpass.attachments.iter().zip(&framebuffer.attachments);
+    for (attachment, (image, range)) in attachments {
+        let (_, last) = attachment_states(attachment);
+        ctx.images.get(*image).state.lock().set(range, last);
+    }
+}
+
+fn vk_viewport(viewport: Viewport) -> vk::Viewport {
+    vk::Viewport {
+        x: viewport.origin.0,
+        y: viewport.origin.1,
+        width: viewport.dimensions.0,
+        height: viewport.dimensions.1,
+        min_depth: viewport.depth_range.0,
+        max_depth: viewport.depth_range.1,
+    }
+}
+
+fn vk_rect(rect: Rect) -> vk::Rect2D {
+    vk::Rect2D {
+        offset: vk::Offset2D {
+            x: rect.offset.0,
+            y: rect.offset.1,
+        },
+        extent: vk::Extent2D {
+            width: rect.extent.0,
+            height: rect.extent.1,
+        },
+    }
+}
+
+/// Begins the renderpass, replays the bound state and draws of `cmd` and ends it.
+/// `cmd` has to be checked with [`validate_renderpass`] first.
+unsafe fn record_renderpass(
+    ctx: &Context,
+    pool: &mut Pool,
+    command_buffer: vk::CommandBuffer,
+    cmd: &RenderpassCommand,
+) {
+    let device = &ctx.device;
+    // Barriers are not allowed inside of the renderpass
+    for command in &cmd.commands {
+        if let RenderCommand::BindDescriptors(shader_arguments) = command {
+            transition_shader_images(
+                ctx,
+                command_buffer,
+                shader_arguments,
+                vk::PipelineStageFlags::VERTEX_SHADER | vk::PipelineStageFlags::FRAGMENT_SHADER,
+            );
+        }
+    }
+    let framebuffer = ctx.framebuffers.get(cmd.framebuffer);
+    let renderpass = ctx.renderpasses.get(cmd.renderpass);
+    transition_attachments(ctx, command_buffer, &renderpass, &framebuffer);
+    let extent = framebuffer.extent;
+    let viewport = Viewport {
+        origin: (0.0, 0.0),
+        dimensions: (extent.width as f32, extent.height as f32),
+        depth_range: (0.0, 1.0),
+    };
+    let render_area = vk::Rect2D {
+        offset: vk::Offset2D { x: 0, y: 0 },
+        extent,
+    };
+    let clear_values = renderpass.clear_values(&cmd.clear_values);
+    let render_pass_begin_info = vk::RenderPassBeginInfo::builder()
+        .render_pass(renderpass.render_pass)
+        .framebuffer(framebuffer.framebuffer)
+        .render_area(render_area)
+        .clear_values(&clear_values);
+    device.cmd_begin_render_pass(
+        command_buffer,
+        &render_pass_begin_info,
+        vk::SubpassContents::INLINE,
+    );
+
+    device.cmd_set_viewport(command_buffer, 0, &[vk_viewport(viewport)]);
+    device.cmd_set_scissor(command_buffer, 0, &[render_area]);
+
+    let mut layout = None;
+    for command in &cmd.commands {
+        match command {
+            RenderCommand::BindPipeline(handle) => {
+                let pipeline = ctx.graphic_pipelines.get(*handle);
+                device.cmd_bind_pipeline(
+                    command_buffer,
+                    vk::PipelineBindPoint::GRAPHICS,
+                    pipeline.pipeline,
+                );
+                layout = Some(pipeline.layout);
+            }
+            RenderCommand::BindDescriptors(shader_arguments) => {
+                let layout = layout.expect("Checked by validate_renderpass");
+                for (set, shader_arguments) in shader_arguments.iter() {
+                    let descriptor_handle = pool.allocate(shader_arguments);
+                    let descriptor = ctx.descriptors.get(descriptor_handle);
+                    device.cmd_bind_descriptor_sets(
+                        command_buffer,
+                        vk::PipelineBindPoint::GRAPHICS,
+                        layout,
+                        *set,
+                        &[descriptor.descriptor_set],
+                        &shader_arguments.dynamic_offsets(),
+                    );
+                }
+            }
+            RenderCommand::BindVertexBuffer(handle) => {
+                let vertex_buffer = ctx.buffers.get(*handle);
+                device.cmd_bind_vertex_buffers(command_buffer, 0, &[vertex_buffer.buffer], &[0]);
+            }
+            RenderCommand::BindIndexBuffer(handle) => {
+                let index_buffer = ctx.buffers.get(*handle);
+                device.cmd_bind_index_buffer(
+                    command_buffer,
+                    index_buffer.buffer,
+                    0,
+                    vk::IndexType::UINT32,
+                );
+            }
+            RenderCommand::Draw {
+                vertices,
+                instances,
+            } => {
+                device.cmd_draw(
+                    command_buffer,
+                    vertices.end - vertices.start,
+                    instances.end - instances.start,
+                    vertices.start,
+                    instances.start,
+                );
+            }
+            RenderCommand::DrawIndexed { indices, instances } => {
+                device.cmd_draw_indexed(
+                    command_buffer,
+                    indices.end - indices.start,
+                    instances.end - instances.start,
+                    indices.start,
+                    0,
+                    instances.start,
+                );
+            }
+            RenderCommand::SetViewport(viewport) => {
+                device.cmd_set_viewport(command_buffer, 0, &[vk_viewport(*viewport)]);
+            }
+            RenderCommand::SetScissor(scissor) => {
+                device.cmd_set_scissor(command_buffer, 0, &[vk_rect(*scissor)]);
+            }
+            RenderCommand::SetLineWidth(line_width) => {
+                device.cmd_set_line_width(command_buffer, *line_width);
+            }
+            RenderCommand::SetDepthBias(depth_bias) => {
+                device.cmd_set_depth_bias(
+                    command_buffer,
+                    depth_bias.constant_factor,
+                    depth_bias.clamp,
+                    depth_bias.slope_factor,
+                );
+            }
+            RenderCommand::SetBlendConstants(constants) => {
+                device.cmd_set_blend_constants(command_buffer, constants);
+            }
+            RenderCommand::SetDepthBounds { min, max } => {
+                device.cmd_set_depth_bounds(command_buffer, *min, *max);
+            }
+            RenderCommand::SetStencilCompareMask(mask) => {
+                device.cmd_set_stencil_compare_mask(
+                    command_buffer,
+                    vk::StencilFaceFlags::STENCIL_FRONT_AND_BACK,
+                    *mask,
+                );
+            }
+            RenderCommand::SetStencilWriteMask(mask) => {
+                device.cmd_set_stencil_write_mask(
+                    command_buffer,
+                    vk::StencilFaceFlags::STENCIL_FRONT_AND_BACK,
+                    *mask,
+                );
+            }
+            RenderCommand::SetStencilReference(reference) => {
+                device.cmd_set_stencil_reference(
+                    command_buffer,
+                    vk::StencilFaceFlags::STENCIL_FRONT_AND_BACK,
+                    *reference,
+                );
+            }
+            RenderCommand::NextSubpass => {
+                device.cmd_next_subpass(command_buffer, vk::SubpassContents::INLINE);
+                // Pipelines belong to a single subpass
+                layout = None;
+            }
+        }
+    }
+    device.cmd_end_render_pass(command_buffer);
+    finish_attachments(ctx, &renderpass, &framebuffer);
+}
+
+/// The dynamic state that a render command sets, if any. The viewport and scissor
+/// are always set at the start of a renderpass.
+fn dynamic_state(command: &RenderCommand) -> Option<DynamicState> {
+    let state = match command {
+        RenderCommand::SetLineWidth(_) => DynamicState::LineWidth,
+        RenderCommand::SetDepthBias(_) => DynamicState::DepthBias,
+        RenderCommand::SetBlendConstants(_) => DynamicState::BlendConstants,
+        RenderCommand::SetDepthBounds { .. } => DynamicState::DepthBounds,
+        RenderCommand::SetStencilCompareMask(_) => DynamicState::StencilCompareMask,
+        RenderCommand::SetStencilWriteMask(_) => DynamicState::StencilWriteMask,
+        RenderCommand::SetStencilReference(_) => DynamicState::StencilReference,
+        _ => return None,
+    };
+    Some(state)
+}
+
+/// The parts of a graphics pipeline that [`validate_render_commands`] checks
+struct BoundPipeline {
+    /// Whether the pipeline can draw in the renderpass
+    compatible: bool,
+    subpass: u32,
+    dynamic_states: Vec<DynamicState>,
+}
+
+/// Checks the clear values and the commands of `cmd` against its renderpass
+fn validate_renderpass(ctx: &Context, cmd: &RenderpassCommand) -> Result<(), SubmitError> {
+    let renderpass = ctx.renderpasses.get(cmd.renderpass);
+    renderpass.validate_clear_values(&cmd.clear_values)?;
+    let wide_lines = ctx.features.wide_lines == vk::TRUE;
+    validate_render_commands(
+        &cmd.commands,
+        renderpass.subpasses.len(),
+        wide_lines,
+        |handle| {
+            let data = ctx.graphic_pipelines.get(handle);
+            BoundPipeline {
+                compatible: data.compatibility == renderpass.compatibility,
+                subpass: data.subpass,
+                dynamic_states: data.dynamic_states.clone(),
+            }
+        },
+    )
+}
+
+/// Replays the bound state of `commands` and checks that every draw has what it
+/// needs. Dynamic states have to be set inside of the renderpass that draws with them.
+fn validate_render_commands<F>(
+    commands: &[RenderCommand],
+    subpass_count: usize,
+    wide_lines: bool,
+    bound_pipeline: F,
+) -> Result<(), SubmitError>
+where
+    F: Fn(GraphicsPipeline) -> BoundPipeline,
+{
+    let mut pipeline: Option<BoundPipeline> = None;
+    let mut index_bound = false;
+    let mut dynamic_states = Vec::new();
+    let mut subpass = 0;
+    for command in commands {
+        if let Some(state) = dynamic_state(command) {
+            if !dynamic_states.contains(&state) {
+                dynamic_states.push(state);
+            }
+        }
+        match command {
+            RenderCommand::BindPipeline(handle) => {
+                let data = bound_pipeline(*handle);
+                if !data.compatible {
+                    return Err(SubmitError::IncompatiblePipeline);
+                }
+                if data.subpass as usize != subpass {
+                    return Err(SubmitError::WrongSubpass {
+                        pipeline: data.subpass,
+                        subpass,
+                    });
+                }
+                // Binding a pipeline with a static state discards its dynamic value
+                dynamic_states.retain(|state| data.dynamic_states.contains(state));
+                pipeline = Some(data);
+            }
+            RenderCommand::BindDescriptors(_) if pipeline.is_none() => {
+                return Err(SubmitError::MissingPipeline);
+            }
+            RenderCommand::BindIndexBuffer(_) => index_bound = true,
+            RenderCommand::Draw { .. } | RenderCommand::DrawIndexed { .. } => {
+                let data = pipeline.as_ref().ok_or(SubmitError::MissingPipeline)?;
+                if let RenderCommand::DrawIndexed { .. } = command {
+                    if !index_bound {
+                        return Err(SubmitError::MissingIndexBuffer);
+                    }
+                }
+                let missing = data
+                    .dynamic_states
+                    .iter()
+                    .find(|state| !dynamic_states.contains(state));
+                if let Some(&state) = missing {
+                    return Err(SubmitError::MissingDynamicState(state));
+                }
+            }
+            RenderCommand::SetLineWidth(line_width) if *line_width != 1.0 && !wide_lines => {
+                return Err(SubmitError::UnsupportedLineWidth(*line_width));
+            }
+            RenderCommand::NextSubpass => {
+                subpass += 1;
+                if subpass >= subpass_count {
+                    return Err(SubmitError::TooManySubpasses(subpass_count));
+                }
+                // Pipelines belong to a single subpass
+                pipeline = None;
+            }
+            _ => (),
+        }
+    }
+    if subpass + 1 != subpass_count {
+        return Err(SubmitError::MissingSubpasses {
+            recorded: subpass + 1,
+            count: subpass_count,
+        });
+    }
+    Ok(())
+}
+
+/// Checks every command of `commands` that the device could reject, so that a
+/// command list is either submitted completely or not at all
+fn validate_commands(ctx: &Context, commands: &CommandList) -> Result<(), SubmitError> {
+    for submit in &commands.submits {
+        for command in &submit.commands {
+            match command {
+                Command::CopyImage(copy_image) => {
+                    super::image::validate_copy(
+                        ctx,
+                        copy_image.src,
+                        copy_image.dst,
+                        &copy_image.regions,
+                    )
+                    .map_err(SubmitError::Image)?;
+                }
+                Command::BlitImage(blit_image) => {
+                    super::image::validate_blit(
+                        ctx,
+                        blit_image.src,
+                        blit_image.dst,
+                        &blit_image.regions,
+                        blit_image.filter,
+                    )
+                    .map_err(SubmitError::Image)?;
+                }
+                Command::GenerateMips(image) => {
+                    let image = ctx.images.get(*image);
+                    super::mips::validate_mip_generation(ctx, &image.desc)
+                        .map_err(SubmitError::Image)?;
+                }
+                Command::Renderpass(renderpass) => validate_renderpass(ctx, renderpass)?,
+                Command::FillBuffer(_)
+                    if matches!(submit.queue_ty, QueueType::Transfer) && !ctx.maintenance1 =>
+                {
+                    return Err(SubmitError::UnsupportedTransferFill);
+                }
+                _ => (),
+            }
+        }
+    }
+    Ok(())
+}
+
 impl SubmitApi for Context {
-    unsafe fn submit_commands(&self, pool: &mut Pool, commands: &CommandList) {
+    fn begin_command_list(&self) -> u64 {
+        self.submissions.lock().begin_command_list()
+    }
+
+    fn end_command_list(&self, epoch: u64) {
+        let unused = {
+            let mut submissions = self.submissions.lock();
+            submissions.open_command_lists.remove(&epoch);
+            submissions.take_unused()
+        };
+        self.destroy_buffers(unused);
+    }
+
+    unsafe fn submit_commands(
+        &self,
+        pool: &mut Pool,
+        commands: &CommandList,
+    ) -> Result<(), SubmitError> {
+        validate_commands(self, commands)?;
         let mut fences = Vec::new();
         let mut buffers = Vec::new();
+        let mut downsamples = Vec::new();
         let device = &self.device;
         for submit in &commands.submits {
             let command_buffer = self.command_pool.get_command_buffer(self);
@@ -46,97 +485,57 @@ impl SubmitApi for Context {
             for command in &submit.commands {
                 match command {
                     Command::CopyImage(copy_image) => {
-                        let src = self.images.get(copy_image.src);
-                        let dst = self.images.get(copy_image.dst);
-                        let aspect_mask = super::image::get_aspect_mask(&dst.desc);
-                        let subresource_range = vk::ImageSubresourceRange {
-                            aspect_mask,
-                            base_mip_level: 0,
-                            level_count: 1,
-                            base_array_layer: 0,
-                            layer_count: 1,
-                        };
-                        let sub_resource_layer = vk::ImageSubresourceLayers {
-                            aspect_mask,
-                            mip_level: 0,
-                            base_array_layer: 0,
-                            layer_count: 1,
-                        };
-                        let image_copy = vk::ImageCopy {
-                            src_subresource: sub_resource_layer,
-                            dst_subresource: sub_resource_layer,
-                            src_offset: vk::Offset3D::default(),
-                            dst_offset: vk::Offset3D::default(),
-                            extent: vk::Extent3D {
-                                width: dst.desc.resolution.width,
-                                height: dst.desc.resolution.height,
-                                depth: 1,
-                            },
-                        };
-                        let src_barrier = vk::ImageMemoryBarrier {
-                            src_access_mask: vk::AccessFlags::MEMORY_READ,
-                            dst_access_mask: vk::AccessFlags::TRANSFER_READ,
-                            old_layout: dst.layout,
-                            new_layout: vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
-                            image: src.image,
-                            subresource_range,
-                            ..Default::default()
-                        };
-                        let dst_barrier = vk::ImageMemoryBarrier {
-                            src_access_mask: vk::AccessFlags::empty(),
-                            dst_access_mask: vk::AccessFlags::TRANSFER_WRITE,
-                            old_layout: dst.layout,
-                            new_layout: vk::ImageLayout::TRANSFER_DST_OPTIMAL,
-                            image: dst.image,
-                            subresource_range,
-                            ..Default::default()
-                        };
-                        device.cmd_pipeline_barrier(
+                        super::image::record_copy(
+                            self,
                             *command_buffer,
-                            vk::PipelineStageFlags::TRANSFER,
-                            vk::PipelineStageFlags::TRANSFER,
-                            vk::DependencyFlags::empty(),
-                            &[],
-                            &[],
-                            &[src_barrier, dst_barrier],
+                            copy_image.src,
+                            copy_image.dst,
+                            &copy_image.regions,
                         );
-                        device.cmd_copy_image(
+                    }
+                    Command::BlitImage(blit_image) => {
+                        super::image::record_blit(
+                            self,
                             *command_buffer,
-                            src.image,
-                            vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
-                            dst.image,
-                            vk::ImageLayout::TRANSFER_DST_OPTIMAL,
-                            &[image_copy],
+                            blit_image.src,
+                            blit_image.dst,
+                            &blit_image.regions,
+                            blit_image.filter,
                         );
-                        let src_barrier = vk::ImageMemoryBarrier {
-                            src_access_mask: vk::AccessFlags::TRANSFER_READ,
-                            dst_access_mask: vk::AccessFlags::MEMORY_READ,
-                            new_layout: src.layout,
-                            old_layout: vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
-                            image: src.image,
-                            subresource_range,
-                            ..Default::default()
-                        };
-                        let dst_barrier = vk::ImageMemoryBarrier {
-                            src_access_mask: vk::AccessFlags::TRANSFER_WRITE,
-                            dst_access_mask: vk::AccessFlags::MEMORY_READ,
-                            new_layout: dst.layout,
-                            old_layout: vk::ImageLayout::TRANSFER_DST_OPTIMAL,
-                            image: dst.image,
-                            subresource_range,
-                            ..Default::default()
-                        };
-                        device.cmd_pipeline_barrier(
+                    }
+                    Command::ClearColorImage(clear) => {
+                        super::image::record_clear_color(
+                            self,
                             *command_buffer,
-                            vk::PipelineStageFlags::TRANSFER,
-                            vk::PipelineStageFlags::TRANSFER,
-                            vk::DependencyFlags::empty(),
-                            &[],
-                            &[],
-                            &[src_barrier, dst_barrier],
+                            clear.image,
+                            clear.color,
+                        );
+                    }
+                    Command::ClearDepthStencilImage(clear) => {
+                        super::image::record_clear_depth_stencil(
+                            self,
+                            *command_buffer,
+                            clear.image,
+                            clear.value,
+                        );
+                    }
+                    Command::FillBuffer(fill) => {
+                        super::buffer::record_fill(
+                            self,
+                            *command_buffer,
+                            fill.buffer,
+                            fill.offset,
+                            fill.size,
+                            fill.data,
                         );
                     }
                     Command::Dispatch(dispatch) => {
+                        transition_shader_images(
+                            self,
+                            *command_buffer,
+                            &dispatch.shader_arguments,
+                            vk::PipelineStageFlags::COMPUTE_SHADER,
+                        );
                         let pipeline = self.compute_pipelines.get(dispatch.pipeline);
                         device.cmd_bind_pipeline(
                             *command_buffer,
@@ -152,93 +551,21 @@ impl SubmitApi for Context {
                                 pipeline.layout,
                                 *set,
                                 &[descriptor.descriptor_set],
-                                &[],
+                                &shader_arguments.dynamic_offsets(),
                             );
                         }
                         device.cmd_dispatch(*command_buffer, dispatch.x, dispatch.y, dispatch.z);
                     }
-                    Command::Draw(draw) => {
-                        let clear_values = [
-                            vk::ClearValue {
-                                color: vk::ClearColorValue {
-                                    float32: [0.0, 0.0, 0.0, 0.0],
-                                },
-                            },
-                            vk::ClearValue {
-                                depth_stencil: vk::ClearDepthStencilValue {
-                                    depth: 1.0,
-                                    stencil: 0,
-                                },
-                            },
-                        ];
-
-                        let viewports = [vk::Viewport {
-                            x: 0.0,
-                            y: 0.0,
-                            width: self.surface_resolution.width as f32,
-                            height: self.surface_resolution.height as f32,
-                            min_depth: 0.0,
-                            max_depth: 1.0,
-                        }];
-                        let scissors = [vk::Rect2D {
-                            offset: vk::Offset2D { x: 0, y: 0 },
-                            extent: self.surface_resolution.clone(),
-                        }];
-                        let framebuffer = self.framebuffers.get(draw.framebuffer);
-                        let pipeline = self.graphic_pipelines.get(draw.graphics_pipeline);
-                        let vertex_buffer = self.buffers.get(draw.vertex);
-                        let index_buffer = self.buffers.get(draw.index.buffer);
-                        let renderpass = self.renderpasses.get(draw.renderpass);
-                        let render_pass_begin_info = vk::RenderPassBeginInfo::builder()
-                            .render_pass(renderpass.render_pass)
-                            .framebuffer(framebuffer.framebuffer)
-                            .render_area(vk::Rect2D {
-                                offset: vk::Offset2D { x: 0, y: 0 },
-                                extent: self.surface_resolution.clone(),
-                            })
-                            .clear_values(&clear_values);
-                        device.cmd_begin_render_pass(
-                            *command_buffer,
-                            &render_pass_begin_info,
-                            vk::SubpassContents::INLINE,
-                        );
-
-                        // TODO: Make configurable
-                        device.cmd_set_viewport(*command_buffer, 0, &viewports);
-                        device.cmd_set_scissor(*command_buffer, 0, &scissors);
-
-                        device.cmd_bind_pipeline(
-                            *command_buffer,
-                            vk::PipelineBindPoint::GRAPHICS,
-                            pipeline.pipeline,
-                        );
-                        for (set, shader_arguments) in draw.shader_arguments.iter() {
-                            let descriptor_handle = pool.allocate(shader_arguments);
-                            let descriptor = self.descriptors.get(descriptor_handle);
-                            device.cmd_bind_descriptor_sets(
-                                *command_buffer,
-                                vk::PipelineBindPoint::GRAPHICS,
-                                pipeline.layout,
-                                *set,
-                                &[descriptor.descriptor_set],
-                                &[],
-                            );
-                        }
-                        device.cmd_bind_vertex_buffers(
-                            *command_buffer,
-                            0,
-                            &[vertex_buffer.buffer],
-                            &[0],
-                        );
-                        device.cmd_bind_index_buffer(
+                    Command::GenerateMips(image) => {
+                        let image = self.images.get(*image);
+                        downsamples.extend(super::mips::record_generate_mips(
+                            self,
                             *command_buffer,
-                            index_buffer.buffer,
-                            0,
-                            vk::IndexType::UINT32,
-                        );
-                        let index_len = index_buffer.size / std::mem::size_of::<u32>() as u64;
-                        device.cmd_draw_indexed(*command_buffer, index_len as u32, 1, 0, 0, 1);
-                        device.cmd_end_render_pass(*command_buffer);
+                            &image,
+                        ));
+                    }
+                    Command::Renderpass(renderpass) => {
+                        record_renderpass(self, pool, *command_buffer, renderpass);
                     }
                 }
             }
@@ -247,6 +574,7 @@ impl SubmitApi for Context {
                 .expect("End commandbuffer");
             let fence_info = vk::FenceCreateInfo::default();
             let fence = device.create_fence(&fence_info, None).unwrap();
+            let submission = self.begin_submission();
             let queue = self.present_queue.inner.lock();
 
             // TODO: Properly implement submission with batching and semaphores
@@ -262,6 +590,8 @@ impl SubmitApi for Context {
             device
                 .wait_for_fences(&[fence], true, u64::max_value())
                 .unwrap();
+            drop(queue);
+            self.end_submission(submission);
             fences.push(fence);
             buffers.push(command_buffer);
         }
@@ -272,5 +602,155 @@ impl SubmitApi for Context {
         for cmd_buffer in buffers {
             cmd_buffer.release();
         }
+        for downsample in downsamples {
+            downsample.destroy(self);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use generational_arena::Index;
+
+    const STATIC: usize = 0;
+    const LINE_WIDTH: usize = 1;
+    const SECOND_SUBPASS: usize = 2;
+    const INCOMPATIBLE: usize = 3;
+
+    fn pipeline(slot: usize) -> GraphicsPipeline {
+        GraphicsPipeline(Index::from_raw_parts(slot, 0))
+    }
+
+    fn bound_pipeline(handle: GraphicsPipeline) -> BoundPipeline {
+        let slot = handle.0.into_raw_parts().0;
+        BoundPipeline {
+            compatible: slot != INCOMPATIBLE,
+            subpass: (slot == SECOND_SUBPASS) as u32,
+            dynamic_states: if slot == LINE_WIDTH {
+                vec![DynamicState::LineWidth]
+            } else {
+                Vec::new()
+            },
+        }
+    }
+
+    fn draw() -> RenderCommand {
+        RenderCommand::Draw {
+            vertices: 0..3,
+            instances: 0..1,
+        }
+    }
+
+    fn validate(commands: &[RenderCommand], subpass_count: usize) -> Result<(), SubmitError> {
+        validate_render_commands(commands, subpass_count, false, bound_pipeline)
+    }
+
+    #[test]
+    fn draws_need_a_pipeline_of_the_current_subpass() {
+        assert!(validate(&[RenderCommand::BindPipeline(pipeline(STATIC)), draw()], 1).is_ok());
+        match validate(&[draw()], 1) {
+            Err(SubmitError::MissingPipeline) => (),
+            result => panic!("Expected a missing pipeline, got {:?}", result),
+        }
+        match validate(&[RenderCommand::BindPipeline(pipeline(INCOMPATIBLE))], 1) {
+            Err(SubmitError::IncompatiblePipeline) => (),
+            result => panic!("Expected an incompatible pipeline, got {:?}", result),
+        }
+        match validate(&[RenderCommand::BindPipeline(pipeline(SECOND_SUBPASS))], 2) {
+            Err(SubmitError::WrongSubpass {
+                pipeline: 1,
+                subpass: 0,
+            }) => (),
+            result => panic!("Expected a wrong subpass, got {:?}", result),
+        }
+    }
+
+    #[test]
+    fn every_subpass_has_to_be_recorded() {
+        let commands = [
+            RenderCommand::BindPipeline(pipeline(STATIC)),
+            draw(),
+            RenderCommand::NextSubpass,
+            RenderCommand::BindPipeline(pipeline(SECOND_SUBPASS)),
+            draw(),
+        ];
+        assert!(validate(&commands, 2).is_ok());
+        match validate(&commands[..2], 2) {
+            Err(SubmitError::MissingSubpasses {
+                recorded: 1,
+                count: 2,
+            }) => (),
+            result => panic!("Expected missing subpasses, got {:?}", result),
+        }
+        match validate(&commands, 1) {
+            Err(SubmitError::TooManySubpasses(1)) => (),
+            result => panic!("Expected too many subpasses, got {:?}", result),
+        }
+        // The pipeline of the first subpass can't draw in the second one
+        let commands = [
+            RenderCommand::BindPipeline(pipeline(STATIC)),
+            RenderCommand::NextSubpass,
+            draw(),
+        ];
+        match validate(&commands, 2) {
+            Err(SubmitError::MissingPipeline) => (),
+            result => panic!("Expected a missing pipeline, got {:?}", result),
+        }
+    }
+
+    #[test]
+    fn dynamic_states_have_to_be_set_before_drawing() {
+        let commands = [
+            RenderCommand::BindPipeline(pipeline(LINE_WIDTH)),
+            RenderCommand::SetLineWidth(1.0),
+            draw(),
+        ];
+        assert!(validate(&commands, 1).is_ok());
+        match validate(&[RenderCommand::BindPipeline(pipeline(LINE_WIDTH)), draw()], 1) {
+            Err(SubmitError::MissingDynamicState(DynamicState::LineWidth)) => (),
+            result => panic!("Expected a missing dynamic state, got {:?}", result),
+        }
+        // A pipeline with a static line width discards the dynamic value
+        let commands = [
+            RenderCommand::SetLineWidth(1.0),
+            RenderCommand::BindPipeline(pipeline(STATIC)),
+            RenderCommand::BindPipeline(pipeline(LINE_WIDTH)),
+            draw(),
+        ];
+        match validate(&commands, 1) {
+            Err(SubmitError::MissingDynamicState(DynamicState::LineWidth)) => (),
+            result => panic!("Expected a missing dynamic state, got {:?}", result),
+        }
+    }
+
+    #[test]
+    fn wide_lines_need_the_feature() {
+        let commands = [
+            RenderCommand::BindPipeline(pipeline(LINE_WIDTH)),
+            RenderCommand::SetLineWidth(2.0),
+            draw(),
+        ];
+        match validate(&commands, 1) {
+            Err(SubmitError::UnsupportedLineWidth(width)) => assert_eq!(width, 2.0),
+            result => panic!("Expected an unsupported line width, got {:?}", result),
+        }
+        assert!(validate_render_commands(&commands, 1, true, bound_pipeline).is_ok());
+    }
+
+    #[test]
+    fn indexed_draws_need_an_index_buffer() {
+        let commands = [
+            RenderCommand::BindPipeline(pipeline(STATIC)),
+            RenderCommand::DrawIndexed {
+                indices: 0..3,
+                instances: 0..1,
+            },
+        ];
+        match validate(&commands, 1) {
+            Err(SubmitError::MissingIndexBuffer) => (),
+            result => panic!("Expected a missing index buffer, got {:?}", result),
+        }
     }
 }
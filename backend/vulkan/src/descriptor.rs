@@ -1,3 +1,5 @@
+use super::image::resolve_image_resource;
+use super::layout::sampled_layout;
 use super::Context;
 use ash::version::DeviceV1_0;
 use ash::vk;
@@ -7,6 +9,32 @@ use tephra::descriptor::{
     DescriptorType, NativePool, PoolApi,
 };
 
+pub(crate) fn descriptor_type(ty: DescriptorType) -> vk::DescriptorType {
+    match ty {
+        DescriptorType::Uniform => vk::DescriptorType::UNIFORM_BUFFER,
+        DescriptorType::UniformDynamic => vk::DescriptorType::UNIFORM_BUFFER_DYNAMIC,
+        DescriptorType::Storage => vk::DescriptorType::STORAGE_BUFFER,
+        DescriptorType::StorageImage => vk::DescriptorType::STORAGE_IMAGE,
+        DescriptorType::SampledImage => vk::DescriptorType::SAMPLED_IMAGE,
+        DescriptorType::Sampler => vk::DescriptorType::SAMPLER,
+        DescriptorType::CombinedImageSampler => vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+        DescriptorType::InputAttachment => vk::DescriptorType::INPUT_ATTACHMENT,
+    }
+}
+
+/// Input attachments can only be bound to fragment shaders
+pub(crate) fn descriptor_stages(ty: DescriptorType) -> vk::ShaderStageFlags {
+    match ty {
+        DescriptorType::InputAttachment => vk::ShaderStageFlags::FRAGMENT,
+        _ => vk::ShaderStageFlags::ALL,
+    }
+}
+
+enum DescriptorInfo {
+    Buffer(vk::DescriptorBufferInfo),
+    Image(vk::DescriptorImageInfo),
+}
+
 pub struct Pool {
     pub ctx: Context,
     pub pool: vk::DescriptorPool,
@@ -46,15 +74,11 @@ impl CreatePool for Context {
         let layout_bindings: Vec<_> = data
             .iter()
             .map(|desc| {
-                let ty = match desc.ty {
-                    DescriptorType::Uniform => vk::DescriptorType::UNIFORM_BUFFER,
-                    DescriptorType::Storage => vk::DescriptorType::STORAGE_BUFFER,
-                };
                 vk::DescriptorSetLayoutBinding {
                     binding: desc.binding,
-                    descriptor_type: ty,
+                    descriptor_type: descriptor_type(desc.ty),
                     descriptor_count: 1,
-                    stage_flags: vk::ShaderStageFlags::ALL,
+                    stage_flags: descriptor_stages(desc.ty),
                     p_immutable_samplers: std::ptr::null(),
                 }
             })
@@ -85,6 +109,13 @@ impl CreatePool for Context {
         if buffer_size.descriptor_count > 0 {
             pool_sizes.push(buffer_size);
         }
+        let dynamic_buffer_size = vk::DescriptorPoolSize {
+            ty: vk::DescriptorType::UNIFORM_BUFFER_DYNAMIC,
+            descriptor_count: sizes.dynamic_buffer * alloc_size,
+        };
+        if dynamic_buffer_size.descriptor_count > 0 {
+            pool_sizes.push(dynamic_buffer_size);
+        }
         let image_size = vk::DescriptorPoolSize {
             ty: vk::DescriptorType::STORAGE_IMAGE,
             descriptor_count: sizes.images * alloc_size,
@@ -92,6 +123,34 @@ impl CreatePool for Context {
         if image_size.descriptor_count > 0 {
             pool_sizes.push(image_size);
         }
+        let sampled_image_size = vk::DescriptorPoolSize {
+            ty: vk::DescriptorType::SAMPLED_IMAGE,
+            descriptor_count: sizes.sampled_images * alloc_size,
+        };
+        if sampled_image_size.descriptor_count > 0 {
+            pool_sizes.push(sampled_image_size);
+        }
+        let sampler_size = vk::DescriptorPoolSize {
+            ty: vk::DescriptorType::SAMPLER,
+            descriptor_count: sizes.samplers * alloc_size,
+        };
+        if sampler_size.descriptor_count > 0 {
+            pool_sizes.push(sampler_size);
+        }
+        let combined_size = vk::DescriptorPoolSize {
+            ty: vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+            descriptor_count: sizes.combined_image_samplers * alloc_size,
+        };
+        if combined_size.descriptor_count > 0 {
+            pool_sizes.push(combined_size);
+        }
+        let input_attachment_size = vk::DescriptorPoolSize {
+            ty: vk::DescriptorType::INPUT_ATTACHMENT,
+            descriptor_count: sizes.input_attachments * alloc_size,
+        };
+        if input_attachment_size.descriptor_count > 0 {
+            pool_sizes.push(input_attachment_size);
+        }
         let descriptor_pool_info = vk::DescriptorPoolCreateInfo {
             pool_size_count: pool_sizes.len() as u32,
             p_pool_sizes: pool_sizes.as_ptr(),
@@ -120,34 +179,73 @@ pub struct DescriptorSet {
 impl DescriptorApi for Context {
     fn write(&self, handle: DescriptorHandle, data: &Descriptor) {
         let descriptor = self.descriptors.get(handle);
-        let buffer_infos: Vec<vk::DescriptorBufferInfo> = data
+        let infos: Vec<DescriptorInfo> = data
             .resources
             .iter()
-            .map(|resource| {
+            .zip(data.views.iter())
+            .map(|(resource, view)| {
                 match *resource {
                     ShaderResource::Buffer(buffer) => {
                         let generic_buffer = buffer;
                         let vkbuffer = self.buffers.get(generic_buffer);
-                        vk::DescriptorBufferInfo {
+                        DescriptorInfo::Buffer(vk::DescriptorBufferInfo {
                             buffer: vkbuffer.buffer,
                             offset: 0,
                             range: vkbuffer.size,
-                        }
+                        })
+                    }
+                    ShaderResource::BufferRange(buffer, range) => {
+                        let vkbuffer = self.buffers.get(buffer);
+                        DescriptorInfo::Buffer(vk::DescriptorBufferInfo {
+                            buffer: vkbuffer.buffer,
+                            offset: 0,
+                            range,
+                        })
+                    }
+                    ShaderResource::Image(resource) => {
+                        let (image_view, image, _) = resolve_image_resource(self, resource);
+                        // Storage images are only accessible in the general layout
+                        let image_layout = match view.ty {
+                            DescriptorType::StorageImage => vk::ImageLayout::GENERAL,
+                            _ => sampled_layout(self.images.get(image).desc.format),
+                        };
+                        DescriptorInfo::Image(vk::DescriptorImageInfo {
+                            sampler: vk::Sampler::null(),
+                            image_view,
+                            image_layout,
+                        })
+                    }
+                    ShaderResource::Sampler(sampler) => {
+                        let sampler = self.samplers.get(sampler);
+                        DescriptorInfo::Image(vk::DescriptorImageInfo {
+                            sampler: sampler.sampler,
+                            image_view: vk::ImageView::null(),
+                            image_layout: vk::ImageLayout::UNDEFINED,
+                        })
+                    }
+                    ShaderResource::CombinedImageSampler(resource, sampler) => {
+                        let (image_view, image, _) = resolve_image_resource(self, resource);
+                        let sampler = self.samplers.get(sampler);
+                        DescriptorInfo::Image(vk::DescriptorImageInfo {
+                            sampler: sampler.sampler,
+                            image_view,
+                            image_layout: sampled_layout(self.images.get(image).desc.format),
+                        })
                     }
-                    _ => unimplemented!(),
                 }
             })
             .collect();
 
-        let writes: Vec<_> = buffer_infos
+        let writes: Vec<_> = infos
             .iter()
             .enumerate()
             .map(|(idx, info)| {
-                let descriptor_type = match data.views[idx].ty {
-                    DescriptorType::Uniform => vk::DescriptorType::UNIFORM_BUFFER,
-                    DescriptorType::Storage => vk::DescriptorType::STORAGE_BUFFER,
-                };
+                let descriptor_type = descriptor_type(data.views[idx].ty);
                 let dst_binding = data.views[idx].binding;
+                let (p_buffer_info, p_image_info) = match info {
+                    DescriptorInfo::Buffer(info) => (info as *const _, std::ptr::null()),
+                    DescriptorInfo::Image(info) => (std::ptr::null(), info as *const _),
+                };
                 vk::WriteDescriptorSet {
                     s_type: vk::StructureType::WRITE_DESCRIPTOR_SET,
                     p_next: std::ptr::null(),
@@ -156,8 +254,8 @@ impl DescriptorApi for Context {
                     dst_array_element: 0,
                     descriptor_count: 1,
                     descriptor_type,
-                    p_image_info: std::ptr::null(),
-                    p_buffer_info: info,
+                    p_image_info,
+                    p_buffer_info,
                     p_texel_buffer_view: std::ptr::null(),
                 }
             })
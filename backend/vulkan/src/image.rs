@@ -1,12 +1,21 @@
 use super::buffer;
+use super::layout::{ImageState, SubresourceState, Transition};
+use super::mips;
+use super::sampler;
 use super::CommandBuffer;
 use super::Context;
 use ash::version::{DeviceV1_0, InstanceV1_0};
 use ash::vk;
+use parking_lot::Mutex;
 use std::ptr;
 use tephra::{
-    buffer::Buffer,
-    image::{Format, ImageApi, ImageDesc, ImageHandle, ImageLayout},
+    buffer::{AllocationError, Buffer, BufferHandle},
+    image::{
+        BufferRegion, ClearColor, ClearDepthStencil, ComponentMapping, Format, ImageApi,
+        ImageAspect, ImageCopy, ImageDesc, ImageDimension, ImageError, ImageHandle, ImageRegion,
+        ImageResource, ImageUsage, ImageView, ImageViewDesc, ImageViewType, SampleCount, Swizzle,
+    },
+    sampler::Filter,
 };
 pub(crate) fn into_format(vk_format: vk::Format) -> Format {
     Format::from_raw(vk_format.as_raw())
@@ -14,31 +23,62 @@ pub(crate) fn into_format(vk_format: vk::Format) -> Format {
 pub(crate) fn from_format(format: Format) -> vk::Format {
     vk::Format::from_raw(format.as_raw())
 }
+pub(crate) fn sample_count(samples: SampleCount) -> vk::SampleCountFlags {
+    vk::SampleCountFlags::from_raw(samples.count())
+}
+pub struct ImageViewData {
+    pub image_view: vk::ImageView,
+    pub image: ImageHandle,
+    pub format: Format,
+    pub subresource_range: vk::ImageSubresourceRange,
+}
 pub struct ImageData {
     pub image: vk::Image,
     pub image_view: vk::ImageView,
-    pub layout: vk::ImageLayout,
+    /// Layout and last access of every subresource
+    pub state: Mutex<ImageState>,
     pub desc: ImageDesc,
 }
 
+impl ImageData {
+    /// Moves `range` into `state` and returns the barriers to record before the access
+    pub fn transition(
+        &self,
+        range: &vk::ImageSubresourceRange,
+        state: SubresourceState,
+    ) -> Transition {
+        self.state.lock().transition(self.image, range, state)
+    }
+}
+
 impl ImageApi for Context {
-    fn allocate_image(&self, desc: ImageDesc) -> ImageHandle {
-        let aspect_mask = match desc.layout {
-            ImageLayout::Color => vk::ImageAspectFlags::COLOR,
-            ImageLayout::Depth => vk::ImageAspectFlags::DEPTH,
-        };
+    fn allocate_image(&self, desc: ImageDesc) -> Result<ImageHandle, ImageError> {
         let format = from_format(desc.format);
-        let usage = match desc.layout {
-            ImageLayout::Color => vk::ImageUsageFlags::COLOR_ATTACHMENT,
-            ImageLayout::Depth => vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT,
-        };
-        let usage = usage | vk::ImageUsageFlags::TRANSFER_SRC | vk::ImageUsageFlags::TRANSFER_DST;
+        desc.validate()?;
+        validate_usage(self, &desc)?;
+        if desc.dimension == ImageDimension::Cube && desc.layers > 6 {
+            let features = unsafe { self.instance.get_physical_device_features(self.pdevice) };
+            if features.image_cube_array == 0 {
+                return Err(ImageError::UnsupportedCubeArray);
+            }
+        }
+        let mut usage = image_usage_flags(desc.usage);
+        if desc.mip_levels > 1 {
+            // Mip generation needs the usage of the path that the format takes
+            usage |= match mips::mip_strategy(self, format, desc.dimension) {
+                Some(mips::MipStrategy::Compute) => {
+                    vk::ImageUsageFlags::STORAGE | vk::ImageUsageFlags::SAMPLED
+                }
+                Some(mips::MipStrategy::Blit(_)) => {
+                    vk::ImageUsageFlags::TRANSFER_SRC | vk::ImageUsageFlags::TRANSFER_DST
+                }
+                None => vk::ImageUsageFlags::empty(),
+            };
+        }
+        let (image_type, view_type, depth) = image_types(&desc);
+        validate_samples(self, &desc, image_type, usage)?;
+        let subresource_range = full_subresource_range(&desc);
 
-        let access = vk::AccessFlags::empty();
-        let target_layout = match desc.layout {
-            ImageLayout::Color => vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
-            ImageLayout::Depth => vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
-        };
         let ctx = self;
         unsafe {
             let device_memory_properties = ctx
@@ -47,17 +87,17 @@ impl ImageApi for Context {
             let depth_image_create_info = vk::ImageCreateInfo {
                 s_type: vk::StructureType::IMAGE_CREATE_INFO,
                 p_next: ptr::null(),
-                flags: Default::default(),
-                image_type: vk::ImageType::TYPE_2D,
+                flags: image_create_flags(&desc),
+                image_type,
                 format,
                 extent: vk::Extent3D {
                     width: desc.resolution.width,
                     height: desc.resolution.height,
-                    depth: 1,
+                    depth,
                 },
-                mip_levels: 1,
-                array_layers: 1,
-                samples: vk::SampleCountFlags::TYPE_1,
+                mip_levels: desc.mip_levels,
+                array_layers: desc.layers,
+                samples: sample_count(desc.samples),
                 tiling: vk::ImageTiling::OPTIMAL,
                 usage,
                 sharing_mode: vk::SharingMode::EXCLUSIVE,
@@ -70,12 +110,19 @@ impl ImageApi for Context {
                 .create_image(&depth_image_create_info, None)
                 .unwrap();
             let depth_image_memory_req = ctx.device.get_image_memory_requirements(depth_image);
-            let depth_image_memory_index = buffer::find_memorytype_index(
+            let depth_image_memory_index = match buffer::find_memorytype_index_for_property(
                 &depth_image_memory_req,
                 &device_memory_properties,
-                vk::MemoryPropertyFlags::DEVICE_LOCAL,
-            )
-            .expect("Unable to find suitable memory index for depth image.");
+                desc.usage.property(),
+            ) {
+                Some(index) => index,
+                None => {
+                    ctx.device.destroy_image(depth_image, None);
+                    return Err(ImageError::AllocationError(
+                        AllocationError::UnsupportedMemorytype,
+                    ));
+                }
+            };
 
             let depth_image_allocate_info = vk::MemoryAllocateInfo {
                 s_type: vk::StructureType::MEMORY_ALLOCATE_INFO,
@@ -90,41 +137,11 @@ impl ImageApi for Context {
             ctx.device
                 .bind_image_memory(depth_image, depth_image_memory, 0)
                 .expect("Unable to bind depth image memory");
-            let command_buffer = CommandBuffer::record(ctx, "ImageAllocate", |command_buffer| {
-                let layout_transition_barrier = vk::ImageMemoryBarrier {
-                    s_type: vk::StructureType::IMAGE_MEMORY_BARRIER,
-                    p_next: ptr::null(),
-                    src_access_mask: Default::default(),
-                    dst_access_mask: access,
-                    old_layout: vk::ImageLayout::UNDEFINED,
-                    new_layout: target_layout,
-                    src_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
-                    dst_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
-                    image: depth_image,
-                    subresource_range: vk::ImageSubresourceRange {
-                        aspect_mask,
-                        base_mip_level: 0,
-                        level_count: 1,
-                        base_array_layer: 0,
-                        layer_count: 1,
-                    },
-                };
-                ctx.device.cmd_pipeline_barrier(
-                    command_buffer,
-                    vk::PipelineStageFlags::TOP_OF_PIPE,
-                    vk::PipelineStageFlags::BOTTOM_OF_PIPE,
-                    vk::DependencyFlags::empty(),
-                    &[],
-                    &[],
-                    &[layout_transition_barrier],
-                );
-            });
-            ctx.present_queue.submit(ctx, &[], &[], &[], command_buffer);
             let depth_image_view_info = vk::ImageViewCreateInfo {
                 s_type: vk::StructureType::IMAGE_VIEW_CREATE_INFO,
                 p_next: ptr::null(),
                 flags: Default::default(),
-                view_type: vk::ImageViewType::TYPE_2D,
+                view_type,
                 format: depth_image_create_info.format,
                 components: vk::ComponentMapping {
                     r: vk::ComponentSwizzle::IDENTITY,
@@ -132,220 +149,991 @@ impl ImageApi for Context {
                     b: vk::ComponentSwizzle::IDENTITY,
                     a: vk::ComponentSwizzle::IDENTITY,
                 },
-                subresource_range: vk::ImageSubresourceRange {
-                    aspect_mask,
-                    base_mip_level: 0,
-                    level_count: 1,
-                    base_array_layer: 0,
-                    layer_count: 1,
-                },
+                subresource_range,
                 image: depth_image,
             };
             let image_view = ctx
                 .device
                 .create_image_view(&depth_image_view_info, None)
                 .unwrap();
+            // New images start out undefined, the first use transitions them
             let data = ImageData {
-                layout: get_image_layout(&desc),
+                state: Mutex::new(ImageState::new(desc.mip_levels, desc.layers)),
                 image_view,
                 image: depth_image,
                 desc,
             };
-            self.images.insert(data)
+            Ok(self.images.insert(data))
         }
     }
-    fn from_buffer(&self, _buffer: Buffer<u8>) -> ImageHandle {
-        unimplemented!()
+    fn from_buffer(
+        &self,
+        desc: ImageDesc,
+        buffer: Buffer<u8>,
+    ) -> Result<ImageHandle, ImageError> {
+        let region = BufferRegion {
+            offset: 0,
+            mip_level: 0,
+            base_layer: 0,
+            layer_count: desc.layers,
+        };
+        self.from_buffer_regions(desc, buffer, &[region])
     }
-    fn desc(&self, handle: ImageHandle) -> ImageDesc {
-        let data = self.images.get(handle);
-        data.desc.clone()
+    fn from_buffer_regions(
+        &self,
+        desc: ImageDesc,
+        buffer: Buffer<u8>,
+        regions: &[BufferRegion],
+    ) -> Result<ImageHandle, ImageError> {
+        let buffer_size = self.buffers.get(buffer.buffer).size;
+        validate_buffer_regions(&desc, buffer_size, regions)?;
+        let handle = self.allocate_image(desc)?;
+        let image_data = self.images.get(handle);
+        let buffer_data = self.buffers.get(buffer.buffer);
+        let desc = &image_data.desc;
+        let aspect_mask = buffer_copy_aspect(desc.format);
+        let subresource_range = full_subresource_range(desc);
+        let (_, _, depth) = image_types(desc);
+        let regions: Vec<_> = regions
+            .iter()
+            .map(|region| {
+                let level = region.mip_level;
+                vk::BufferImageCopy {
+                    buffer_offset: region.offset,
+                    // Zero means tightly packed, which also covers block compressed rows
+                    buffer_row_length: 0,
+                    buffer_image_height: 0,
+                    image_subresource: vk::ImageSubresourceLayers {
+                        aspect_mask,
+                        mip_level: level,
+                        base_array_layer: region.base_layer,
+                        layer_count: region.layer_count,
+                    },
+                    image_offset: vk::Offset3D::default(),
+                    image_extent: vk::Extent3D {
+                        width: (desc.resolution.width >> level).max(1),
+                        height: (desc.resolution.height >> level).max(1),
+                        depth: (depth >> level).max(1),
+                    },
+                }
+            })
+            .collect();
+        let command_buffer = CommandBuffer::record(self, "ImageUpload", |command_buffer| unsafe {
+            image_data
+                .transition(&subresource_range, SubresourceState::transfer_dst())
+                .record(self, command_buffer);
+            self.device.cmd_copy_buffer_to_image(
+                command_buffer,
+                buffer_data.buffer,
+                image_data.image,
+                vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                &regions,
+            );
+        });
+        self.present_queue
+            .submit(self, &[], &[], &[], command_buffer);
+        Ok(handle)
     }
-    fn copy_image(&self, src: ImageHandle, dst: ImageHandle) {
-        let src_data = self.images.get(src);
-        let dst_data = self.images.get(dst);
-        let _self_layout = get_image_layout(&src_data.desc);
-        let _target_layout = get_image_layout(&dst_data.desc);
-        let aspect_mask = get_aspect_mask(&dst_data.desc);
-        let sub_resource_layer = vk::ImageSubresourceLayers {
-            aspect_mask,
-            mip_level: 0,
+    fn format_usage(&self, format: Format) -> ImageUsage {
+        let features = unsafe {
+            self.instance
+                .get_physical_device_format_properties(self.pdevice, from_format(format))
+                .optimal_tiling_features
+        };
+        if features.is_empty() {
+            return ImageUsage::empty();
+        }
+        // Transfers are supported by every format that can be used at all
+        let mut usage = ImageUsage::TRANSFER_SRC | ImageUsage::TRANSFER_DST;
+        for &(image_usage, feature) in USAGE_FEATURES.iter() {
+            if features.contains(feature) {
+                usage |= image_usage;
+            }
+        }
+        // Input attachments need to be either a color or a depth attachment
+        let attachment = ImageUsage::COLOR_ATTACHMENT | ImageUsage::DEPTH_STENCIL_ATTACHMENT;
+        if usage.intersects(attachment) {
+            usage |= ImageUsage::INPUT_ATTACHMENT | ImageUsage::TRANSIENT_ATTACHMENT;
+        }
+        usage
+    }
+    fn generate_mips(&self, image: ImageHandle) -> Result<(), ImageError> {
+        let image_data = self.images.get(image);
+        mips::validate_mip_generation(self, &image_data.desc)?;
+        let mut downsample = None;
+        let command_buffer = CommandBuffer::record(self, "GenerateMips", |command_buffer| unsafe {
+            downsample = mips::record_generate_mips(self, command_buffer, &image_data);
+        });
+        self.present_queue
+            .submit(self, &[], &[], &[], command_buffer);
+        if let Some(downsample) = downsample {
+            unsafe {
+                downsample.destroy(self);
+            }
+        }
+        Ok(())
+    }
+    fn copy_image_to_buffer(&self, image: ImageHandle, buffer: BufferHandle) {
+        let image_data = self.images.get(image);
+        let buffer_data = self.buffers.get(buffer);
+        let desc = &image_data.desc;
+        let aspect_mask = buffer_copy_aspect(desc.format);
+        let subresource_range = vk::ImageSubresourceRange {
+            aspect_mask: get_aspect_mask(desc),
+            base_mip_level: 0,
+            level_count: 1,
             base_array_layer: 0,
             layer_count: 1,
         };
-        let image_copy = vk::ImageCopy {
-            src_subresource: sub_resource_layer,
-            dst_subresource: sub_resource_layer,
-            src_offset: vk::Offset3D::default(),
-            dst_offset: vk::Offset3D::default(),
-            extent: vk::Extent3D {
-                width: dst_data.desc.resolution.width,
-                height: dst_data.desc.resolution.height,
+        let region = vk::BufferImageCopy {
+            buffer_offset: 0,
+            buffer_row_length: 0,
+            buffer_image_height: 0,
+            image_subresource: vk::ImageSubresourceLayers {
+                aspect_mask,
+                mip_level: 0,
+                base_array_layer: 0,
+                layer_count: 1,
+            },
+            image_offset: vk::Offset3D::default(),
+            image_extent: vk::Extent3D {
+                width: desc.resolution.width,
+                height: desc.resolution.height,
                 depth: 1,
             },
         };
-        let command_buffer = CommandBuffer::record(self, "DST", |command_buffer| {
-            let layout_transition_barrier = vk::ImageMemoryBarrier {
-                s_type: vk::StructureType::IMAGE_MEMORY_BARRIER,
-                p_next: ptr::null(),
-                src_access_mask: vk::AccessFlags::empty(),
-                dst_access_mask: vk::AccessFlags::empty(),
-                old_layout: vk::ImageLayout::PRESENT_SRC_KHR,
-                new_layout: vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+        let command_buffer = CommandBuffer::record(self, "ImageReadback", |command_buffer| unsafe {
+            image_data
+                .transition(&subresource_range, SubresourceState::transfer_src())
+                .record(self, command_buffer);
+            self.device.cmd_copy_image_to_buffer(
+                command_buffer,
+                image_data.image,
+                vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                buffer_data.buffer,
+                &[region],
+            );
+            // Makes the copy visible to the host before the buffer is mapped
+            let buffer_barrier = vk::BufferMemoryBarrier {
+                src_access_mask: vk::AccessFlags::TRANSFER_WRITE,
+                dst_access_mask: vk::AccessFlags::HOST_READ,
                 src_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
                 dst_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
-                image: dst_data.image,
-                subresource_range: vk::ImageSubresourceRange {
-                    aspect_mask,
-                    base_mip_level: 0,
-                    level_count: 1,
-                    base_array_layer: 0,
-                    layer_count: 1,
-                },
+                buffer: buffer_data.buffer,
+                offset: 0,
+                size: vk::WHOLE_SIZE,
+                ..Default::default()
             };
-            unsafe {
-                self.device.cmd_pipeline_barrier(
-                    command_buffer,
-                    vk::PipelineStageFlags::TOP_OF_PIPE,
-                    vk::PipelineStageFlags::BOTTOM_OF_PIPE,
-                    vk::DependencyFlags::empty(),
-                    &[],
-                    &[],
-                    &[layout_transition_barrier],
-                );
-            }
+            self.device.cmd_pipeline_barrier(
+                command_buffer,
+                vk::PipelineStageFlags::TRANSFER,
+                vk::PipelineStageFlags::HOST,
+                vk::DependencyFlags::empty(),
+                &[],
+                &[buffer_barrier],
+                &[],
+            );
         });
         self.present_queue
             .submit(self, &[], &[], &[], command_buffer);
-        let command_buffer = CommandBuffer::record(self, "ToSrcOptimal", |command_buffer| {
-            let layout_transition_barrier = vk::ImageMemoryBarrier {
-                s_type: vk::StructureType::IMAGE_MEMORY_BARRIER,
-                p_next: ptr::null(),
-                src_access_mask: vk::AccessFlags::empty(),
-                dst_access_mask: vk::AccessFlags::empty(),
-                old_layout: vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
-                new_layout: vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
-                src_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
-                dst_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
-                image: src_data.image,
-                subresource_range: vk::ImageSubresourceRange {
-                    aspect_mask,
-                    base_mip_level: 0,
-                    level_count: 1,
-                    base_array_layer: 0,
-                    layer_count: 1,
-                },
-            };
-            unsafe {
-                self.device.cmd_pipeline_barrier(
-                    command_buffer,
-                    vk::PipelineStageFlags::TOP_OF_PIPE,
-                    vk::PipelineStageFlags::BOTTOM_OF_PIPE,
-                    vk::DependencyFlags::empty(),
-                    &[],
-                    &[],
-                    &[layout_transition_barrier],
-                );
+    }
+    fn desc(&self, handle: ImageHandle) -> ImageDesc {
+        let data = self.images.get(handle);
+        data.desc.clone()
+    }
+    fn create_image_view(&self, desc: &ImageViewDesc) -> Result<ImageView, ImageError> {
+        let image = self.images.get(desc.image);
+        let image_desc = &image.desc;
+        let mip_levels = desc
+            .mip_levels
+            .unwrap_or_else(|| image_desc.mip_levels.saturating_sub(desc.base_mip_level));
+        let layers = desc
+            .layers
+            .unwrap_or_else(|| image_desc.layers.saturating_sub(desc.base_layer));
+        let mips = desc.base_mip_level..desc.base_mip_level + mip_levels;
+        let layer_range = desc.base_layer..desc.base_layer + layers;
+        if mip_levels == 0
+            || layers == 0
+            || mips.end > image_desc.mip_levels
+            || layer_range.end > image_desc.layers
+        {
+            return Err(ImageError::InvalidViewRange {
+                mips,
+                layers: layer_range,
+                mip_levels: image_desc.mip_levels,
+                image_layers: image_desc.layers,
+            });
+        }
+        let format = desc.format.unwrap_or(image_desc.format);
+        if format != image_desc.format {
+            if !image_desc.mutable_format {
+                return Err(ImageError::ImmutableFormat);
+            }
+            if !image_desc.format.is_view_compatible(format) {
+                return Err(ImageError::IncompatibleViewFormat {
+                    image: image_desc.format,
+                    view: format,
+                });
             }
+        }
+        let all_aspects = image_desc.format.aspect_mask();
+        let aspect = desc.aspect.unwrap_or(all_aspects);
+        if aspect.is_empty() || !all_aspects.contains(aspect) {
+            return Err(ImageError::InvalidViewAspect {
+                format: image_desc.format,
+                aspect,
+            });
+        }
+        let view_type = desc
+            .view_type
+            .unwrap_or_else(|| default_view_type(image_desc, desc.base_layer, layers));
+        let subresource_range = vk::ImageSubresourceRange {
+            aspect_mask: vk::ImageAspectFlags::from_raw(aspect.bits()),
+            base_mip_level: desc.base_mip_level,
+            level_count: mip_levels,
+            base_array_layer: desc.base_layer,
+            layer_count: layers,
+        };
+        let view_info = vk::ImageViewCreateInfo {
+            view_type: image_view_type(view_type),
+            format: from_format(format),
+            components: component_mapping(desc.components),
+            subresource_range,
+            image: image.image,
+            ..Default::default()
+        };
+        let image_view = unsafe {
+            self.device
+                .create_image_view(&view_info, None)
+                .expect("Unable to create image view")
+        };
+        let data = ImageViewData {
+            image_view,
+            image: desc.image,
+            format,
+            subresource_range,
+        };
+        Ok(self.image_views.insert(data))
+    }
+    fn destroy_image_view(&self, view: ImageView) {
+        let data = self
+            .image_views
+            .remove(view)
+            .expect("Invalid image view handle");
+        unsafe {
+            self.device.destroy_image_view(data.image_view, None);
+        }
+    }
+    fn copy_image(&self, src: ImageHandle, dst: ImageHandle) -> Result<(), ImageError> {
+        let src_desc = self.desc(src);
+        let dst_desc = self.desc(dst);
+        let layers = src_desc.layers.min(dst_desc.layers);
+        let copy = ImageCopy {
+            src: ImageRegion::mip(&src_desc, 0).with_layers(0, layers),
+            dst: ImageRegion::mip(&dst_desc, 0).with_layers(0, layers),
+        };
+        if copy.src.extent == copy.dst.extent {
+            self.copy_image_regions(src, dst, &[copy])
+        } else {
+            self.blit_image(src, dst, &[copy], Filter::Linear)
+        }
+    }
+    fn copy_image_regions(
+        &self,
+        src: ImageHandle,
+        dst: ImageHandle,
+        regions: &[ImageCopy],
+    ) -> Result<(), ImageError> {
+        validate_copy(self, src, dst, regions)?;
+        let command_buffer = CommandBuffer::record(self, "CopyImage", |command_buffer| unsafe {
+            record_copy(self, command_buffer, src, dst, regions);
         });
         self.present_queue
             .submit(self, &[], &[], &[], command_buffer);
-        let command_buffer = CommandBuffer::record(self, "ImageCopy", |command_buffer| unsafe {
-            self.device.cmd_copy_image(
-                command_buffer,
-                src_data.image,
-                vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
-                dst_data.image,
-                vk::ImageLayout::TRANSFER_DST_OPTIMAL,
-                &[image_copy],
-            );
+        Ok(())
+    }
+    fn blit_image(
+        &self,
+        src: ImageHandle,
+        dst: ImageHandle,
+        regions: &[ImageCopy],
+        filter: Filter,
+    ) -> Result<(), ImageError> {
+        validate_blit(self, src, dst, regions, filter)?;
+        let command_buffer = CommandBuffer::record(self, "BlitImage", |command_buffer| unsafe {
+            record_blit(self, command_buffer, src, dst, regions, filter);
         });
-        self.present_queue.submit(
-            self,
-            &[vk::PipelineStageFlags::TRANSFER],
-            &[],
-            &[],
-            command_buffer,
-        );
-        let command_buffer = CommandBuffer::record(self, "FromSrc", |command_buffer| {
-            let layout_transition_barrier = vk::ImageMemoryBarrier {
-                s_type: vk::StructureType::IMAGE_MEMORY_BARRIER,
-                p_next: ptr::null(),
-                src_access_mask: vk::AccessFlags::empty(),
-                dst_access_mask: vk::AccessFlags::COLOR_ATTACHMENT_WRITE,
-                old_layout: vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
-                new_layout: vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
-                src_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
-                dst_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
-                image: src_data.image,
-                subresource_range: vk::ImageSubresourceRange {
-                    aspect_mask,
-                    base_mip_level: 0,
-                    level_count: 1,
-                    base_array_layer: 0,
-                    layer_count: 1,
+        self.present_queue
+            .submit(self, &[], &[], &[], command_buffer);
+        Ok(())
+    }
+}
+
+fn subresource_layers(desc: &ImageDesc, region: &ImageRegion) -> vk::ImageSubresourceLayers {
+    vk::ImageSubresourceLayers {
+        aspect_mask: get_aspect_mask(desc),
+        mip_level: region.mip_level,
+        base_array_layer: region.base_layer,
+        layer_count: region.layer_count,
+    }
+}
+
+fn offset(region: &ImageRegion) -> vk::Offset3D {
+    let (x, y, z) = region.offset;
+    vk::Offset3D { x, y, z }
+}
+
+/// The corner opposite to the offset
+fn end_offset(region: &ImageRegion) -> vk::Offset3D {
+    let (x, y, z) = region.offset;
+    let (width, height, depth) = region.extent;
+    vk::Offset3D {
+        x: x + width as i32,
+        y: y + height as i32,
+        z: z + depth as i32,
+    }
+}
+
+/// Checks that `region` lies inside of `desc`
+fn validate_region(desc: &ImageDesc, region: &ImageRegion) -> Result<(), ImageError> {
+    if region.mip_level >= desc.mip_levels {
+        return Err(ImageError::InvalidMipLevel {
+            mip_level: region.mip_level,
+            mip_levels: desc.mip_levels,
+        });
+    }
+    if region.layer_count == 0 || region.base_layer + region.layer_count > desc.layers {
+        return Err(ImageError::InvalidLayerRange {
+            layers: region.base_layer..region.base_layer + region.layer_count,
+            image_layers: desc.layers,
+        });
+    }
+    let (x, y, z) = region.offset;
+    let (width, height, depth) = region.extent;
+    let (mip_width, mip_height, mip_depth) = desc.mip_extent(region.mip_level);
+    let inside = |offset: i32, extent: u32, mip_extent: u32| {
+        offset >= 0 && offset as u64 + extent as u64 <= mip_extent as u64
+    };
+    if !inside(x, width, mip_width)
+        || !inside(y, height, mip_height)
+        || !inside(z, depth, mip_depth)
+    {
+        return Err(ImageError::RegionOutOfBounds {
+            offset: region.offset,
+            extent: region.extent,
+            mip_extent: (mip_width, mip_height, mip_depth),
+        });
+    }
+    Ok(())
+}
+
+/// Extent of a region in blocks of `format`, compressed and uncompressed formats
+/// with the same block size copy one block to one texel
+fn extent_in_blocks(format: Format, (width, height, depth): (u32, u32, u32)) -> (u32, u32, u32) {
+    let (block_width, block_height) = format.block_extent().unwrap_or((1, 1));
+    (
+        width.div_ceil(block_width),
+        height.div_ceil(block_height),
+        depth,
+    )
+}
+
+/// Checks that the formats can be copied, that every region lies inside of its
+/// image and that no region is scaled
+pub(crate) fn validate_copy(
+    ctx: &Context,
+    src: ImageHandle,
+    dst: ImageHandle,
+    regions: &[ImageCopy],
+) -> Result<(), ImageError> {
+    let src_desc = ctx.images.get(src).desc.clone();
+    let dst_desc = ctx.images.get(dst).desc.clone();
+    if !src_desc.format.is_copy_compatible(dst_desc.format) {
+        return Err(ImageError::IncompatibleCopyFormats {
+            src: src_desc.format,
+            dst: dst_desc.format,
+        });
+    }
+    for copy in regions {
+        validate_region(&src_desc, &copy.src)?;
+        validate_region(&dst_desc, &copy.dst)?;
+        let scaled = extent_in_blocks(src_desc.format, copy.src.extent)
+            != extent_in_blocks(dst_desc.format, copy.dst.extent)
+            || copy.src.layer_count != copy.dst.layer_count;
+        if scaled {
+            return Err(ImageError::CopyExtentMismatch);
+        }
+    }
+    Ok(())
+}
+
+/// Checks that both formats support blits and `filter` and that every region lies
+/// inside of its image. Depth and stencil formats can't be filtered and only be
+/// blitted to the same format, multisampled images have to be resolved instead.
+pub(crate) fn validate_blit(
+    ctx: &Context,
+    src: ImageHandle,
+    dst: ImageHandle,
+    regions: &[ImageCopy],
+    filter: Filter,
+) -> Result<(), ImageError> {
+    let src_desc = ctx.images.get(src).desc.clone();
+    let dst_desc = ctx.images.get(dst).desc.clone();
+    if src_desc.samples != SampleCount::S1 || dst_desc.samples != SampleCount::S1 {
+        return Err(ImageError::MultisampledBlit);
+    }
+    for blit in regions {
+        validate_region(&src_desc, &blit.src)?;
+        validate_region(&dst_desc, &blit.dst)?;
+    }
+    let src_format = src_desc.format;
+    let dst_format = dst_desc.format;
+    let blit_features = |format| unsafe {
+        ctx.instance
+            .get_physical_device_format_properties(ctx.pdevice, from_format(format))
+            .optimal_tiling_features
+    };
+    let src_features = blit_features(src_format);
+    let depth_stencil = src_format.aspect_mask() != ImageAspect::COLOR
+        || dst_format.aspect_mask() != ImageAspect::COLOR;
+    if !src_features.contains(vk::FormatFeatureFlags::BLIT_SRC)
+        || !blit_features(dst_format).contains(vk::FormatFeatureFlags::BLIT_DST)
+        || (depth_stencil && src_format != dst_format)
+    {
+        return Err(ImageError::UnsupportedBlit {
+            src: src_format,
+            dst: dst_format,
+        });
+    }
+    let linear = src_features.contains(vk::FormatFeatureFlags::SAMPLED_IMAGE_FILTER_LINEAR);
+    if filter == Filter::Linear && (depth_stencil || !linear) {
+        return Err(ImageError::UnsupportedLinearFilter(src_format));
+    }
+    Ok(())
+}
+
+/// Records a copy of every region. Affected subresources are moved into the transfer
+/// layouts and stay there until their next use. The regions have to be checked with
+/// [`validate_copy`] first.
+pub(crate) unsafe fn record_copy(
+    ctx: &Context,
+    command_buffer: vk::CommandBuffer,
+    src: ImageHandle,
+    dst: ImageHandle,
+    regions: &[ImageCopy],
+) {
+    let src_data = ctx.images.get(src);
+    let dst_data = ctx.images.get(dst);
+    let copies: Vec<_> = regions
+        .iter()
+        .map(|copy| {
+            vk::ImageCopy {
+                src_subresource: subresource_layers(&src_data.desc, &copy.src),
+                src_offset: offset(&copy.src),
+                dst_subresource: subresource_layers(&dst_data.desc, &copy.dst),
+                dst_offset: offset(&copy.dst),
+                extent: vk::Extent3D {
+                    width: copy.src.extent.0,
+                    height: copy.src.extent.1,
+                    depth: copy.src.extent.2,
                 },
-            };
-            unsafe {
-                self.device.cmd_pipeline_barrier(
-                    command_buffer,
-                    vk::PipelineStageFlags::TOP_OF_PIPE,
-                    vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
-                    vk::DependencyFlags::empty(),
-                    &[],
-                    &[],
-                    &[layout_transition_barrier],
-                );
             }
+        })
+        .collect();
+    transition_for_transfer(ctx, command_buffer, &src_data, &dst_data, regions);
+    ctx.device.cmd_copy_image(
+        command_buffer,
+        src_data.image,
+        vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+        dst_data.image,
+        vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+        &copies,
+    );
+}
+
+/// Like [`record_copy`], but scales every region with `filter`. The formats have
+/// to be checked with [`validate_blit`] first.
+pub(crate) unsafe fn record_blit(
+    ctx: &Context,
+    command_buffer: vk::CommandBuffer,
+    src: ImageHandle,
+    dst: ImageHandle,
+    regions: &[ImageCopy],
+    filter: Filter,
+) {
+    let src_data = ctx.images.get(src);
+    let dst_data = ctx.images.get(dst);
+    let blits: Vec<_> = regions
+        .iter()
+        .map(|blit| {
+            vk::ImageBlit {
+                src_subresource: subresource_layers(&src_data.desc, &blit.src),
+                src_offsets: [offset(&blit.src), end_offset(&blit.src)],
+                dst_subresource: subresource_layers(&dst_data.desc, &blit.dst),
+                dst_offsets: [offset(&blit.dst), end_offset(&blit.dst)],
+            }
+        })
+        .collect();
+    transition_for_transfer(ctx, command_buffer, &src_data, &dst_data, regions);
+    ctx.device.cmd_blit_image(
+        command_buffer,
+        src_data.image,
+        vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+        dst_data.image,
+        vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+        &blits,
+        sampler::filter(filter),
+    );
+}
+
+/// Moves the source regions into the transfer source layout and the destination
+/// regions into the transfer destination layout
+unsafe fn transition_for_transfer(
+    ctx: &Context,
+    command_buffer: vk::CommandBuffer,
+    src: &ImageData,
+    dst: &ImageData,
+    regions: &[ImageCopy],
+) {
+    let range = |desc: &ImageDesc, region: &ImageRegion| {
+        vk::ImageSubresourceRange {
+            aspect_mask: get_aspect_mask(desc),
+            base_mip_level: region.mip_level,
+            level_count: 1,
+            base_array_layer: region.base_layer,
+            layer_count: region.layer_count,
+        }
+    };
+    let mut transition = Transition::default();
+    for copy in regions {
+        transition.merge(src.transition(
+            &range(&src.desc, &copy.src),
+            SubresourceState::transfer_src(),
+        ));
+        transition.merge(dst.transition(
+            &range(&dst.desc, &copy.dst),
+            SubresourceState::transfer_dst(),
+        ));
+    }
+    transition.record(ctx, command_buffer);
+}
+
+pub(crate) fn clear_color_value(color: ClearColor) -> vk::ClearColorValue {
+    match color {
+        ClearColor::Float(float32) => vk::ClearColorValue { float32 },
+        ClearColor::Int(int32) => vk::ClearColorValue { int32 },
+        ClearColor::Uint(uint32) => vk::ClearColorValue { uint32 },
+    }
+}
+
+/// Records a clear of every subresource of a color image or view
+pub(crate) unsafe fn record_clear_color(
+    ctx: &Context,
+    command_buffer: vk::CommandBuffer,
+    resource: ImageResource,
+    color: ClearColor,
+) {
+    let (_, image, range) = resolve_image_resource(ctx, resource);
+    let data = ctx.images.get(image);
+    assert!(
+        data.desc.format.aspect_mask().contains(ImageAspect::COLOR),
+        "{:?} is not a color format",
+        data.desc.format
+    );
+    data.transition(&range, SubresourceState::transfer_dst())
+        .record(ctx, command_buffer);
+    ctx.device.cmd_clear_color_image(
+        command_buffer,
+        data.image,
+        vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+        &clear_color_value(color),
+        &[range],
+    );
+}
+
+/// Records a clear of every subresource of a depth or stencil image or view
+pub(crate) unsafe fn record_clear_depth_stencil(
+    ctx: &Context,
+    command_buffer: vk::CommandBuffer,
+    resource: ImageResource,
+    value: ClearDepthStencil,
+) {
+    let (_, image, range) = resolve_image_resource(ctx, resource);
+    let data = ctx.images.get(image);
+    assert!(
+        !data.desc.format.aspect_mask().contains(ImageAspect::COLOR),
+        "{:?} is not a depth or stencil format",
+        data.desc.format
+    );
+    data.transition(&range, SubresourceState::transfer_dst())
+        .record(ctx, command_buffer);
+    ctx.device.cmd_clear_depth_stencil_image(
+        command_buffer,
+        data.image,
+        vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+        &vk::ClearDepthStencilValue {
+            depth: value.depth,
+            stencil: value.stencil,
+        },
+        &[range],
+    );
+}
+
+/// Records and submits a transition of the whole image outside of a command list
+pub(crate) fn transition_image_now(ctx: &Context, image: ImageHandle, state: SubresourceState) {
+    let data = ctx.images.get(image);
+    let range = full_subresource_range(&data.desc);
+    let transition = data.transition(&range, state);
+    let command_buffer = CommandBuffer::record(ctx, "ImageTransition", |command_buffer| unsafe {
+        transition.record(ctx, command_buffer);
+    });
+    ctx.present_queue.submit(ctx, &[], &[], &[], command_buffer);
+}
+
+fn image_usage_flags(usage: ImageUsage) -> vk::ImageUsageFlags {
+    let mut flags = vk::ImageUsageFlags::empty();
+    if usage.contains(ImageUsage::TRANSFER_SRC) {
+        flags |= vk::ImageUsageFlags::TRANSFER_SRC;
+    }
+    if usage.contains(ImageUsage::TRANSFER_DST) {
+        flags |= vk::ImageUsageFlags::TRANSFER_DST;
+    }
+    if usage.contains(ImageUsage::SAMPLED) {
+        flags |= vk::ImageUsageFlags::SAMPLED;
+    }
+    if usage.contains(ImageUsage::STORAGE) {
+        flags |= vk::ImageUsageFlags::STORAGE;
+    }
+    if usage.contains(ImageUsage::COLOR_ATTACHMENT) {
+        flags |= vk::ImageUsageFlags::COLOR_ATTACHMENT;
+    }
+    if usage.contains(ImageUsage::DEPTH_STENCIL_ATTACHMENT) {
+        flags |= vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT;
+    }
+    if usage.contains(ImageUsage::INPUT_ATTACHMENT) {
+        flags |= vk::ImageUsageFlags::INPUT_ATTACHMENT;
+    }
+    if usage.contains(ImageUsage::TRANSIENT_ATTACHMENT) {
+        flags |= vk::ImageUsageFlags::TRANSIENT_ATTACHMENT;
+    }
+    flags
+}
+
+/// Usages and the optimal tiling features they need
+const USAGE_FEATURES: [(ImageUsage, vk::FormatFeatureFlags); 4] = [
+    (ImageUsage::SAMPLED, vk::FormatFeatureFlags::SAMPLED_IMAGE),
+    (ImageUsage::STORAGE, vk::FormatFeatureFlags::STORAGE_IMAGE),
+    (
+        ImageUsage::COLOR_ATTACHMENT,
+        vk::FormatFeatureFlags::COLOR_ATTACHMENT,
+    ),
+    (
+        ImageUsage::DEPTH_STENCIL_ATTACHMENT,
+        vk::FormatFeatureFlags::DEPTH_STENCIL_ATTACHMENT,
+    ),
+];
+
+/// Checks the usage against the optimal tiling features of the format. Transfer
+/// usages are not checked, Vulkan 1.0 doesn't report them.
+fn validate_usage(ctx: &Context, desc: &ImageDesc) -> Result<(), ImageError> {
+    let unsupported = desc.usage - ctx.format_usage(desc.format);
+    if !unsupported.is_empty() {
+        return Err(ImageError::UnsupportedUsage {
+            format: desc.format,
+            usage: unsupported,
         });
-        self.present_queue
-            .submit(self, &[], &[], &[], command_buffer);
-        let command_buffer =
-            CommandBuffer::record(self, "ToPresentFromImageCopy", |command_buffer| {
-                let layout_transition_barrier = vk::ImageMemoryBarrier {
-                    s_type: vk::StructureType::IMAGE_MEMORY_BARRIER,
-                    p_next: ptr::null(),
-                    src_access_mask: vk::AccessFlags::empty(),
-                    dst_access_mask: vk::AccessFlags::empty(),
-                    old_layout: vk::ImageLayout::TRANSFER_DST_OPTIMAL,
-                    new_layout: vk::ImageLayout::PRESENT_SRC_KHR,
-                    src_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
-                    dst_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
-                    image: dst_data.image,
-                    subresource_range: vk::ImageSubresourceRange {
-                        aspect_mask,
-                        base_mip_level: 0,
-                        level_count: 1,
-                        base_array_layer: 0,
-                        layer_count: 1,
-                    },
-                };
-                unsafe {
-                    self.device.cmd_pipeline_barrier(
-                        command_buffer,
-                        vk::PipelineStageFlags::TOP_OF_PIPE,
-                        vk::PipelineStageFlags::BOTTOM_OF_PIPE,
-                        vk::DependencyFlags::empty(),
-                        &[],
-                        &[],
-                        &[layout_transition_barrier],
-                    );
-                }
-            });
-        self.present_queue
-            .submit(self, &[], &[], &[], command_buffer);
     }
+    Ok(())
 }
 
-fn get_image_layout(desc: &ImageDesc) -> vk::ImageLayout {
-    match desc.layout {
-        ImageLayout::Color => vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
-        ImageLayout::Depth => vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
+fn validate_samples(
+    ctx: &Context,
+    desc: &ImageDesc,
+    image_type: vk::ImageType,
+    usage: vk::ImageUsageFlags,
+) -> Result<(), ImageError> {
+    if desc.samples == SampleCount::S1 {
+        return Ok(());
+    }
+    if desc.mip_levels > 1 {
+        return Err(ImageError::MultisampledMips);
     }
+    let supported = unsafe {
+        ctx.instance
+            .get_physical_device_image_format_properties(
+                ctx.pdevice,
+                from_format(desc.format),
+                image_type,
+                vk::ImageTiling::OPTIMAL,
+                usage,
+                image_create_flags(desc),
+            )
+            .map(|properties| properties.sample_counts)
+            .unwrap_or_else(|_| vk::SampleCountFlags::empty())
+    };
+    if !desc.samples.is_supported(supported.as_raw()) {
+        return Err(ImageError::UnsupportedSampleCount {
+            format: desc.format,
+            samples: desc.samples,
+        });
+    }
+    Ok(())
 }
+
+/// Returns the image type, the view type that covers the whole image and the depth
+fn image_types(desc: &ImageDesc) -> (vk::ImageType, vk::ImageViewType, u32) {
+    let arrayed = desc.layers > 1;
+    match desc.dimension {
+        ImageDimension::D1 if arrayed => {
+            (vk::ImageType::TYPE_1D, vk::ImageViewType::TYPE_1D_ARRAY, 1)
+        }
+        ImageDimension::D1 => (vk::ImageType::TYPE_1D, vk::ImageViewType::TYPE_1D, 1),
+        ImageDimension::D2 if arrayed => {
+            (vk::ImageType::TYPE_2D, vk::ImageViewType::TYPE_2D_ARRAY, 1)
+        }
+        ImageDimension::D2 => (vk::ImageType::TYPE_2D, vk::ImageViewType::TYPE_2D, 1),
+        ImageDimension::D3 => {
+            (vk::ImageType::TYPE_3D, vk::ImageViewType::TYPE_3D, desc.depth)
+        }
+        ImageDimension::Cube if desc.layers > 6 => {
+            (vk::ImageType::TYPE_2D, vk::ImageViewType::CUBE_ARRAY, 1)
+        }
+        ImageDimension::Cube => (vk::ImageType::TYPE_2D, vk::ImageViewType::CUBE, 1),
+    }
+}
+
+fn image_create_flags(desc: &ImageDesc) -> vk::ImageCreateFlags {
+    let mut flags = match desc.dimension {
+        ImageDimension::Cube => vk::ImageCreateFlags::CUBE_COMPATIBLE,
+        _ => vk::ImageCreateFlags::empty(),
+    };
+    if desc.mutable_format {
+        flags |= vk::ImageCreateFlags::MUTABLE_FORMAT;
+    }
+    flags
+}
+
+/// Whole cubes are viewed as cube maps, everything else as single images or arrays
+fn default_view_type(desc: &ImageDesc, base_layer: u32, layers: u32) -> ImageViewType {
+    let arrayed = layers > 1;
+    match desc.dimension {
+        ImageDimension::D1 if arrayed => ImageViewType::D1Array,
+        ImageDimension::D1 => ImageViewType::D1,
+        ImageDimension::D3 => ImageViewType::D3,
+        ImageDimension::Cube if base_layer.is_multiple_of(6) && layers == 6 => ImageViewType::Cube,
+        ImageDimension::Cube if base_layer.is_multiple_of(6) && layers.is_multiple_of(6) => {
+            ImageViewType::CubeArray
+        }
+        _ if arrayed => ImageViewType::D2Array,
+        _ => ImageViewType::D2,
+    }
+}
+
+fn image_view_type(view_type: ImageViewType) -> vk::ImageViewType {
+    match view_type {
+        ImageViewType::D1 => vk::ImageViewType::TYPE_1D,
+        ImageViewType::D1Array => vk::ImageViewType::TYPE_1D_ARRAY,
+        ImageViewType::D2 => vk::ImageViewType::TYPE_2D,
+        ImageViewType::D2Array => vk::ImageViewType::TYPE_2D_ARRAY,
+        ImageViewType::D3 => vk::ImageViewType::TYPE_3D,
+        ImageViewType::Cube => vk::ImageViewType::CUBE,
+        ImageViewType::CubeArray => vk::ImageViewType::CUBE_ARRAY,
+    }
+}
+
+fn swizzle(swizzle: Swizzle) -> vk::ComponentSwizzle {
+    match swizzle {
+        Swizzle::Identity => vk::ComponentSwizzle::IDENTITY,
+        Swizzle::Zero => vk::ComponentSwizzle::ZERO,
+        Swizzle::One => vk::ComponentSwizzle::ONE,
+        Swizzle::R => vk::ComponentSwizzle::R,
+        Swizzle::G => vk::ComponentSwizzle::G,
+        Swizzle::B => vk::ComponentSwizzle::B,
+        Swizzle::A => vk::ComponentSwizzle::A,
+    }
+}
+
+fn component_mapping(components: ComponentMapping) -> vk::ComponentMapping {
+    vk::ComponentMapping {
+        r: swizzle(components.r),
+        g: swizzle(components.g),
+        b: swizzle(components.b),
+        a: swizzle(components.a),
+    }
+}
+
+/// The view, the image and the subresources behind an image resource
+pub(crate) fn resolve_image_resource(
+    ctx: &Context,
+    resource: ImageResource,
+) -> (vk::ImageView, ImageHandle, vk::ImageSubresourceRange) {
+    match resource {
+        ImageResource::Image(image) => {
+            let data = ctx.images.get(image);
+            (data.image_view, image, full_subresource_range(&data.desc))
+        }
+        ImageResource::View(view) => {
+            let view = ctx.image_views.get(view);
+            (view.image_view, view.image, view.subresource_range)
+        }
+    }
+}
+
+pub(crate) fn full_subresource_range(desc: &ImageDesc) -> vk::ImageSubresourceRange {
+    vk::ImageSubresourceRange {
+        aspect_mask: get_aspect_mask(desc),
+        base_mip_level: 0,
+        level_count: desc.mip_levels,
+        base_array_layer: 0,
+        layer_count: desc.layers,
+    }
+}
+
 pub(crate) fn get_aspect_mask(desc: &ImageDesc) -> vk::ImageAspectFlags {
-    match desc.layout {
-        ImageLayout::Color => vk::ImageAspectFlags::COLOR,
-        ImageLayout::Depth => vk::ImageAspectFlags::DEPTH,
+    vk::ImageAspectFlags::from_raw(desc.format.aspect_mask().bits())
+}
+
+/// Buffer copies transfer a single aspect, depth is preferred over stencil
+/// Bytes per texel or block of the aspect that [`buffer_copy_aspect`] selects
+fn buffer_copy_block_size(format: Format) -> Option<u64> {
+    match format {
+        Format::D16_UNORM | Format::D16_UNORM_S8_UINT => Some(2),
+        Format::X8_D24_UNORM_PACK32
+        | Format::D24_UNORM_S8_UINT
+        | Format::D32_SFLOAT
+        | Format::D32_SFLOAT_S8_UINT => Some(4),
+        _ => format.block_size().map(u64::from),
+    }
+}
+
+/// Checks that every region addresses existing subresources and that the buffer
+/// holds all of their tightly packed texels
+fn validate_buffer_regions(
+    desc: &ImageDesc,
+    buffer_size: u64,
+    regions: &[BufferRegion],
+) -> Result<(), ImageError> {
+    let block_size =
+        buffer_copy_block_size(desc.format).ok_or(ImageError::UnknownTexelSize(desc.format))?;
+    let (block_width, block_height) = desc.format.block_extent().unwrap_or((1, 1));
+    for region in regions {
+        if region.mip_level >= desc.mip_levels {
+            return Err(ImageError::InvalidMipLevel {
+                mip_level: region.mip_level,
+                mip_levels: desc.mip_levels,
+            });
+        }
+        if region.layer_count == 0 || region.base_layer + region.layer_count > desc.layers {
+            return Err(ImageError::InvalidLayerRange {
+                layers: region.base_layer..region.base_layer + region.layer_count,
+                image_layers: desc.layers,
+            });
+        }
+        let (width, height, depth) = desc.mip_extent(region.mip_level);
+        let layer_size = width.div_ceil(block_width) as u64
+            * height.div_ceil(block_height) as u64
+            * depth as u64
+            * block_size;
+        let required = region.offset + layer_size * region.layer_count as u64;
+        if required > buffer_size {
+            return Err(ImageError::SizeMismatch {
+                expected: required as usize,
+                actual: buffer_size as usize,
+            });
+        }
+    }
+    Ok(())
+}
+
+fn buffer_copy_aspect(format: Format) -> vk::ImageAspectFlags {
+    if format.has_depth() {
+        vk::ImageAspectFlags::DEPTH
+    } else if format.has_stencil() {
+        vk::ImageAspectFlags::STENCIL
+    } else {
+        vk::ImageAspectFlags::COLOR
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tephra::image::{ImageLayout, Resolution};
+
+    fn desc(format: Format) -> ImageDesc {
+        let resolution = Resolution {
+            width: 16,
+            height: 8,
+        };
+        let mut desc = ImageDesc::new(resolution, ImageLayout::Sampled, format)
+            .with_dimension(ImageDimension::D2, 2);
+        desc.mip_levels = 2;
+        desc
+    }
+
+    #[test]
+    fn regions_have_to_fit_the_image() {
+        let desc = desc(Format::R8G8B8A8_UNORM);
+        assert!(validate_region(&desc, &ImageRegion::mip(&desc, 1)).is_ok());
+        match validate_region(&desc, &ImageRegion::mip(&desc, 2)) {
+            Err(ImageError::InvalidMipLevel { mip_level: 2, .. }) => (),
+            result => panic!("Expected an invalid mip level, got {:?}", result),
+        }
+        let layers = ImageRegion::mip(&desc, 0).with_layers(1, 2);
+        match validate_region(&desc, &layers) {
+            Err(ImageError::InvalidLayerRange { image_layers: 2, .. }) => (),
+            result => panic!("Expected an invalid layer range, got {:?}", result),
+        }
+        let mut shifted = ImageRegion::mip(&desc, 1);
+        shifted.offset = (1, 0, 0);
+        match validate_region(&desc, &shifted) {
+            Err(ImageError::RegionOutOfBounds {
+                mip_extent: (8, 4, 1),
+                ..
+            }) => (),
+            result => panic!("Expected an out of bounds region, got {:?}", result),
+        }
+    }
+
+    #[test]
+    fn buffer_regions_have_to_fit_the_buffer() {
+        let compressed = desc(Format::BC1_RGB_UNORM_BLOCK);
+        // 4x2 blocks of 8 bytes for both layers of the first mip
+        let regions = [BufferRegion {
+            offset: 16,
+            mip_level: 0,
+            base_layer: 0,
+            layer_count: 2,
+        }];
+        assert!(validate_buffer_regions(&compressed, 144, &regions).is_ok());
+        match validate_buffer_regions(&compressed, 143, &regions) {
+            Err(ImageError::SizeMismatch {
+                expected: 144,
+                actual: 143,
+            }) => (),
+            result => panic!("Expected a size mismatch, got {:?}", result),
+        }
+        let depth = desc(Format::D32_SFLOAT_S8_UINT);
+        // Only the depth aspect is copied
+        assert!(validate_buffer_regions(&depth, 16 * 8 * 4 * 2, &regions[..]).is_err());
+        let regions = [BufferRegion {
+            offset: 0,
+            ..regions[0]
+        }];
+        assert!(validate_buffer_regions(&depth, 16 * 8 * 4 * 2, &regions).is_ok());
+        let undefined = desc(Format::UNDEFINED);
+        match validate_buffer_regions(&undefined, 1 << 20, &regions) {
+            Err(ImageError::UnknownTexelSize(Format::UNDEFINED)) => (),
+            result => panic!("Expected an unknown texel size, got {:?}", result),
+        }
+    }
+
+    #[test]
+    fn compressed_extents_are_counted_in_blocks() {
+        let compressed = Format::BC1_RGB_UNORM_BLOCK;
+        assert_eq!(extent_in_blocks(compressed, (16, 8, 1)), (4, 2, 1));
+        // Partial blocks at the edge of a mip level still count as a whole block
+        assert_eq!(extent_in_blocks(compressed, (2, 2, 1)), (1, 1, 1));
+        assert_eq!(extent_in_blocks(Format::R32G32_UINT, (4, 2, 1)), (4, 2, 1));
     }
 }
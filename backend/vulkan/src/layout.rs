@@ -0,0 +1,338 @@
+//! Tracks the layout and the last access of every image subresource, so that the
+//! barriers between two uses of an image can be recorded automatically.
+use super::Context;
+use ash::{version::DeviceV1_0, vk};
+use tephra::{commandbuffer::Access, image::Format, image::ImageAspect};
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct SubresourceState {
+    pub layout: vk::ImageLayout,
+    pub access: vk::AccessFlags,
+    pub stage: vk::PipelineStageFlags,
+}
+
+impl SubresourceState {
+    pub fn new(
+        layout: vk::ImageLayout,
+        access: vk::AccessFlags,
+        stage: vk::PipelineStageFlags,
+    ) -> Self {
+        SubresourceState {
+            layout,
+            access,
+            stage,
+        }
+    }
+
+    /// The state of a new image, the content is undefined
+    pub fn undefined() -> Self {
+        SubresourceState::new(
+            vk::ImageLayout::UNDEFINED,
+            vk::AccessFlags::empty(),
+            vk::PipelineStageFlags::TOP_OF_PIPE,
+        )
+    }
+
+    pub fn transfer_src() -> Self {
+        SubresourceState::new(
+            vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+            vk::AccessFlags::TRANSFER_READ,
+            vk::PipelineStageFlags::TRANSFER,
+        )
+    }
+
+    pub fn transfer_dst() -> Self {
+        SubresourceState::new(
+            vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+            vk::AccessFlags::TRANSFER_WRITE,
+            vk::PipelineStageFlags::TRANSFER,
+        )
+    }
+
+    /// Read through a sampled image descriptor in `stage`
+    pub fn sampled(format: Format, stage: vk::PipelineStageFlags) -> Self {
+        SubresourceState::new(
+            sampled_layout(format),
+            vk::AccessFlags::SHADER_READ,
+            stage,
+        )
+    }
+
+    /// Accessed through a storage image descriptor in `stage`
+    pub fn storage(access: Access, stage: vk::PipelineStageFlags) -> Self {
+        let access = match access {
+            Access::Read => vk::AccessFlags::SHADER_READ,
+            Access::Write => vk::AccessFlags::SHADER_READ | vk::AccessFlags::SHADER_WRITE,
+        };
+        SubresourceState::new(vk::ImageLayout::GENERAL, access, stage)
+    }
+
+    /// Rendered to as a color or depth attachment, depending on `format`
+    pub fn attachment(format: Format) -> Self {
+        if format.aspect_mask().contains(ImageAspect::COLOR) {
+            SubresourceState::new(
+                vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+                vk::AccessFlags::COLOR_ATTACHMENT_READ | vk::AccessFlags::COLOR_ATTACHMENT_WRITE,
+                vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+            )
+        } else {
+            SubresourceState::new(
+                vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
+                vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_READ
+                    | vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE,
+                vk::PipelineStageFlags::EARLY_FRAGMENT_TESTS
+                    | vk::PipelineStageFlags::LATE_FRAGMENT_TESTS,
+            )
+        }
+    }
+
+    pub fn present() -> Self {
+        SubresourceState::new(
+            vk::ImageLayout::PRESENT_SRC_KHR,
+            vk::AccessFlags::MEMORY_READ,
+            vk::PipelineStageFlags::BOTTOM_OF_PIPE,
+        )
+    }
+
+    fn writes(&self) -> vk::AccessFlags {
+        let writes = vk::AccessFlags::SHADER_WRITE
+            | vk::AccessFlags::COLOR_ATTACHMENT_WRITE
+            | vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE
+            | vk::AccessFlags::TRANSFER_WRITE
+            | vk::AccessFlags::HOST_WRITE
+            | vk::AccessFlags::MEMORY_WRITE;
+        self.access & writes
+    }
+}
+
+/// The layout that sampled and combined image descriptors of `format` use
+pub fn sampled_layout(format: Format) -> vk::ImageLayout {
+    if format.aspect_mask().contains(ImageAspect::COLOR) {
+        vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL
+    } else {
+        vk::ImageLayout::DEPTH_STENCIL_READ_ONLY_OPTIMAL
+    }
+}
+
+/// The state of every mip level and layer of an image
+pub struct ImageState {
+    layers: u32,
+    subresources: Vec<SubresourceState>,
+}
+
+impl ImageState {
+    pub fn new(mip_levels: u32, layers: u32) -> Self {
+        ImageState {
+            layers,
+            subresources: vec![SubresourceState::undefined(); (mip_levels * layers) as usize],
+        }
+    }
+
+    fn indices<'a>(
+        &self,
+        range: &'a vk::ImageSubresourceRange,
+    ) -> impl Iterator<Item = (u32, u32, usize)> + 'a {
+        let layers = self.layers;
+        let mips = range.base_mip_level..range.base_mip_level + range.level_count;
+        mips.flat_map(move |mip| {
+            let layer_range = range.base_array_layer..range.base_array_layer + range.layer_count;
+            layer_range.map(move |layer| (mip, layer, (mip * layers + layer) as usize))
+        })
+    }
+
+    /// Records `state` without a barrier, for commands that transition the image
+    /// themselves like renderpasses
+    pub fn set(&mut self, range: &vk::ImageSubresourceRange, state: SubresourceState) {
+        for (_, _, index) in self.indices(range).collect::<Vec<_>>() {
+            self.subresources[index] = state;
+        }
+    }
+
+    /// Moves every subresource in `range` into `state` and returns the barriers that
+    /// have to be recorded before the access. Reads in the same layout don't need
+    /// a barrier.
+    pub fn transition(
+        &mut self,
+        image: vk::Image,
+        range: &vk::ImageSubresourceRange,
+        state: SubresourceState,
+    ) -> Transition {
+        let mut transition = Transition::default();
+        for (mip, layer, index) in self.indices(range).collect::<Vec<_>>() {
+            let current = self.subresources[index];
+            let read_after_read = current.layout == state.layout
+                && current.writes().is_empty()
+                && state.writes().is_empty();
+            if read_after_read {
+                self.subresources[index].access |= state.access;
+                self.subresources[index].stage |= state.stage;
+                continue;
+            }
+            transition.src_stage |= current.stage;
+            transition.dst_stage |= state.stage;
+            let subresource_range = vk::ImageSubresourceRange {
+                aspect_mask: range.aspect_mask,
+                base_mip_level: mip,
+                level_count: 1,
+                base_array_layer: layer,
+                layer_count: 1,
+            };
+            // Neighbouring layers of the same mip with the same state share a barrier
+            if let Some(last) = transition.barriers.last_mut() {
+                let last_range = &mut last.subresource_range;
+                let adjacent = last.image == image
+                    && last_range.base_mip_level == mip
+                    && last_range.base_array_layer + last_range.layer_count == layer
+                    && last.old_layout == current.layout
+                    && last.src_access_mask == current.writes();
+                if adjacent {
+                    last_range.layer_count += 1;
+                    self.subresources[index] = state;
+                    continue;
+                }
+            }
+            transition.barriers.push(vk::ImageMemoryBarrier {
+                src_access_mask: current.writes(),
+                dst_access_mask: state.access,
+                old_layout: current.layout,
+                new_layout: state.layout,
+                src_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
+                dst_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
+                image,
+                subresource_range,
+                ..Default::default()
+            });
+            self.subresources[index] = state;
+        }
+        transition
+    }
+}
+
+/// Barriers of one or more transitions that are recorded together
+#[derive(Default)]
+pub struct Transition {
+    src_stage: vk::PipelineStageFlags,
+    dst_stage: vk::PipelineStageFlags,
+    barriers: Vec<vk::ImageMemoryBarrier>,
+}
+
+impl Transition {
+    pub fn merge(&mut self, other: Transition) {
+        self.src_stage |= other.src_stage;
+        self.dst_stage |= other.dst_stage;
+        self.barriers.extend(other.barriers);
+    }
+
+    /// # Safety
+    ///
+    /// `command_buffer` has to be recording.
+    pub unsafe fn record(&self, ctx: &Context, command_buffer: vk::CommandBuffer) {
+        if self.barriers.is_empty() {
+            return;
+        }
+        ctx.device.cmd_pipeline_barrier(
+            command_buffer,
+            self.src_stage,
+            self.dst_stage,
+            vk::DependencyFlags::empty(),
+            &[],
+            &[],
+            &self.barriers,
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn range(
+        base_mip_level: u32,
+        level_count: u32,
+        layer_count: u32,
+    ) -> vk::ImageSubresourceRange {
+        vk::ImageSubresourceRange {
+            aspect_mask: vk::ImageAspectFlags::COLOR,
+            base_mip_level,
+            level_count,
+            base_array_layer: 0,
+            layer_count,
+        }
+    }
+
+    #[test]
+    fn adjacent_layers_share_a_barrier() {
+        let mut state = ImageState::new(1, 3);
+        let transition =
+            state.transition(vk::Image::null(), &range(0, 1, 3), SubresourceState::transfer_dst());
+        assert_eq!(transition.barriers.len(), 1);
+        let barrier = &transition.barriers[0];
+        assert_eq!(barrier.old_layout, vk::ImageLayout::UNDEFINED);
+        assert_eq!(barrier.new_layout, vk::ImageLayout::TRANSFER_DST_OPTIMAL);
+        assert_eq!(barrier.subresource_range.layer_count, 3);
+        assert_eq!(transition.src_stage, vk::PipelineStageFlags::TOP_OF_PIPE);
+        assert_eq!(transition.dst_stage, vk::PipelineStageFlags::TRANSFER);
+    }
+
+    #[test]
+    fn mips_and_differing_layers_are_split() {
+        let mut state = ImageState::new(2, 3);
+        state.transition(vk::Image::null(), &range(0, 2, 3), SubresourceState::transfer_dst());
+        let middle = vk::ImageSubresourceRange {
+            base_array_layer: 1,
+            ..range(0, 1, 1)
+        };
+        state.set(&middle, SubresourceState::transfer_src());
+        let sampled = SubresourceState::sampled(
+            Format::R8G8B8A8_UNORM,
+            vk::PipelineStageFlags::FRAGMENT_SHADER,
+        );
+        let transition = state.transition(vk::Image::null(), &range(0, 2, 3), sampled);
+        let barriers: Vec<_> = transition
+            .barriers
+            .iter()
+            .map(|barrier| {
+                let range = barrier.subresource_range;
+                (range.base_mip_level, range.base_array_layer, range.layer_count)
+            })
+            .collect();
+        assert_eq!(barriers, vec![(0, 0, 1), (0, 1, 1), (0, 2, 1), (1, 0, 3)]);
+        assert_eq!(transition.barriers[1].old_layout, vk::ImageLayout::TRANSFER_SRC_OPTIMAL);
+        assert_eq!(transition.barriers[1].src_access_mask, vk::AccessFlags::empty());
+        assert_eq!(transition.barriers[3].src_access_mask, vk::AccessFlags::TRANSFER_WRITE);
+    }
+
+    #[test]
+    fn reads_in_the_same_layout_are_merged() {
+        let mut state = ImageState::new(1, 1);
+        let fragment = SubresourceState::sampled(
+            Format::R8G8B8A8_UNORM,
+            vk::PipelineStageFlags::FRAGMENT_SHADER,
+        );
+        let compute = SubresourceState::sampled(
+            Format::R8G8B8A8_UNORM,
+            vk::PipelineStageFlags::COMPUTE_SHADER,
+        );
+        state.transition(vk::Image::null(), &range(0, 1, 1), fragment);
+        let transition = state.transition(vk::Image::null(), &range(0, 1, 1), compute);
+        assert!(transition.barriers.is_empty());
+        assert_eq!(
+            state.subresources[0].stage,
+            vk::PipelineStageFlags::FRAGMENT_SHADER | vk::PipelineStageFlags::COMPUTE_SHADER
+        );
+        // A write has to wait for both reads
+        let mut transition =
+            state.transition(vk::Image::null(), &range(0, 1, 1), SubresourceState::transfer_dst());
+        assert_eq!(transition.barriers.len(), 1);
+        assert_eq!(
+            transition.src_stage,
+            vk::PipelineStageFlags::FRAGMENT_SHADER | vk::PipelineStageFlags::COMPUTE_SHADER
+        );
+        // Writes in the same layout still need a barrier
+        let write_after_write =
+            state.transition(vk::Image::null(), &range(0, 1, 1), SubresourceState::transfer_dst());
+        assert_eq!(write_after_write.barriers.len(), 1);
+        transition.merge(write_after_write);
+        assert_eq!(transition.barriers.len(), 2);
+    }
+}
@@ -12,6 +12,7 @@ use ash::{
 use parking_lot::{Mutex};
 use std::{
     cell::RefCell,
+    collections::{BTreeSet, HashMap},
     ffi::{CStr, CString},
     marker::PhantomData,
     ops::{Deref, Drop},
@@ -21,11 +22,12 @@ use std::{
 };
 use tephra::{
     buffer::BufferHandle,
-    context::{self, ContextApi},
+    context::{self, ContextApi, Limits, LimitsApi},
     descriptor::DescriptorHandle,
-    image::ImageHandle,
+    image::{ImageHandle, ImageView},
     pipeline::{ComputePipeline, GraphicsPipeline},
     renderpass::{Framebuffer, Renderpass},
+    sampler::{Sampler, SamplerDesc},
     shader::ShaderModule,
     HandleMap,
 };
@@ -35,8 +37,11 @@ pub mod buffer;
 pub mod commandbuffer;
 pub mod descriptor;
 pub mod image;
+pub mod layout;
+pub mod mips;
 pub mod pipeline;
 pub mod renderpass;
+pub mod sampler;
 pub mod shader;
 pub mod swapchain;
 
@@ -149,6 +154,104 @@ impl CommandPool {
     }
 }
 
+/// Keeps track of unfinished submissions and command lists that haven't been
+/// dropped, so that resources that might still be used by them are only destroyed
+/// once they are done
+#[derive(Default)]
+pub struct Submissions {
+    next: u64,
+    /// Submissions that haven't finished yet
+    pending: BTreeSet<u64>,
+    next_command_list: u64,
+    /// Command lists that can still be submitted
+    open_command_lists: BTreeSet<u64>,
+    /// Buffers with the first submission and command list that can't use them anymore
+    buffers: Vec<(u64, u64, BufferHandle)>,
+}
+
+impl Submissions {
+    fn begin_submission(&mut self) -> u64 {
+        let index = self.next;
+        self.next += 1;
+        self.pending.insert(index);
+        index
+    }
+
+    fn begin_command_list(&mut self) -> u64 {
+        let epoch = self.next_command_list;
+        self.next_command_list += 1;
+        self.open_command_lists.insert(epoch);
+        epoch
+    }
+
+    /// The buffer is destroyed once everything that exists right now is done
+    fn retire(&mut self, buffer: BufferHandle) {
+        self.buffers.push((self.next, self.next_command_list, buffer));
+    }
+
+    /// Every submission before `submission` has finished and every command list
+    /// before `command_list` has been dropped
+    fn done_before(&self, submission: u64, command_list: u64) -> bool {
+        let submitted = self.pending.first().is_none_or(|&first| first >= submission);
+        let dropped = self
+            .open_command_lists
+            .first()
+            .is_none_or(|&first| first >= command_list);
+        submitted && dropped
+    }
+
+    /// Removes the retired buffers that nothing can use anymore
+    fn take_unused(&mut self) -> Vec<BufferHandle> {
+        let buffers = std::mem::take(&mut self.buffers);
+        let (unused, used): (Vec<_>, _) = buffers
+            .into_iter()
+            .partition(|&(submission, command_list, _)| {
+                self.done_before(submission, command_list)
+            });
+        self.buffers = used;
+        unused.into_iter().map(|(_, _, buffer)| buffer).collect()
+    }
+}
+
+impl Context {
+    /// Registers a submission, has to be followed by `end_submission`
+    pub fn begin_submission(&self) -> u64 {
+        self.submissions.lock().begin_submission()
+    }
+
+    /// Marks the submission as finished and destroys the resources that are no
+    /// longer in use
+    pub fn end_submission(&self, index: u64) {
+        let unused = {
+            let mut submissions = self.submissions.lock();
+            submissions.pending.remove(&index);
+            submissions.take_unused()
+        };
+        self.destroy_buffers(unused);
+    }
+
+    /// Destroys `buffer` once every submission that was made so far has finished and
+    /// every command list that exists right now has been dropped. Until then the
+    /// handle stays valid for those command lists.
+    pub fn destroy_after_use(&self, buffer: BufferHandle) {
+        let unused = {
+            let mut submissions = self.submissions.lock();
+            submissions.retire(buffer);
+            submissions.take_unused()
+        };
+        self.destroy_buffers(unused);
+    }
+
+    fn destroy_buffers(&self, buffers: Vec<BufferHandle>) {
+        for buffer in buffers {
+            let data = self.buffers.remove(buffer).expect("Invalid buffer handle");
+            unsafe {
+                data.destroy(self);
+            }
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct Queue {
     pub inner: Mutex<vk::Queue>,
@@ -179,6 +282,7 @@ impl Queue {
                 .device
                 .create_fence(&fence_create_info, None)
                 .expect("Create fence failed 2.");
+            let submission = context.begin_submission();
             let queue = self.inner.lock();
             let submit_info = vk::SubmitInfo {
                 s_type: vk::StructureType::SUBMIT_INFO,
@@ -200,6 +304,8 @@ impl Queue {
                 .device
                 .wait_for_fences(&[submit_fence], true, u64::max_value())
                 .expect("Unable to wait");
+            drop(queue);
+            context.end_submission(submission);
             context.device.destroy_fence(submit_fence, None);
         }
     }
@@ -304,6 +410,7 @@ impl Deref for Context {
 
 pub struct InnerContext {
     pub images: HandleMap<ImageHandle, image::ImageData>,
+    pub image_views: HandleMap<ImageView, image::ImageViewData>,
     pub shader_modules: HandleMap<ShaderModule, shader::ShaderModuleData>,
     pub compute_pipelines: HandleMap<ComputePipeline, pipeline::ComputePipelineData>,
     pub graphic_pipelines: HandleMap<GraphicsPipeline, pipeline::GraphicsPipelineData>,
@@ -311,6 +418,11 @@ pub struct InnerContext {
     pub descriptors: HandleMap<DescriptorHandle, descriptor::DescriptorSet>,
     pub renderpasses: HandleMap<Renderpass, renderpass::RenderpassData>,
     pub framebuffers: HandleMap<Framebuffer, renderpass::FramebufferData>,
+    pub samplers: HandleMap<Sampler, sampler::SamplerData>,
+    pub sampler_cache: Mutex<HashMap<SamplerDesc, Sampler>>,
+    pub submissions: Mutex<Submissions>,
+    /// Created by the first mip generation that needs the compute path
+    pub downsampler: Mutex<Option<Arc<mips::Downsampler>>>,
     pub entry: Entry,
     pub instance: Instance,
     pub device: Device,
@@ -329,6 +441,12 @@ pub struct InnerContext {
     //pub events_loop: RefCell<winit::EventsLoop>,
     //pub debug_call_back: vk::DebugReportCallbackEXT,
     pub pdevice: vk::PhysicalDevice,
+    /// The features that were enabled on `device`
+    pub features: vk::PhysicalDeviceFeatures,
+    /// Whether `VK_KHR_sampler_mirror_clamp_to_edge` was enabled on `device`
+    pub mirror_clamp_to_edge: bool,
+    /// Whether `VK_KHR_maintenance1` was enabled on `device`
+    pub maintenance1: bool,
     pub device_memory_properties: vk::PhysicalDeviceMemoryProperties,
     pub queue_family_index: u32,
     pub present_queue: Queue,
@@ -353,6 +471,21 @@ pub struct InnerContext {
     pub pipeline_cache: vk::PipelineCache,
 }
 impl ContextApi for Context {}
+impl LimitsApi for Context {
+    fn limits(&self) -> Limits {
+        let limits = unsafe {
+            self.instance
+                .get_physical_device_properties(self.physical_device)
+                .limits
+        };
+        Limits {
+            min_uniform_buffer_offset_alignment: limits.min_uniform_buffer_offset_alignment,
+            non_coherent_atom_size: limits.non_coherent_atom_size,
+            framebuffer_color_sample_counts: limits.framebuffer_color_sample_counts.as_raw(),
+            framebuffer_depth_sample_counts: limits.framebuffer_depth_sample_counts.as_raw(),
+        }
+    }
+}
 impl Context {
     // pub fn render_loop<F: FnMut()>(&self, mut f: F) {
     //     use winit::*;
@@ -483,9 +616,36 @@ impl Context {
             //     instance.enumerate_device_extension_properties(pdevice)
             // );
             let queue_family_index = queue_family_index as u32;
-            let device_extension_names_raw = [Swapchain::name().as_ptr()];
+            let device_extensions = instance
+                .enumerate_device_extension_properties(pdevice)
+                .expect("Unable to query device extensions");
+            let supports = |name: &CStr| {
+                device_extensions
+                    .iter()
+                    .any(|extension| CStr::from_ptr(extension.extension_name.as_ptr()) == name)
+            };
+            let mirror_clamp_to_edge = supports(vk::KhrSamplerMirrorClampToEdgeFn::name());
+            let maintenance1 = supports(vk::KhrMaintenance1Fn::name());
+            let mut device_extension_names_raw = vec![Swapchain::name().as_ptr()];
+            if mirror_clamp_to_edge {
+                let name = vk::KhrSamplerMirrorClampToEdgeFn::name();
+                device_extension_names_raw.push(name.as_ptr());
+            }
+            if maintenance1 {
+                device_extension_names_raw.push(vk::KhrMaintenance1Fn::name().as_ptr());
+            }
+            let supported_features = instance.get_physical_device_features(pdevice);
             let features = vk::PhysicalDeviceFeatures {
                 shader_clip_distance: 1,
+                sampler_anisotropy: supported_features.sampler_anisotropy,
+                image_cube_array: supported_features.image_cube_array,
+                shader_storage_image_write_without_format: supported_features
+                    .shader_storage_image_write_without_format,
+                // Pipelines that need these fail to be created without them
+                fill_mode_non_solid: supported_features.fill_mode_non_solid,
+                wide_lines: supported_features.wide_lines,
+                depth_clamp: supported_features.depth_clamp,
+                depth_bounds: supported_features.depth_bounds,
                 ..Default::default()
             };
             let priorities = [1.0];
@@ -746,7 +906,12 @@ impl Context {
                 renderpasses: HandleMap::new(),
                 buffers: HandleMap::new(),
                 images: HandleMap::new(),
+                image_views: HandleMap::new(),
                 descriptors: HandleMap::new(),
+                samplers: HandleMap::new(),
+                sampler_cache: Mutex::new(HashMap::new()),
+                submissions: Mutex::new(Submissions::default()),
+                downsampler: Mutex::new(None),
                 command_pool: ThreadLocalCommandPool::new(queue_family_index),
                 entry,
                 physical_device: pdevice,
@@ -756,6 +921,9 @@ impl Context {
                 device: device,
                 queue_family_index: queue_family_index,
                 pdevice: pdevice,
+                features,
+                mirror_clamp_to_edge,
+                maintenance1,
                 device_memory_properties: device_memory_properties,
                 //window: window,
                 surface_loader: surface_loader,
@@ -793,6 +961,19 @@ impl Context {
 
 impl Drop for InnerContext {
     fn drop(&mut self) {
+        if let Some(downsampler) = self.downsampler.lock().take() {
+            unsafe {
+                downsampler.destroy(&self.device);
+            }
+        }
+        // Every sampler is deduplicated through the cache
+        for (_, sampler) in self.sampler_cache.lock().drain() {
+            if let Some(data) = self.samplers.remove(sampler) {
+                unsafe {
+                    self.device.destroy_sampler(data.sampler, None);
+                }
+            }
+        }
         // unsafe {
         //     // self.device.destroy_device(None);
         //     // self.instance.destroy_instance(None);
@@ -951,3 +1132,33 @@ unsafe extern "system" fn debug_utils_callback(
     }
     0
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn retired_buffers_outlive_recorded_command_lists() {
+        let handles: HandleMap<BufferHandle, ()> = HandleMap::new();
+        let (old, newer) = (handles.insert(()), handles.insert(()));
+        let mut submissions = Submissions::default();
+        // A command list records with the old buffer, then the buffer is reallocated
+        let recorded = submissions.begin_command_list();
+        submissions.retire(old);
+        assert!(submissions.take_unused().is_empty());
+        // Command lists created after the reallocation don't keep it alive
+        let later = submissions.begin_command_list();
+        let submission = submissions.begin_submission();
+        submissions.pending.remove(&submission);
+        assert!(submissions.take_unused().is_empty());
+        submissions.open_command_lists.remove(&recorded);
+        assert_eq!(submissions.take_unused(), vec![old]);
+        // Buffers retired while a submission is in flight wait for it
+        let submission = submissions.begin_submission();
+        submissions.retire(newer);
+        submissions.open_command_lists.remove(&later);
+        assert!(submissions.take_unused().is_empty());
+        submissions.pending.remove(&submission);
+        assert_eq!(submissions.take_unused(), vec![newer]);
+    }
+}
@@ -0,0 +1,525 @@
+use super::image::{from_format, full_subresource_range, get_aspect_mask, ImageData};
+use super::layout::SubresourceState;
+use super::Context;
+use ash::version::{DeviceV1_0, InstanceV1_0};
+use ash::vk;
+use ash::Device;
+use parking_lot::Mutex;
+use std::ffi::CString;
+use std::io::Cursor;
+use std::sync::Arc;
+use tephra::{
+    commandbuffer::Access,
+    image::{ImageDesc, ImageDimension, ImageError},
+};
+
+/// Compiled from `shader/downsample.comp`
+const DOWNSAMPLE_SPV: &[u8] = include_bytes!("../shader/downsample.comp.spv");
+const LOCAL_SIZE: u32 = 8;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum MipStrategy {
+    Blit(vk::Filter),
+    Compute,
+}
+
+/// Picks how the mips of an image with `format` are generated. Linear blits are
+/// preferred, formats that can't be filtered go through the downsample shader.
+/// Integer formats, formats without storage support and devices that can't write
+/// storage images without a format fall back to a nearest blit.
+pub fn mip_strategy(
+    ctx: &Context,
+    format: vk::Format,
+    dimension: ImageDimension,
+) -> Option<MipStrategy> {
+    let features = unsafe {
+        ctx.instance
+            .get_physical_device_format_properties(ctx.pdevice, format)
+            .optimal_tiling_features
+    };
+    let blit = vk::FormatFeatureFlags::BLIT_SRC | vk::FormatFeatureFlags::BLIT_DST;
+    if features.contains(blit | vk::FormatFeatureFlags::SAMPLED_IMAGE_FILTER_LINEAR) {
+        return Some(MipStrategy::Blit(vk::Filter::LINEAR));
+    }
+    let compute = vk::FormatFeatureFlags::SAMPLED_IMAGE | vk::FormatFeatureFlags::STORAGE_IMAGE;
+    let layered_2d = dimension == ImageDimension::D2 || dimension == ImageDimension::Cube;
+    // The downsample shader writes its storage image without a format qualifier
+    let write_without_format = ctx.features.shader_storage_image_write_without_format != 0;
+    if features.contains(compute) && layered_2d && write_without_format && !is_integer(format) {
+        return Some(MipStrategy::Compute);
+    }
+    if features.contains(blit) {
+        return Some(MipStrategy::Blit(vk::Filter::NEAREST));
+    }
+    None
+}
+
+fn is_integer(format: vk::Format) -> bool {
+    matches!(
+        format,
+        vk::Format::R8_UINT
+            | vk::Format::R8_SINT
+            | vk::Format::R8G8_UINT
+            | vk::Format::R8G8_SINT
+            | vk::Format::R8G8B8_UINT
+            | vk::Format::R8G8B8_SINT
+            | vk::Format::B8G8R8_UINT
+            | vk::Format::B8G8R8_SINT
+            | vk::Format::R8G8B8A8_UINT
+            | vk::Format::R8G8B8A8_SINT
+            | vk::Format::B8G8R8A8_UINT
+            | vk::Format::B8G8R8A8_SINT
+            | vk::Format::A8B8G8R8_UINT_PACK32
+            | vk::Format::A8B8G8R8_SINT_PACK32
+            | vk::Format::A2R10G10B10_UINT_PACK32
+            | vk::Format::A2R10G10B10_SINT_PACK32
+            | vk::Format::A2B10G10R10_UINT_PACK32
+            | vk::Format::A2B10G10R10_SINT_PACK32
+            | vk::Format::R16_UINT
+            | vk::Format::R16_SINT
+            | vk::Format::R16G16_UINT
+            | vk::Format::R16G16_SINT
+            | vk::Format::R16G16B16_UINT
+            | vk::Format::R16G16B16_SINT
+            | vk::Format::R16G16B16A16_UINT
+            | vk::Format::R16G16B16A16_SINT
+            | vk::Format::R32_UINT
+            | vk::Format::R32_SINT
+            | vk::Format::R32G32_UINT
+            | vk::Format::R32G32_SINT
+            | vk::Format::R32G32B32_UINT
+            | vk::Format::R32G32B32_SINT
+            | vk::Format::R32G32B32A32_UINT
+            | vk::Format::R32G32B32A32_SINT
+            | vk::Format::R64_UINT
+            | vk::Format::R64_SINT
+            | vk::Format::R64G64_UINT
+            | vk::Format::R64G64_SINT
+            | vk::Format::R64G64B64_UINT
+            | vk::Format::R64G64B64_SINT
+            | vk::Format::R64G64B64A64_UINT
+            | vk::Format::R64G64B64A64_SINT
+            | vk::Format::S8_UINT
+    )
+}
+
+fn mip_extent(image: &ImageData, level: u32) -> vk::Offset3D {
+    let desc = &image.desc;
+    let (height, depth) = match desc.dimension {
+        ImageDimension::D1 => (1, 1),
+        ImageDimension::D2 | ImageDimension::Cube => (desc.resolution.height, 1),
+        ImageDimension::D3 => (desc.resolution.height, desc.depth),
+    };
+    vk::Offset3D {
+        x: (desc.resolution.width >> level).max(1) as i32,
+        y: (height >> level).max(1) as i32,
+        z: (depth >> level).max(1) as i32,
+    }
+}
+
+fn barrier(
+    image: &ImageData,
+    levels: std::ops::Range<u32>,
+    src_access_mask: vk::AccessFlags,
+    dst_access_mask: vk::AccessFlags,
+    old_layout: vk::ImageLayout,
+    new_layout: vk::ImageLayout,
+) -> vk::ImageMemoryBarrier {
+    vk::ImageMemoryBarrier {
+        src_access_mask,
+        dst_access_mask,
+        old_layout,
+        new_layout,
+        src_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
+        dst_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
+        image: image.image,
+        subresource_range: vk::ImageSubresourceRange {
+            aspect_mask: get_aspect_mask(&image.desc),
+            base_mip_level: levels.start,
+            level_count: levels.end - levels.start,
+            base_array_layer: 0,
+            layer_count: image.desc.layers,
+        },
+        ..Default::default()
+    }
+}
+
+/// The pipeline of the compute path. It is created on first use and shared by every
+/// mip generation of a context.
+pub struct Downsampler {
+    shader_module: vk::ShaderModule,
+    set_layout: vk::DescriptorSetLayout,
+    pipeline_layout: vk::PipelineLayout,
+    pipeline: vk::Pipeline,
+    /// Descriptor sets are freed again once their mips have been generated
+    pools: Mutex<Vec<vk::DescriptorPool>>,
+}
+
+impl Downsampler {
+    /// # Safety
+    ///
+    /// No command buffer that uses the downsampler may still be executing.
+    pub unsafe fn destroy(&self, device: &Device) {
+        for &pool in self.pools.lock().iter() {
+            device.destroy_descriptor_pool(pool, None);
+        }
+        device.destroy_pipeline(self.pipeline, None);
+        device.destroy_pipeline_layout(self.pipeline_layout, None);
+        device.destroy_descriptor_set_layout(self.set_layout, None);
+        device.destroy_shader_module(self.shader_module, None);
+    }
+
+    unsafe fn allocate_set(&self, ctx: &Context) -> (vk::DescriptorPool, vk::DescriptorSet) {
+        let set_layouts = [self.set_layout];
+        let mut pools = self.pools.lock();
+        for &pool in pools.iter().rev() {
+            let alloc_info = vk::DescriptorSetAllocateInfo::builder()
+                .descriptor_pool(pool)
+                .set_layouts(&set_layouts);
+            if let Ok(sets) = ctx.device.allocate_descriptor_sets(&alloc_info) {
+                return (pool, sets[0]);
+            }
+        }
+        let pool = create_descriptor_pool(ctx);
+        pools.push(pool);
+        let alloc_info = vk::DescriptorSetAllocateInfo::builder()
+            .descriptor_pool(pool)
+            .set_layouts(&set_layouts);
+        let set = ctx
+            .device
+            .allocate_descriptor_sets(&alloc_info)
+            .expect("Unable to allocate a downsample descriptor set")[0];
+        (pool, set)
+    }
+}
+
+/// Resources of a single mip generation that have to outlive the command buffer
+pub struct Downsample {
+    sets: Vec<(vk::DescriptorPool, vk::DescriptorSet)>,
+    views: Vec<vk::ImageView>,
+}
+
+impl Downsample {
+    /// # Safety
+    ///
+    /// The command buffer that generated the mips has to have finished.
+    pub unsafe fn destroy(self, ctx: &Context) {
+        for view in self.views {
+            ctx.device.destroy_image_view(view, None);
+        }
+        let downsampler = downsampler(ctx);
+        let _pools = downsampler.pools.lock();
+        for (pool, set) in self.sets {
+            ctx.device.free_descriptor_sets(pool, &[set]);
+        }
+    }
+}
+
+/// Fails if the mips of `desc` can neither be blitted nor computed
+pub fn validate_mip_generation(ctx: &Context, desc: &ImageDesc) -> Result<(), ImageError> {
+    if desc.mip_levels > 1 && mip_strategy(ctx, from_format(desc.format), desc.dimension).is_none()
+    {
+        return Err(ImageError::UnsupportedMipGeneration(desc.format));
+    }
+    Ok(())
+}
+
+/// Records the commands that fill mip levels `1..mip_levels` from level 0. The image
+/// is transitioned from its tracked state and the state it is left in is recorded.
+/// The returned resources have to be destroyed once the command buffer has finished.
+///
+/// # Safety
+///
+/// The format has to be checked with [`validate_mip_generation`] first.
+pub unsafe fn record_generate_mips(
+    ctx: &Context,
+    command_buffer: vk::CommandBuffer,
+    image: &ImageData,
+) -> Option<Downsample> {
+    if image.desc.mip_levels <= 1 {
+        return None;
+    }
+    let format = from_format(image.desc.format);
+    let strategy = mip_strategy(ctx, format, image.desc.dimension)
+        .expect("Checked by validate_mip_generation");
+    match strategy {
+        MipStrategy::Blit(filter) => {
+            record_blit(ctx, command_buffer, image, filter);
+            None
+        }
+        MipStrategy::Compute => Some(record_compute(ctx, command_buffer, image)),
+    }
+}
+
+unsafe fn record_blit(
+    ctx: &Context,
+    command_buffer: vk::CommandBuffer,
+    image: &ImageData,
+    filter: vk::Filter,
+) {
+    let levels = image.desc.mip_levels;
+    let aspect_mask = get_aspect_mask(&image.desc);
+    let range = full_subresource_range(&image.desc);
+    image
+        .transition(&range, SubresourceState::transfer_dst())
+        .record(ctx, command_buffer);
+    for level in 1..levels {
+        ctx.device.cmd_pipeline_barrier(
+            command_buffer,
+            vk::PipelineStageFlags::TRANSFER,
+            vk::PipelineStageFlags::TRANSFER,
+            vk::DependencyFlags::empty(),
+            &[],
+            &[],
+            &[barrier(
+                image,
+                level - 1..level,
+                vk::AccessFlags::TRANSFER_WRITE,
+                vk::AccessFlags::TRANSFER_READ,
+                vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+            )],
+        );
+        let subresource = |mip_level| {
+            vk::ImageSubresourceLayers {
+                aspect_mask,
+                mip_level,
+                base_array_layer: 0,
+                layer_count: image.desc.layers,
+            }
+        };
+        let blit = vk::ImageBlit {
+            src_subresource: subresource(level - 1),
+            src_offsets: [vk::Offset3D::default(), mip_extent(image, level - 1)],
+            dst_subresource: subresource(level),
+            dst_offsets: [vk::Offset3D::default(), mip_extent(image, level)],
+        };
+        ctx.device.cmd_blit_image(
+            command_buffer,
+            image.image,
+            vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+            image.image,
+            vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+            &[blit],
+            filter,
+        );
+    }
+    // Every level but the last one was read by the following blit
+    let mut state = image.state.lock();
+    state.set(
+        &vk::ImageSubresourceRange {
+            level_count: levels - 1,
+            ..range
+        },
+        SubresourceState::transfer_src(),
+    );
+    state.set(
+        &vk::ImageSubresourceRange {
+            base_mip_level: levels - 1,
+            level_count: 1,
+            ..range
+        },
+        SubresourceState::transfer_dst(),
+    );
+}
+
+/// Number of descriptor sets per pool of the compute path
+const SETS_PER_POOL: u32 = 64;
+
+unsafe fn create_descriptor_pool(ctx: &Context) -> vk::DescriptorPool {
+    let pool_sizes = [
+        vk::DescriptorPoolSize {
+            ty: vk::DescriptorType::SAMPLED_IMAGE,
+            descriptor_count: SETS_PER_POOL,
+        },
+        vk::DescriptorPoolSize {
+            ty: vk::DescriptorType::STORAGE_IMAGE,
+            descriptor_count: SETS_PER_POOL,
+        },
+    ];
+    let pool_info = vk::DescriptorPoolCreateInfo::builder()
+        .flags(vk::DescriptorPoolCreateFlags::FREE_DESCRIPTOR_SET)
+        .pool_sizes(&pool_sizes)
+        .max_sets(SETS_PER_POOL);
+    ctx.device
+        .create_descriptor_pool(&pool_info, None)
+        .unwrap()
+}
+
+/// The shared downsampler of `ctx`, created on first use
+fn downsampler(ctx: &Context) -> Arc<Downsampler> {
+    ctx.downsampler
+        .lock()
+        .get_or_insert_with(|| Arc::new(unsafe { create_downsampler(ctx) }))
+        .clone()
+}
+
+unsafe fn create_downsampler(ctx: &Context) -> Downsampler {
+    let code = ash::util::read_spv(&mut Cursor::new(DOWNSAMPLE_SPV)).expect("downsample spirv");
+    let shader_info = vk::ShaderModuleCreateInfo::builder().code(&code);
+    let shader_module = ctx
+        .device
+        .create_shader_module(&shader_info, None)
+        .expect("downsample shader");
+    let bindings = [
+        vk::DescriptorSetLayoutBinding {
+            binding: 0,
+            descriptor_type: vk::DescriptorType::SAMPLED_IMAGE,
+            descriptor_count: 1,
+            stage_flags: vk::ShaderStageFlags::COMPUTE,
+            p_immutable_samplers: std::ptr::null(),
+        },
+        vk::DescriptorSetLayoutBinding {
+            binding: 1,
+            descriptor_type: vk::DescriptorType::STORAGE_IMAGE,
+            descriptor_count: 1,
+            stage_flags: vk::ShaderStageFlags::COMPUTE,
+            p_immutable_samplers: std::ptr::null(),
+        },
+    ];
+    let set_layout_info = vk::DescriptorSetLayoutCreateInfo::builder().bindings(&bindings);
+    let set_layout = ctx
+        .device
+        .create_descriptor_set_layout(&set_layout_info, None)
+        .unwrap();
+    let pipeline_layout = super::pipeline::create_pipeline_layout(ctx, &[set_layout]);
+    let entry_name = CString::new("main").unwrap();
+    let create_info = vk::ComputePipelineCreateInfo {
+        layout: pipeline_layout,
+        stage: vk::PipelineShaderStageCreateInfo {
+            stage: vk::ShaderStageFlags::COMPUTE,
+            module: shader_module,
+            p_name: entry_name.as_ptr(),
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    let pipeline = ctx
+        .device
+        .create_compute_pipelines(ctx.pipeline_cache, &[create_info], None)
+        .expect("downsample pipeline")[0];
+    Downsampler {
+        shader_module,
+        set_layout,
+        pipeline_layout,
+        pipeline,
+        pools: Mutex::new(Vec::new()),
+    }
+}
+
+unsafe fn create_level_view(
+    ctx: &Context,
+    image: &ImageData,
+    level: u32,
+    layer: u32,
+) -> vk::ImageView {
+    let info = vk::ImageViewCreateInfo {
+        view_type: vk::ImageViewType::TYPE_2D,
+        format: from_format(image.desc.format),
+        components: vk::ComponentMapping {
+            r: vk::ComponentSwizzle::IDENTITY,
+            g: vk::ComponentSwizzle::IDENTITY,
+            b: vk::ComponentSwizzle::IDENTITY,
+            a: vk::ComponentSwizzle::IDENTITY,
+        },
+        subresource_range: vk::ImageSubresourceRange {
+            aspect_mask: get_aspect_mask(&image.desc),
+            base_mip_level: level,
+            level_count: 1,
+            base_array_layer: layer,
+            layer_count: 1,
+        },
+        image: image.image,
+        ..Default::default()
+    };
+    ctx.device.create_image_view(&info, None).unwrap()
+}
+
+unsafe fn record_compute(
+    ctx: &Context,
+    command_buffer: vk::CommandBuffer,
+    image: &ImageData,
+) -> Downsample {
+    let levels = image.desc.mip_levels;
+    let layers = image.desc.layers;
+    let downsampler = downsampler(ctx);
+    let mut downsample = Downsample {
+        sets: Vec::new(),
+        views: Vec::new(),
+    };
+    // Both the sampled and the storage view need the general layout, because a
+    // level is written in one pass and read in the next.
+    let range = full_subresource_range(&image.desc);
+    let storage = SubresourceState::storage(Access::Write, vk::PipelineStageFlags::COMPUTE_SHADER);
+    image.transition(&range, storage).record(ctx, command_buffer);
+    ctx.device.cmd_bind_pipeline(
+        command_buffer,
+        vk::PipelineBindPoint::COMPUTE,
+        downsampler.pipeline,
+    );
+    for level in 1..levels {
+        for layer in 0..layers {
+            let src_view = create_level_view(ctx, image, level - 1, layer);
+            let dst_view = create_level_view(ctx, image, level, layer);
+            downsample.views.push(src_view);
+            downsample.views.push(dst_view);
+            let (pool, set) = downsampler.allocate_set(ctx);
+            downsample.sets.push((pool, set));
+            let src_info = [vk::DescriptorImageInfo {
+                sampler: vk::Sampler::null(),
+                image_view: src_view,
+                image_layout: vk::ImageLayout::GENERAL,
+            }];
+            let dst_info = [vk::DescriptorImageInfo {
+                sampler: vk::Sampler::null(),
+                image_view: dst_view,
+                image_layout: vk::ImageLayout::GENERAL,
+            }];
+            let writes = [
+                vk::WriteDescriptorSet::builder()
+                    .dst_set(set)
+                    .dst_binding(0)
+                    .descriptor_type(vk::DescriptorType::SAMPLED_IMAGE)
+                    .image_info(&src_info)
+                    .build(),
+                vk::WriteDescriptorSet::builder()
+                    .dst_set(set)
+                    .dst_binding(1)
+                    .descriptor_type(vk::DescriptorType::STORAGE_IMAGE)
+                    .image_info(&dst_info)
+                    .build(),
+            ];
+            ctx.device.update_descriptor_sets(&writes, &[]);
+            ctx.device.cmd_bind_descriptor_sets(
+                command_buffer,
+                vk::PipelineBindPoint::COMPUTE,
+                downsampler.pipeline_layout,
+                0,
+                &[set],
+                &[],
+            );
+            let extent = mip_extent(image, level);
+            ctx.device.cmd_dispatch(
+                command_buffer,
+                (extent.x as u32).div_ceil(LOCAL_SIZE),
+                (extent.y as u32).div_ceil(LOCAL_SIZE),
+                1,
+            );
+        }
+        let memory_barrier = vk::MemoryBarrier {
+            src_access_mask: vk::AccessFlags::SHADER_WRITE,
+            dst_access_mask: vk::AccessFlags::SHADER_READ,
+            ..Default::default()
+        };
+        ctx.device.cmd_pipeline_barrier(
+            command_buffer,
+            vk::PipelineStageFlags::COMPUTE_SHADER,
+            vk::PipelineStageFlags::COMPUTE_SHADER,
+            vk::DependencyFlags::empty(),
+            &[memory_barrier],
+            &[],
+            &[],
+        );
+    }
+    downsample
+}
@@ -1,10 +1,16 @@
+use super::descriptor::{descriptor_stages, descriptor_type};
+use super::image::sample_count;
+use super::renderpass::Compatibility;
 use super::Context;
 use ash::{version::DeviceV1_0, vk};
 use std::{ffi::CString, ptr};
 use tephra::{
     descriptor::{Binding, DescriptorType},
     pipeline::{
-        ComputePipeline, ComputePipelineState, GraphicsPipeline, GraphicsPipelineState, PipelineApi,
+        Blend, BlendFactor, BlendOp, ColorBlendAttachment, CompareOp, ComputePipeline,
+        ComputePipelineState, CullMode, DynamicState, FixedFunctionState, FrontFace,
+        GraphicsPipeline, GraphicsPipelineState, PipelineApi, PipelineError, PolygonMode,
+        PrimitiveTopology, StencilFaceState, StencilOp,
     },
     renderpass::{VertexInputData, VertexType},
 };
@@ -16,15 +22,11 @@ unsafe fn create_layout(
     let layout_bindings: Vec<_> = data
         .iter()
         .map(|desc| {
-            let ty = match desc.data {
-                DescriptorType::Uniform => vk::DescriptorType::UNIFORM_BUFFER,
-                DescriptorType::Storage => vk::DescriptorType::STORAGE_BUFFER,
-            };
             vk::DescriptorSetLayoutBinding {
-                binding: 0,
-                descriptor_type: ty,
+                binding: desc.binding,
+                descriptor_type: descriptor_type(desc.data),
                 descriptor_count: 1,
-                stage_flags: vk::ShaderStageFlags::ALL,
+                stage_flags: descriptor_stages(desc.data),
                 p_immutable_samplers: std::ptr::null(),
             }
         })
@@ -84,7 +86,27 @@ impl PipelineApi for Context {
         };
         self.compute_pipelines.insert(data)
     }
-    unsafe fn create_graphics_pipeline(&self, state: &GraphicsPipelineState) -> GraphicsPipeline {
+    unsafe fn create_graphics_pipeline(
+        &self,
+        state: &GraphicsPipelineState,
+    ) -> Result<GraphicsPipeline, PipelineError> {
+        let render_target_data = self.renderpasses.get(state.render_target);
+        let subpass_count = render_target_data.subpasses.len();
+        if state.subpass as usize >= subpass_count {
+            return Err(PipelineError::UnknownSubpass {
+                subpass: state.subpass,
+                count: subpass_count,
+            });
+        }
+        if let Some(samples) = render_target_data.subpass_samples(state.subpass as usize) {
+            if samples != state.samples {
+                return Err(PipelineError::SampleCountMismatch {
+                    pipeline: state.samples,
+                    attachments: samples,
+                });
+            }
+        }
+        check_features(self, &state.fixed_function)?;
         let vertex_shader = &state.vertex_shader;
         let vk_vertex = self.shader_modules.get(state.vertex_shader.shader_module);
         let vk_fragment = self.shader_modules.get(state.fragment_shader.shader_module);
@@ -126,26 +148,18 @@ impl PipelineApi for Context {
             vertex_binding_description_count: vertex_input_binding_descriptions.len() as u32,
             p_vertex_binding_descriptions: vertex_input_binding_descriptions.as_ptr(),
         };
+        let fixed_function = &state.fixed_function;
+        let input_assembly = fixed_function.input_assembly;
         let vertex_input_assembly_state_info = vk::PipelineInputAssemblyStateCreateInfo {
             s_type: vk::StructureType::PIPELINE_INPUT_ASSEMBLY_STATE_CREATE_INFO,
             flags: Default::default(),
             p_next: ptr::null(),
-            primitive_restart_enable: 0,
-            topology: vk::PrimitiveTopology::TRIANGLE_LIST,
+            primitive_restart_enable: input_assembly.primitive_restart as u32,
+            topology: primitive_topology(input_assembly.topology),
         };
-        // TODO: Custom resolution
-        let viewports = [vk::Viewport {
-            x: 0.0,
-            y: 0.0,
-            width: self.surface_resolution.width as f32,
-            height: self.surface_resolution.height as f32,
-            min_depth: 0.0,
-            max_depth: 1.0,
-        }];
-        let scissors = [vk::Rect2D {
-            offset: vk::Offset2D { x: 0, y: 0 },
-            extent: self.surface_resolution.clone(),
-        }];
+        // Only the counts matter, viewports and scissors are always dynamic
+        let viewports = [vk::Viewport::default()];
+        let scissors = [vk::Rect2D::default()];
         let viewport_state_info = vk::PipelineViewportStateCreateInfo {
             s_type: vk::StructureType::PIPELINE_VIEWPORT_STATE_CREATE_INFO,
             p_next: ptr::null(),
@@ -155,65 +169,66 @@ impl PipelineApi for Context {
             viewport_count: viewports.len() as u32,
             p_viewports: viewports.as_ptr(),
         };
+        let rasterizer = fixed_function.rasterizer;
+        let depth_bias = rasterizer.depth_bias.unwrap_or_default();
         let rasterization_info = vk::PipelineRasterizationStateCreateInfo {
             s_type: vk::StructureType::PIPELINE_RASTERIZATION_STATE_CREATE_INFO,
             p_next: ptr::null(),
             flags: Default::default(),
-            cull_mode: vk::CullModeFlags::NONE,
-            depth_bias_clamp: 0.0,
-            depth_bias_constant_factor: 0.0,
-            depth_bias_enable: 0,
-            depth_bias_slope_factor: 0.0,
-            depth_clamp_enable: 0,
-            front_face: vk::FrontFace::COUNTER_CLOCKWISE,
-            line_width: 1.0,
-            polygon_mode: vk::PolygonMode::FILL,
+            cull_mode: cull_mode(rasterizer.cull_mode),
+            depth_bias_clamp: depth_bias.clamp,
+            depth_bias_constant_factor: depth_bias.constant_factor,
+            depth_bias_enable: rasterizer.depth_bias.is_some() as u32,
+            depth_bias_slope_factor: depth_bias.slope_factor,
+            depth_clamp_enable: rasterizer.depth_clamp as u32,
+            front_face: front_face(rasterizer.front_face),
+            line_width: rasterizer.line_width,
+            polygon_mode: polygon_mode(rasterizer.polygon_mode),
             rasterizer_discard_enable: 0,
         };
         let multisample_state_info = vk::PipelineMultisampleStateCreateInfo {
             s_type: vk::StructureType::PIPELINE_MULTISAMPLE_STATE_CREATE_INFO,
             flags: Default::default(),
             p_next: ptr::null(),
-            rasterization_samples: vk::SampleCountFlags::TYPE_1,
+            rasterization_samples: sample_count(state.samples),
             sample_shading_enable: 0,
             min_sample_shading: 0.0,
             p_sample_mask: ptr::null(),
             alpha_to_one_enable: 0,
             alpha_to_coverage_enable: 0,
         };
-        let noop_stencil_state = vk::StencilOpState {
-            fail_op: vk::StencilOp::KEEP,
-            pass_op: vk::StencilOp::KEEP,
-            depth_fail_op: vk::StencilOp::KEEP,
-            compare_op: vk::CompareOp::ALWAYS,
-            compare_mask: 0,
-            write_mask: 0,
-            reference: 0,
-        };
+        let depth_stencil = fixed_function.depth_stencil;
+        let (min_depth_bounds, max_depth_bounds) = depth_stencil.depth_bounds.unwrap_or((0.0, 1.0));
         let depth_state_info = vk::PipelineDepthStencilStateCreateInfo {
             s_type: vk::StructureType::PIPELINE_DEPTH_STENCIL_STATE_CREATE_INFO,
             p_next: ptr::null(),
             flags: Default::default(),
-            depth_test_enable: 1,
-            depth_write_enable: 1,
-            depth_compare_op: vk::CompareOp::LESS_OR_EQUAL,
-            depth_bounds_test_enable: 0,
-            stencil_test_enable: 0,
-            front: noop_stencil_state.clone(),
-            back: noop_stencil_state.clone(),
-            max_depth_bounds: 1.0,
-            min_depth_bounds: 0.0,
+            depth_test_enable: depth_stencil.depth_test as u32,
+            depth_write_enable: depth_stencil.depth_write as u32,
+            depth_compare_op: compare_op(depth_stencil.depth_compare),
+            depth_bounds_test_enable: depth_stencil.depth_bounds.is_some() as u32,
+            stencil_test_enable: depth_stencil.stencil_test as u32,
+            front: stencil_face(depth_stencil.front),
+            back: stencil_face(depth_stencil.back),
+            max_depth_bounds,
+            min_depth_bounds,
         };
-        let color_blend_attachment_states = [vk::PipelineColorBlendAttachmentState {
-            blend_enable: 0,
-            src_color_blend_factor: vk::BlendFactor::ZERO,
-            dst_color_blend_factor: vk::BlendFactor::ZERO,
-            color_blend_op: vk::BlendOp::ADD,
-            src_alpha_blend_factor: vk::BlendFactor::ZERO,
-            dst_alpha_blend_factor: vk::BlendFactor::ZERO,
-            alpha_blend_op: vk::BlendOp::ADD,
-            color_write_mask: vk::ColorComponentFlags::all(),
-        }];
+        let color_attachment_count = render_target_data.subpasses[state.subpass as usize]
+            .color_attachments
+            .len();
+        let blend_attachments = &fixed_function.color_blend.attachments;
+        if blend_attachments.len() > color_attachment_count {
+            return Err(PipelineError::BlendStateCountMismatch {
+                color_attachments: color_attachment_count,
+                blend_states: blend_attachments.len(),
+            });
+        }
+        // Every color attachment of the subpass needs a blend state
+        let color_blend_attachment_states: Vec<_> = (0..color_attachment_count)
+            .map(|index| {
+                color_blend_attachment(blend_attachments.get(index).cloned().unwrap_or_default())
+            })
+            .collect();
         let color_blend_state = vk::PipelineColorBlendStateCreateInfo {
             s_type: vk::StructureType::PIPELINE_COLOR_BLEND_STATE_CREATE_INFO,
             p_next: ptr::null(),
@@ -222,9 +237,15 @@ impl PipelineApi for Context {
             logic_op: vk::LogicOp::CLEAR,
             attachment_count: color_blend_attachment_states.len() as u32,
             p_attachments: color_blend_attachment_states.as_ptr(),
-            blend_constants: [0.0, 0.0, 0.0, 0.0],
+            blend_constants: fixed_function.color_blend.constants,
         };
-        let dynamic_state = [vk::DynamicState::VIEWPORT, vk::DynamicState::SCISSOR];
+        let mut dynamic_state = vec![vk::DynamicState::VIEWPORT, vk::DynamicState::SCISSOR];
+        for &state in &fixed_function.dynamic_states {
+            let state = dynamic_state_flag(state);
+            if !dynamic_state.contains(&state) {
+                dynamic_state.push(state);
+            }
+        }
         let dynamic_state_info = vk::PipelineDynamicStateCreateInfo {
             s_type: vk::StructureType::PIPELINE_DYNAMIC_STATE_CREATE_INFO,
             p_next: ptr::null(),
@@ -234,7 +255,6 @@ impl PipelineApi for Context {
         };
         let descriptor_layouts = create_layout(self, &state.layout);
         let pipeline_layout = create_pipeline_layout(self, &descriptor_layouts);
-        let render_target_data = self.renderpasses.get(state.render_target);
         let graphic_pipeline_info = vk::GraphicsPipelineCreateInfo {
             s_type: vk::StructureType::GRAPHICS_PIPELINE_CREATE_INFO,
             p_next: ptr::null(),
@@ -252,7 +272,7 @@ impl PipelineApi for Context {
             p_dynamic_state: &dynamic_state_info,
             layout: pipeline_layout,
             render_pass: render_target_data.render_pass,
-            subpass: 0,
+            subpass: state.subpass,
             base_pipeline_handle: vk::Pipeline::null(),
             base_pipeline_index: 0,
         };
@@ -264,10 +284,172 @@ impl PipelineApi for Context {
             pipeline: graphics_pipelines[0],
             layout: pipeline_layout,
             descriptor_layouts,
+            compatibility: render_target_data.compatibility.clone(),
+            subpass: state.subpass,
+            dynamic_states: fixed_function.dynamic_states.clone(),
         };
-        self.graphic_pipelines.insert(data)
+        Ok(self.graphic_pipelines.insert(data))
     }
 }
+
+/// Fails if `fixed_function` uses a state that needs a feature the device lacks
+fn check_features(ctx: &Context, fixed_function: &FixedFunctionState) -> Result<(), PipelineError> {
+    let features = &ctx.features;
+    let rasterizer = fixed_function.rasterizer;
+    let required = [
+        (
+            rasterizer.polygon_mode != PolygonMode::Fill,
+            features.fill_mode_non_solid,
+            "fillModeNonSolid",
+        ),
+        (rasterizer.line_width != 1.0, features.wide_lines, "wideLines"),
+        (rasterizer.depth_clamp, features.depth_clamp, "depthClamp"),
+        (
+            fixed_function.depth_stencil.depth_bounds.is_some(),
+            features.depth_bounds,
+            "depthBounds",
+        ),
+    ];
+    for &(used, supported, feature) in &required {
+        if used && supported == vk::FALSE {
+            return Err(PipelineError::UnsupportedFeature(feature));
+        }
+    }
+    Ok(())
+}
+fn primitive_topology(topology: PrimitiveTopology) -> vk::PrimitiveTopology {
+    match topology {
+        PrimitiveTopology::PointList => vk::PrimitiveTopology::POINT_LIST,
+        PrimitiveTopology::LineList => vk::PrimitiveTopology::LINE_LIST,
+        PrimitiveTopology::LineStrip => vk::PrimitiveTopology::LINE_STRIP,
+        PrimitiveTopology::TriangleList => vk::PrimitiveTopology::TRIANGLE_LIST,
+        PrimitiveTopology::TriangleStrip => vk::PrimitiveTopology::TRIANGLE_STRIP,
+        PrimitiveTopology::TriangleFan => vk::PrimitiveTopology::TRIANGLE_FAN,
+    }
+}
+
+fn polygon_mode(mode: PolygonMode) -> vk::PolygonMode {
+    match mode {
+        PolygonMode::Fill => vk::PolygonMode::FILL,
+        PolygonMode::Line => vk::PolygonMode::LINE,
+        PolygonMode::Point => vk::PolygonMode::POINT,
+    }
+}
+
+fn cull_mode(mode: CullMode) -> vk::CullModeFlags {
+    match mode {
+        CullMode::None => vk::CullModeFlags::NONE,
+        CullMode::Front => vk::CullModeFlags::FRONT,
+        CullMode::Back => vk::CullModeFlags::BACK,
+        CullMode::FrontAndBack => vk::CullModeFlags::FRONT_AND_BACK,
+    }
+}
+
+fn front_face(face: FrontFace) -> vk::FrontFace {
+    match face {
+        FrontFace::CounterClockwise => vk::FrontFace::COUNTER_CLOCKWISE,
+        FrontFace::Clockwise => vk::FrontFace::CLOCKWISE,
+    }
+}
+
+fn compare_op(op: CompareOp) -> vk::CompareOp {
+    match op {
+        CompareOp::Never => vk::CompareOp::NEVER,
+        CompareOp::Less => vk::CompareOp::LESS,
+        CompareOp::Equal => vk::CompareOp::EQUAL,
+        CompareOp::LessOrEqual => vk::CompareOp::LESS_OR_EQUAL,
+        CompareOp::Greater => vk::CompareOp::GREATER,
+        CompareOp::NotEqual => vk::CompareOp::NOT_EQUAL,
+        CompareOp::GreaterOrEqual => vk::CompareOp::GREATER_OR_EQUAL,
+        CompareOp::Always => vk::CompareOp::ALWAYS,
+    }
+}
+
+fn stencil_op(op: StencilOp) -> vk::StencilOp {
+    match op {
+        StencilOp::Keep => vk::StencilOp::KEEP,
+        StencilOp::Zero => vk::StencilOp::ZERO,
+        StencilOp::Replace => vk::StencilOp::REPLACE,
+        StencilOp::IncrementAndClamp => vk::StencilOp::INCREMENT_AND_CLAMP,
+        StencilOp::DecrementAndClamp => vk::StencilOp::DECREMENT_AND_CLAMP,
+        StencilOp::Invert => vk::StencilOp::INVERT,
+        StencilOp::IncrementAndWrap => vk::StencilOp::INCREMENT_AND_WRAP,
+        StencilOp::DecrementAndWrap => vk::StencilOp::DECREMENT_AND_WRAP,
+    }
+}
+
+fn stencil_face(state: StencilFaceState) -> vk::StencilOpState {
+    vk::StencilOpState {
+        fail_op: stencil_op(state.fail_op),
+        pass_op: stencil_op(state.pass_op),
+        depth_fail_op: stencil_op(state.depth_fail_op),
+        compare_op: compare_op(state.compare_op),
+        compare_mask: state.compare_mask,
+        write_mask: state.write_mask,
+        reference: state.reference,
+    }
+}
+
+fn blend_factor(factor: BlendFactor) -> vk::BlendFactor {
+    match factor {
+        BlendFactor::Zero => vk::BlendFactor::ZERO,
+        BlendFactor::One => vk::BlendFactor::ONE,
+        BlendFactor::SrcColor => vk::BlendFactor::SRC_COLOR,
+        BlendFactor::OneMinusSrcColor => vk::BlendFactor::ONE_MINUS_SRC_COLOR,
+        BlendFactor::DstColor => vk::BlendFactor::DST_COLOR,
+        BlendFactor::OneMinusDstColor => vk::BlendFactor::ONE_MINUS_DST_COLOR,
+        BlendFactor::SrcAlpha => vk::BlendFactor::SRC_ALPHA,
+        BlendFactor::OneMinusSrcAlpha => vk::BlendFactor::ONE_MINUS_SRC_ALPHA,
+        BlendFactor::DstAlpha => vk::BlendFactor::DST_ALPHA,
+        BlendFactor::OneMinusDstAlpha => vk::BlendFactor::ONE_MINUS_DST_ALPHA,
+        BlendFactor::ConstantColor => vk::BlendFactor::CONSTANT_COLOR,
+        BlendFactor::OneMinusConstantColor => vk::BlendFactor::ONE_MINUS_CONSTANT_COLOR,
+        BlendFactor::ConstantAlpha => vk::BlendFactor::CONSTANT_ALPHA,
+        BlendFactor::OneMinusConstantAlpha => vk::BlendFactor::ONE_MINUS_CONSTANT_ALPHA,
+        BlendFactor::SrcAlphaSaturate => vk::BlendFactor::SRC_ALPHA_SATURATE,
+    }
+}
+
+fn blend_op(op: BlendOp) -> vk::BlendOp {
+    match op {
+        BlendOp::Add => vk::BlendOp::ADD,
+        BlendOp::Subtract => vk::BlendOp::SUBTRACT,
+        BlendOp::ReverseSubtract => vk::BlendOp::REVERSE_SUBTRACT,
+        BlendOp::Min => vk::BlendOp::MIN,
+        BlendOp::Max => vk::BlendOp::MAX,
+    }
+}
+
+fn color_blend_attachment(
+    attachment: ColorBlendAttachment,
+) -> vk::PipelineColorBlendAttachmentState {
+    // The factors are ignored without blending
+    let blend = attachment.blend.unwrap_or_else(Blend::additive);
+    vk::PipelineColorBlendAttachmentState {
+        blend_enable: attachment.blend.is_some() as u32,
+        src_color_blend_factor: blend_factor(blend.src_color),
+        dst_color_blend_factor: blend_factor(blend.dst_color),
+        color_blend_op: blend_op(blend.color_op),
+        src_alpha_blend_factor: blend_factor(blend.src_alpha),
+        dst_alpha_blend_factor: blend_factor(blend.dst_alpha),
+        alpha_blend_op: blend_op(blend.alpha_op),
+        // Both use the same bits
+        color_write_mask: vk::ColorComponentFlags::from_raw(attachment.write_mask.bits()),
+    }
+}
+
+fn dynamic_state_flag(state: DynamicState) -> vk::DynamicState {
+    match state {
+        DynamicState::LineWidth => vk::DynamicState::LINE_WIDTH,
+        DynamicState::DepthBias => vk::DynamicState::DEPTH_BIAS,
+        DynamicState::BlendConstants => vk::DynamicState::BLEND_CONSTANTS,
+        DynamicState::DepthBounds => vk::DynamicState::DEPTH_BOUNDS,
+        DynamicState::StencilCompareMask => vk::DynamicState::STENCIL_COMPARE_MASK,
+        DynamicState::StencilWriteMask => vk::DynamicState::STENCIL_WRITE_MASK,
+        DynamicState::StencilReference => vk::DynamicState::STENCIL_REFERENCE,
+    }
+}
+
 pub fn vertex_format(ty: VertexType) -> vk::Format {
     match ty {
         VertexType::F32(size) => {
@@ -307,4 +489,9 @@ pub struct GraphicsPipelineData {
     // been created?
     pub layout: vk::PipelineLayout,
     pub descriptor_layouts: Vec<vk::DescriptorSetLayout>,
+    /// The renderpasses and subpass the pipeline can draw in
+    pub compatibility: Compatibility,
+    pub subpass: u32,
+    /// Have to be set before the pipeline draws
+    pub dynamic_states: Vec<DynamicState>,
 }
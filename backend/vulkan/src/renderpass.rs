@@ -1,29 +1,151 @@
-use super::image::from_format;
+use super::image::{clear_color_value, from_format, resolve_image_resource, sample_count};
+use super::layout::{sampled_layout, SubresourceState};
 use super::Context;
 use ash::{version::DeviceV1_0, vk};
 use tephra::{
-    image::Image,
+    commandbuffer::SubmitError,
+    image::{Format, ImageHandle, ImageResource, ImageUsage, SampleCount},
     renderpass::{
-        Attachment, Framebuffer, FramebufferApi, Renderpass, RenderpassApi, RenderpassState,
+        Attachment, AttachmentLayout, AttachmentRefs, ClearValue, Framebuffer, FramebufferApi,
+        FramebufferError, LoadOp, Renderpass, RenderpassApi, RenderpassState, StoreOp,
+        SubpassState,
     },
 };
 pub struct FramebufferData {
     pub framebuffer: vk::Framebuffer,
+    /// The images and subresources behind the attachments, used for layout transitions
+    pub attachments: Vec<(ImageHandle, vk::ImageSubresourceRange)>,
+    /// The default viewport, scissor and render area
+    pub extent: vk::Extent2D,
 }
+/// The properties of an attachment image that [`validate_attachments`] checks
+#[derive(Debug, Clone)]
+struct AttachmentImage {
+    format: Format,
+    samples: SampleCount,
+    usage: ImageUsage,
+    level_count: u32,
+    extent: (u32, u32),
+}
+
+/// Checks that `attachments` can be used with `renderpass` and returns their extent
+fn validate_framebuffer(
+    ctx: &Context,
+    renderpass: &RenderpassData,
+    attachments: &[ImageResource],
+) -> Result<vk::Extent2D, FramebufferError> {
+    let images: Vec<_> = attachments
+        .iter()
+        .map(|&resource| {
+            let (_, image, range) = resolve_image_resource(ctx, resource);
+            let desc = ctx.images.get(image).desc.clone();
+            let format = match resource {
+                ImageResource::Image(_) => desc.format,
+                ImageResource::View(view) => ctx.image_views.get(view).format,
+            };
+            let mip = range.base_mip_level;
+            AttachmentImage {
+                format,
+                samples: desc.samples,
+                usage: desc.usage,
+                level_count: range.level_count,
+                extent: (
+                    (desc.resolution.width >> mip).max(1),
+                    (desc.resolution.height >> mip).max(1),
+                ),
+            }
+        })
+        .collect();
+    let extent = validate_attachments(&renderpass.attachments, &renderpass.subpasses, &images)?;
+    Ok(extent.map_or(ctx.surface_resolution, |(width, height)| {
+        vk::Extent2D { width, height }
+    }))
+}
+
+/// Checks the attachment images against the attachments of a renderpass and returns
+/// their common extent, `None` without attachments
+fn validate_attachments(
+    attachments: &[Attachment],
+    subpasses: &[SubpassState],
+    images: &[AttachmentImage],
+) -> Result<Option<(u32, u32)>, FramebufferError> {
+    if images.len() != attachments.len() {
+        return Err(FramebufferError::AttachmentCountMismatch {
+            expected: attachments.len(),
+            actual: images.len(),
+        });
+    }
+    let mut first_extent = None;
+    for (attachment, image) in attachments.iter().zip(images) {
+        let index = attachment.index;
+        if image.format != attachment.format {
+            return Err(FramebufferError::FormatMismatch {
+                index,
+                expected: attachment.format,
+                actual: image.format,
+            });
+        }
+        if image.samples != attachment.samples {
+            return Err(FramebufferError::SampleCountMismatch {
+                index,
+                expected: attachment.samples,
+                actual: image.samples,
+            });
+        }
+        if image.level_count != 1 {
+            return Err(FramebufferError::MultipleMipLevels(index));
+        }
+        let mut usage = if image.format.has_depth() || image.format.has_stencil() {
+            ImageUsage::DEPTH_STENCIL_ATTACHMENT
+        } else {
+            ImageUsage::COLOR_ATTACHMENT
+        };
+        let input = subpasses
+            .iter()
+            .any(|subpass| subpass.input_attachments.contains(&index));
+        if input {
+            usage |= ImageUsage::INPUT_ATTACHMENT;
+        }
+        if !image.usage.contains(usage) {
+            return Err(FramebufferError::MissingUsage {
+                index,
+                usage: usage - image.usage,
+            });
+        }
+        match first_extent {
+            None => first_extent = Some(image.extent),
+            Some(expected) if expected != image.extent => {
+                return Err(FramebufferError::ExtentMismatch {
+                    index,
+                    expected,
+                    actual: image.extent,
+                });
+            }
+            Some(_) => (),
+        }
+    }
+    Ok(first_extent)
+}
+
 impl FramebufferApi for Context {
-    unsafe fn create_framebuffer(&self, renderpass: Renderpass, images: &[Image]) -> Framebuffer {
+    unsafe fn create_framebuffer(
+        &self,
+        renderpass: Renderpass,
+        attachments: &[ImageResource],
+    ) -> Result<Framebuffer, FramebufferError> {
         let renderpass_data = self.renderpasses.get(renderpass);
-        // TODO: Proper resolution
-        let framebuffer_attachments: Vec<_> = images
+        let extent = validate_framebuffer(self, &renderpass_data, attachments)?;
+        let resolved: Vec<_> = attachments
             .iter()
-            .map(|&image| self.images.get(image.handle).image_view)
+            .map(|&attachment| resolve_image_resource(self, attachment))
             .collect();
+        let framebuffer_attachments: Vec<_> = resolved.iter().map(|&(view, _, _)| view).collect();
         let frame_buffer_create_info = vk::FramebufferCreateInfo {
             render_pass: renderpass_data.render_pass,
             attachment_count: framebuffer_attachments.len() as u32,
             p_attachments: framebuffer_attachments.as_ptr(),
-            width: self.surface_resolution.width,
-            height: self.surface_resolution.height,
+            width: extent.width,
+            height: extent.height,
             layers: 1,
             ..Default::default()
         };
@@ -31,72 +153,306 @@ impl FramebufferApi for Context {
             .device
             .create_framebuffer(&frame_buffer_create_info, None)
             .unwrap();
-        let data = FramebufferData { framebuffer };
-        self.framebuffers.insert(data)
+        let attachments = resolved
+            .into_iter()
+            .map(|(_, image, range)| (image, range))
+            .collect();
+        let data = FramebufferData {
+            framebuffer,
+            attachments,
+            extent,
+        };
+        Ok(self.framebuffers.insert(data))
     }
 }
 pub struct RenderpassData {
     pub render_pass: vk::RenderPass,
+    /// Every attachment, ordered by index
+    pub attachments: Vec<Attachment>,
+    pub subpasses: Vec<SubpassState>,
+    pub compatibility: Compatibility,
 }
-impl RenderpassApi for Context {
-    unsafe fn create_renderpass(&self, builder: &RenderpassState) -> Renderpass {
-        fn build_attachment(
-            _ctx: &Context,
-            attachment: &Attachment,
-            layout: vk::ImageLayout,
-        ) -> vk::AttachmentDescription {
-            // TODO: Add more configs
-            vk::AttachmentDescription {
-                format: from_format(attachment.format),
-                flags: vk::AttachmentDescriptionFlags::empty(),
-                samples: vk::SampleCountFlags::TYPE_1,
-                load_op: vk::AttachmentLoadOp::CLEAR,
-                store_op: vk::AttachmentStoreOp::STORE,
-                stencil_load_op: vk::AttachmentLoadOp::DONT_CARE,
-                stencil_store_op: vk::AttachmentStoreOp::DONT_CARE,
-                initial_layout: vk::ImageLayout::UNDEFINED,
-                final_layout: layout,
-            }
+
+/// What two renderpasses have to agree on, so that pipelines created for one of them
+/// can draw in the other
+#[derive(Clone, PartialEq)]
+pub struct Compatibility {
+    attachments: Vec<(Format, SampleCount)>,
+    /// The color, resolve, depth and input attachments of every subpass
+    subpasses: Vec<(AttachmentRefs, AttachmentRefs, Option<u32>, AttachmentRefs)>,
+}
+
+impl Compatibility {
+    fn new(attachments: &[Attachment], subpasses: &[SubpassState]) -> Self {
+        Compatibility {
+            attachments: attachments
+                .iter()
+                .map(|attachment| (attachment.format, attachment.samples))
+                .collect(),
+            subpasses: subpasses
+                .iter()
+                .map(|subpass| {
+                    (
+                        subpass.color_attachments.clone(),
+                        subpass.resolve_attachments.clone(),
+                        subpass.depth_attachment,
+                        subpass.input_attachments.clone(),
+                    )
+                })
+                .collect(),
         }
-        let mut attachments: Vec<_> = builder
+    }
+}
+
+impl RenderpassData {
+    /// The sample count of the attachments that `subpass` renders to, if it renders
+    /// to any
+    pub fn subpass_samples(&self, subpass: usize) -> Option<SampleCount> {
+        let subpass = &self.subpasses[subpass];
+        subpass
             .color_attachments
             .iter()
-            .map(|attachment| {
-                build_attachment(self, attachment, vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
+            .chain(subpass.depth_attachment.as_ref())
+            .filter_map(|&index| {
+                self.attachments
+                    .iter()
+                    .find(|attachment| attachment.index == index)
             })
-            .collect();
-        if let Some(depth_attachment) = builder.depth_attachment.as_ref() {
-            attachments.push(build_attachment(
-                self,
-                depth_attachment,
-                vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
-            ));
+            .map(|attachment| attachment.samples)
+            .next()
+    }
+
+    /// Checks that non empty `overrides` have a matching value for every attachment
+    pub fn validate_clear_values(&self, overrides: &[ClearValue]) -> Result<(), SubmitError> {
+        if overrides.is_empty() {
+            return Ok(());
         }
+        if overrides.len() != self.attachments.len() {
+            return Err(SubmitError::ClearValueCount {
+                expected: self.attachments.len(),
+                actual: overrides.len(),
+            });
+        }
+        let mismatch = self
+            .attachments
+            .iter()
+            .zip(overrides)
+            .find(|(attachment, value)| !value.matches(attachment.format));
+        if let Some((attachment, _)) = mismatch {
+            return Err(SubmitError::ClearValueMismatch(attachment.index));
+        }
+        Ok(())
+    }
 
-        let color_attachments: Vec<_> = builder
-            .color_attachments
+    /// `overrides` replace the clear values of the attachments if they aren't empty.
+    /// They have to be checked with [`RenderpassData::validate_clear_values`] first.
+    pub fn clear_values(&self, overrides: &[ClearValue]) -> Vec<vk::ClearValue> {
+        if overrides.is_empty() {
+            return self
+                .attachments
+                .iter()
+                .map(|attachment| clear_value(attachment.clear_value))
+                .collect();
+        }
+        overrides.iter().map(|&value| clear_value(value)).collect()
+    }
+}
+
+fn clear_value(value: ClearValue) -> vk::ClearValue {
+    match value {
+        ClearValue::Color(color) => {
+            vk::ClearValue {
+                color: clear_color_value(color),
+            }
+        }
+        ClearValue::DepthStencil(value) => {
+            vk::ClearValue {
+                depth_stencil: vk::ClearDepthStencilValue {
+                    depth: value.depth,
+                    stencil: value.stencil,
+                },
+            }
+        }
+    }
+}
+
+fn load_op(op: LoadOp) -> vk::AttachmentLoadOp {
+    match op {
+        LoadOp::Clear => vk::AttachmentLoadOp::CLEAR,
+        LoadOp::Load => vk::AttachmentLoadOp::LOAD,
+        LoadOp::DontCare => vk::AttachmentLoadOp::DONT_CARE,
+    }
+}
+
+fn store_op(op: StoreOp) -> vk::AttachmentStoreOp {
+    match op {
+        StoreOp::Store => vk::AttachmentStoreOp::STORE,
+        StoreOp::DontCare => vk::AttachmentStoreOp::DONT_CARE,
+    }
+}
+
+fn image_layout(layout: AttachmentLayout, format: Format) -> vk::ImageLayout {
+    match layout {
+        AttachmentLayout::Undefined => vk::ImageLayout::UNDEFINED,
+        AttachmentLayout::Attachment => SubresourceState::attachment(format).layout,
+        AttachmentLayout::ShaderRead => sampled_layout(format),
+        AttachmentLayout::General => vk::ImageLayout::GENERAL,
+        AttachmentLayout::TransferSrc => vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+        AttachmentLayout::TransferDst => vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+        AttachmentLayout::Present => vk::ImageLayout::PRESENT_SRC_KHR,
+    }
+}
+
+/// The tracked states of an attachment before and after the renderpass. Both keep
+/// the attachment accesses, so that later uses wait until rendering has finished.
+pub(crate) fn attachment_states(attachment: &Attachment) -> (SubresourceState, SubresourceState) {
+    let rendering = SubresourceState::attachment(attachment.format);
+    let state = |layout| {
+        SubresourceState {
+            layout: image_layout(layout, attachment.format),
+            ..rendering
+        }
+    };
+    let initial = match attachment.initial_layout {
+        // The renderpass discards the content anyway
+        AttachmentLayout::Undefined => rendering,
+        layout => state(layout),
+    };
+    (initial, state(attachment.final_layout))
+}
+
+/// The stages and accesses in which `subpass` writes to its attachments
+fn subpass_writes(subpass: &SubpassState) -> (vk::PipelineStageFlags, vk::AccessFlags) {
+    let mut stages = vk::PipelineStageFlags::empty();
+    let mut access = vk::AccessFlags::empty();
+    if !subpass.color_attachments.is_empty() {
+        stages |= vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT;
+        access |= vk::AccessFlags::COLOR_ATTACHMENT_WRITE;
+    }
+    if subpass.depth_attachment.is_some() {
+        stages |= vk::PipelineStageFlags::EARLY_FRAGMENT_TESTS
+            | vk::PipelineStageFlags::LATE_FRAGMENT_TESTS;
+        access |= vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE;
+    }
+    (stages, access)
+}
+
+/// The stages and accesses in which `subpass` reads from or writes to its attachments
+fn subpass_accesses(subpass: &SubpassState) -> (vk::PipelineStageFlags, vk::AccessFlags) {
+    let (mut stages, mut access) = subpass_writes(subpass);
+    if !subpass.color_attachments.is_empty() {
+        access |= vk::AccessFlags::COLOR_ATTACHMENT_READ;
+    }
+    if subpass.depth_attachment.is_some() {
+        access |= vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_READ;
+    }
+    if !subpass.input_attachments.is_empty() {
+        stages |= vk::PipelineStageFlags::FRAGMENT_SHADER;
+        access |= vk::AccessFlags::INPUT_ATTACHMENT_READ;
+    }
+    (stages, access)
+}
+
+/// The attachment references of one subpass, which have to outlive the description
+struct SubpassReferences {
+    color: Vec<vk::AttachmentReference>,
+    resolve: Vec<vk::AttachmentReference>,
+    input: Vec<vk::AttachmentReference>,
+    depth: Option<vk::AttachmentReference>,
+}
+
+impl SubpassReferences {
+    fn new(subpass: &SubpassState, attachments: &[Attachment]) -> Self {
+        let format = |index: u32| {
+            attachments
+                .iter()
+                .find(|attachment| attachment.index == index)
+                .expect("Validated attachment")
+                .format
+        };
+        let reference = |&index: &u32, layout| {
+            vk::AttachmentReference {
+                attachment: index,
+                layout,
+            }
+        };
+        let color_layout = vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL;
+        SubpassReferences {
+            color: subpass
+                .color_attachments
+                .iter()
+                .map(|index| reference(index, color_layout))
+                .collect(),
+            resolve: subpass
+                .resolve_attachments
+                .iter()
+                .map(|index| reference(index, color_layout))
+                .collect(),
+            input: subpass
+                .input_attachments
+                .iter()
+                .map(|index| reference(index, sampled_layout(format(*index))))
+                .collect(),
+            depth: subpass.depth_attachment.as_ref().map(|index| {
+                reference(index, vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL)
+            }),
+        }
+    }
+
+    fn description(&self, subpass: &SubpassState) -> vk::SubpassDescription {
+        let mut description = vk::SubpassDescription {
+            color_attachment_count: self.color.len() as _,
+            p_color_attachments: self.color.as_ptr(),
+            input_attachment_count: self.input.len() as _,
+            p_input_attachments: self.input.as_ptr(),
+            preserve_attachment_count: subpass.preserve_attachments.len() as _,
+            p_preserve_attachments: subpass.preserve_attachments.as_ptr(),
+            pipeline_bind_point: vk::PipelineBindPoint::GRAPHICS,
+            ..Default::default()
+        };
+        if let Some(ref depth) = self.depth {
+            description.p_depth_stencil_attachment = depth;
+        }
+        if !self.resolve.is_empty() {
+            description.p_resolve_attachments = self.resolve.as_ptr();
+        }
+        description
+    }
+}
+
+impl RenderpassApi for Context {
+    unsafe fn create_renderpass(&self, builder: &RenderpassState) -> Renderpass {
+        // Descriptions have to be in the order of the attachment indices
+        let mut sorted_attachments: Vec<Attachment> = builder.attachments.to_vec();
+        sorted_attachments.sort_by_key(|attachment| attachment.index);
+        let attachments: Vec<_> = sorted_attachments
             .iter()
             .map(|attachment| {
-                vk::AttachmentReference {
-                    attachment: attachment.index,
-                    layout: vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+                vk::AttachmentDescription {
+                    format: from_format(attachment.format),
+                    flags: vk::AttachmentDescriptionFlags::empty(),
+                    samples: sample_count(attachment.samples),
+                    load_op: load_op(attachment.load_op),
+                    store_op: store_op(attachment.store_op),
+                    stencil_load_op: load_op(attachment.stencil_load_op),
+                    stencil_store_op: store_op(attachment.stencil_store_op),
+                    initial_layout: image_layout(attachment.initial_layout, attachment.format),
+                    final_layout: image_layout(attachment.final_layout, attachment.format),
                 }
             })
             .collect();
-        let depth_attachment = builder.depth_attachment.as_ref().map(|attachment| {
-            vk::AttachmentReference {
-                attachment: attachment.index,
-                layout: vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
-            }
-        });
-        let mut dst_access_mask = vk::AccessFlags::empty();
-        if builder.color_attachments.len() > 0 {
-            dst_access_mask |= vk::AccessFlags::COLOR_ATTACHMENT_WRITE;
-        }
-        if builder.depth_attachment.is_some() {
-            dst_access_mask |= vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE;
-        }
-        let dependency = vk::SubpassDependency {
+
+        let references: Vec<_> = builder
+            .subpasses
+            .iter()
+            .map(|subpass| SubpassReferences::new(subpass, &sorted_attachments))
+            .collect();
+        let subpasses: Vec<_> = references
+            .iter()
+            .zip(&builder.subpasses)
+            .map(|(references, subpass)| references.description(subpass))
+            .collect();
+        let mut dependencies = vec![vk::SubpassDependency {
             dependency_flags: Default::default(),
             src_subpass: vk::SUBPASS_EXTERNAL,
             dst_subpass: Default::default(),
@@ -104,23 +460,42 @@ impl RenderpassApi for Context {
             dst_stage_mask: vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
             src_access_mask: vk::AccessFlags::empty(),
             dst_access_mask: vk::AccessFlags::COLOR_ATTACHMENT_WRITE,
-        };
-        let mut subpass = vk::SubpassDescription {
-            color_attachment_count: color_attachments.len() as _,
-            p_color_attachments: color_attachments.as_ptr(),
-            pipeline_bind_point: vk::PipelineBindPoint::GRAPHICS,
-            ..Default::default()
-        };
-        if let Some(ref depth_attachment) = depth_attachment {
-            subpass.p_depth_stencil_attachment = depth_attachment;
-        }
+        }];
+        dependencies.extend(builder.dependencies.iter().map(|dependency| {
+            let (src_stage_mask, src_access_mask) =
+                subpass_writes(&builder.subpasses[dependency.src as usize]);
+            let (dst_stage_mask, dst_access_mask) =
+                subpass_accesses(&builder.subpasses[dependency.dst as usize]);
+            let dependency_flags = if dependency.by_region {
+                vk::DependencyFlags::BY_REGION
+            } else {
+                vk::DependencyFlags::empty()
+            };
+            // Stage masks can't be empty, even if a subpass doesn't touch any attachment
+            let or_all = |stages: vk::PipelineStageFlags| {
+                if stages.is_empty() {
+                    vk::PipelineStageFlags::ALL_GRAPHICS
+                } else {
+                    stages
+                }
+            };
+            vk::SubpassDependency {
+                dependency_flags,
+                src_subpass: dependency.src,
+                dst_subpass: dependency.dst,
+                src_stage_mask: or_all(src_stage_mask),
+                dst_stage_mask: or_all(dst_stage_mask),
+                src_access_mask,
+                dst_access_mask,
+            }
+        }));
         let renderpass_create_info = vk::RenderPassCreateInfo {
             attachment_count: attachments.len() as u32,
             p_attachments: attachments.as_ptr(),
-            subpass_count: 1,
-            p_subpasses: &subpass,
-            dependency_count: 1,
-            p_dependencies: &dependency,
+            subpass_count: subpasses.len() as u32,
+            p_subpasses: subpasses.as_ptr(),
+            dependency_count: dependencies.len() as u32,
+            p_dependencies: dependencies.as_ptr(),
             ..Default::default()
         };
         let render_pass = self
@@ -148,7 +523,13 @@ impl RenderpassApi for Context {
         //     .device
         //     .create_framebuffer(&frame_buffer_create_info, None)
         //     .unwrap();
-        self.renderpasses.insert(RenderpassData { render_pass })
+        let compatibility = Compatibility::new(&sorted_attachments, &builder.subpasses);
+        self.renderpasses.insert(RenderpassData {
+            render_pass,
+            attachments: sorted_attachments,
+            subpasses: builder.subpasses.to_vec(),
+            compatibility,
+        })
     }
 }
 // use super::{CommandBuffer, Vulkan};
@@ -352,3 +733,115 @@ impl RenderpassApi for Context {
 //         }
 //     }
 // }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn attachment(index: u32, format: Format) -> Attachment {
+        Attachment::builder()
+            .index(index)
+            .format(format)
+            .build()
+            .unwrap()
+    }
+
+    fn image(format: Format, usage: ImageUsage) -> AttachmentImage {
+        AttachmentImage {
+            format,
+            samples: SampleCount::S1,
+            usage,
+            level_count: 1,
+            extent: (64, 32),
+        }
+    }
+
+    fn attachments() -> (Vec<Attachment>, Vec<AttachmentImage>) {
+        let attachments = vec![
+            attachment(0, Format::R8G8B8A8_UNORM),
+            attachment(1, Format::D32_SFLOAT),
+        ];
+        let images = vec![
+            image(Format::R8G8B8A8_UNORM, ImageUsage::COLOR_ATTACHMENT),
+            image(Format::D32_SFLOAT, ImageUsage::DEPTH_STENCIL_ATTACHMENT),
+        ];
+        (attachments, images)
+    }
+
+    #[test]
+    fn matching_attachments_return_their_extent() {
+        let (attachments, images) = attachments();
+        let extent = validate_attachments(&attachments, &[], &images).unwrap();
+        assert_eq!(extent, Some((64, 32)));
+        assert_eq!(validate_attachments(&[], &[], &[]).unwrap(), None);
+    }
+
+    #[test]
+    fn attachment_count_has_to_match() {
+        let (attachments, images) = attachments();
+        match validate_attachments(&attachments, &[], &images[..1]) {
+            Err(FramebufferError::AttachmentCountMismatch {
+                expected: 2,
+                actual: 1,
+            }) => (),
+            result => panic!("Expected a count mismatch, got {:?}", result),
+        }
+    }
+
+    #[test]
+    fn attachment_format_has_to_match() {
+        let (attachments, mut images) = attachments();
+        images[0].format = Format::B8G8R8A8_UNORM;
+        match validate_attachments(&attachments, &[], &images) {
+            Err(FramebufferError::FormatMismatch {
+                index: 0,
+                expected: Format::R8G8B8A8_UNORM,
+                actual: Format::B8G8R8A8_UNORM,
+            }) => (),
+            result => panic!("Expected a format mismatch, got {:?}", result),
+        }
+    }
+
+    #[test]
+    fn attachment_samples_have_to_match() {
+        let (attachments, mut images) = attachments();
+        images[1].samples = SampleCount::S4;
+        match validate_attachments(&attachments, &[], &images) {
+            Err(FramebufferError::SampleCountMismatch {
+                index: 1,
+                expected: SampleCount::S1,
+                actual: SampleCount::S4,
+            }) => (),
+            result => panic!("Expected a sample count mismatch, got {:?}", result),
+        }
+    }
+
+    #[test]
+    fn attachment_extents_have_to_match() {
+        let (attachments, mut images) = attachments();
+        images[1].extent = (32, 16);
+        match validate_attachments(&attachments, &[], &images) {
+            Err(FramebufferError::ExtentMismatch {
+                index: 1,
+                expected: (64, 32),
+                actual: (32, 16),
+            }) => (),
+            result => panic!("Expected an extent mismatch, got {:?}", result),
+        }
+    }
+
+    #[test]
+    fn input_attachments_need_the_input_usage() {
+        let (attachments, images) = attachments();
+        let subpass = SubpassState {
+            input_attachments: [0].iter().cloned().collect(),
+            ..Default::default()
+        };
+        match validate_attachments(&attachments, &[subpass], &images) {
+            Err(FramebufferError::MissingUsage { index: 0, usage }) => {
+                assert_eq!(usage, ImageUsage::INPUT_ATTACHMENT)
+            }
+            result => panic!("Expected a missing usage, got {:?}", result),
+        }
+    }
+}
@@ -0,0 +1,112 @@
+use super::Context;
+use ash::version::{DeviceV1_0, InstanceV1_0};
+use ash::vk;
+use tephra::sampler::{
+    AddressMode, BorderColor, CompareOp, Filter, MipmapMode, Sampler, SamplerApi, SamplerDesc,
+    SamplerError,
+};
+
+pub struct SamplerData {
+    pub sampler: vk::Sampler,
+    pub desc: SamplerDesc,
+}
+
+pub(crate) fn filter(filter: Filter) -> vk::Filter {
+    match filter {
+        Filter::Nearest => vk::Filter::NEAREST,
+        Filter::Linear => vk::Filter::LINEAR,
+    }
+}
+
+fn mipmap_mode(mode: MipmapMode) -> vk::SamplerMipmapMode {
+    match mode {
+        MipmapMode::Nearest => vk::SamplerMipmapMode::NEAREST,
+        MipmapMode::Linear => vk::SamplerMipmapMode::LINEAR,
+    }
+}
+
+fn address_mode(mode: AddressMode) -> vk::SamplerAddressMode {
+    match mode {
+        AddressMode::Repeat => vk::SamplerAddressMode::REPEAT,
+        AddressMode::MirroredRepeat => vk::SamplerAddressMode::MIRRORED_REPEAT,
+        AddressMode::ClampToEdge => vk::SamplerAddressMode::CLAMP_TO_EDGE,
+        AddressMode::ClampToBorder => vk::SamplerAddressMode::CLAMP_TO_BORDER,
+        // VK_KHR_sampler_mirror_clamp_to_edge, ash 0.29 doesn't define the constant
+        AddressMode::MirrorClampToEdge => vk::SamplerAddressMode::from_raw(4),
+    }
+}
+
+fn border_color(color: BorderColor) -> vk::BorderColor {
+    match color {
+        BorderColor::TransparentBlack => vk::BorderColor::FLOAT_TRANSPARENT_BLACK,
+        BorderColor::OpaqueBlack => vk::BorderColor::FLOAT_OPAQUE_BLACK,
+        BorderColor::OpaqueWhite => vk::BorderColor::FLOAT_OPAQUE_WHITE,
+    }
+}
+
+pub(crate) fn compare_op(op: CompareOp) -> vk::CompareOp {
+    match op {
+        CompareOp::Never => vk::CompareOp::NEVER,
+        CompareOp::Less => vk::CompareOp::LESS,
+        CompareOp::Equal => vk::CompareOp::EQUAL,
+        CompareOp::LessOrEqual => vk::CompareOp::LESS_OR_EQUAL,
+        CompareOp::Greater => vk::CompareOp::GREATER,
+        CompareOp::NotEqual => vk::CompareOp::NOT_EQUAL,
+        CompareOp::GreaterOrEqual => vk::CompareOp::GREATER_OR_EQUAL,
+        CompareOp::Always => vk::CompareOp::ALWAYS,
+    }
+}
+
+impl SamplerApi for Context {
+    fn create_sampler(&self, desc: &SamplerDesc) -> Result<Sampler, SamplerError> {
+        let mut cache = self.sampler_cache.lock();
+        if let Some(&sampler) = cache.get(desc) {
+            return Ok(sampler);
+        }
+        let address_modes = [desc.address_mode_u, desc.address_mode_v, desc.address_mode_w];
+        if !self.mirror_clamp_to_edge && address_modes.contains(&AddressMode::MirrorClampToEdge) {
+            return Err(SamplerError::UnsupportedAddressMode(AddressMode::MirrorClampToEdge));
+        }
+        let (anisotropy_supported, max_anisotropy_limit) = unsafe {
+            let features = self.instance.get_physical_device_features(self.pdevice);
+            let properties = self.instance.get_physical_device_properties(self.pdevice);
+            (
+                features.sampler_anisotropy == vk::TRUE,
+                properties.limits.max_sampler_anisotropy,
+            )
+        };
+        let max_anisotropy = desc
+            .max_anisotropy
+            .filter(|_| anisotropy_supported)
+            .map(|anisotropy| anisotropy.min(max_anisotropy_limit).max(1.0));
+        let create_info = vk::SamplerCreateInfo {
+            mag_filter: filter(desc.mag_filter),
+            min_filter: filter(desc.min_filter),
+            mipmap_mode: mipmap_mode(desc.mipmap_mode),
+            address_mode_u: address_mode(desc.address_mode_u),
+            address_mode_v: address_mode(desc.address_mode_v),
+            address_mode_w: address_mode(desc.address_mode_w),
+            mip_lod_bias: desc.mip_lod_bias,
+            anisotropy_enable: max_anisotropy.is_some() as vk::Bool32,
+            max_anisotropy: max_anisotropy.unwrap_or(1.0),
+            compare_enable: desc.compare_op.is_some() as vk::Bool32,
+            compare_op: desc.compare_op.map_or(vk::CompareOp::NEVER, compare_op),
+            min_lod: desc.min_lod,
+            max_lod: desc.max_lod,
+            border_color: border_color(desc.border_color),
+            unnormalized_coordinates: vk::FALSE,
+            ..Default::default()
+        };
+        let sampler = unsafe {
+            self.device
+                .create_sampler(&create_info, None)
+                .expect("create sampler")
+        };
+        let handle = self.samplers.insert(SamplerData {
+            sampler,
+            desc: *desc,
+        });
+        cache.insert(*desc, handle);
+        Ok(handle)
+    }
+}
@@ -5,6 +5,7 @@ use std::ptr;
 use tephra::shader::{ShaderApi, ShaderError, ShaderModule};
 pub struct ShaderModuleData {
     pub shader_module: vk::ShaderModule,
+    pub code: Vec<u8>,
 }
 
 impl ShaderApi for Context {
@@ -21,7 +22,13 @@ impl ShaderApi for Context {
             .device
             .create_shader_module(&shader_info, None)
             .expect("Vertex shader module error");
-        let shader_data = ShaderModuleData { shader_module };
+        let shader_data = ShaderModuleData {
+            shader_module,
+            code: bytes.to_vec(),
+        };
         Ok(self.shader_modules.insert(shader_data))
     }
+    fn spirv(&self, module: ShaderModule) -> Vec<u8> {
+        self.shader_modules.get(module).code.clone()
+    }
 }
@@ -1,9 +1,10 @@
-use super::image::{into_format, ImageData};
-use super::CommandBuffer;
+use super::image::{into_format, transition_image_now, ImageData};
+use super::layout::{ImageState, SubresourceState};
 use super::Context;
 use ash::extensions::khr;
 use ash::version::DeviceV1_0;
 use ash::vk;
+use parking_lot::Mutex;
 use std::ops::Drop;
 use std::ptr;
 use tephra::{
@@ -35,7 +36,9 @@ impl SwapchainApi for SwapchainData {
     fn copy_and_present(&self, image: Image) {
         let index = self.aquire_next_image().expect("acquire");
         let present_image = &self.present_images()[index as usize];
-        self.context.copy_image(image.handle, present_image.handle);
+        self.context
+            .copy_image(image.handle, present_image.handle)
+            .expect("The image can't be copied to the swapchain");
         self.present(index);
     }
     fn recreate(&mut self) {
@@ -73,6 +76,8 @@ impl SwapchainApi for SwapchainData {
         }
     }
     fn present(&self, index: u32) {
+        let present_image = self.present_images[index as usize];
+        transition_image_now(&self.context, present_image.handle, SubresourceState::present());
         unsafe {
             let present_info = vk::PresentInfoKHR {
                 s_type: vk::StructureType::PRESENT_INFO_KHR,
@@ -129,13 +134,13 @@ unsafe fn get_swapchain_images(
                 .device
                 .create_image_view(&create_view_info, None)
                 .unwrap();
-            let desc = ImageDesc {
+            let desc = ImageDesc::new(
                 resolution,
-                layout: ImageLayout::Color,
-                format: into_format(ctx.surface_format.format),
-            };
+                ImageLayout::Color,
+                into_format(ctx.surface_format.format),
+            );
             let data = ImageData {
-                layout: vk::ImageLayout::PRESENT_SRC_KHR,
+                state: Mutex::new(ImageState::new(1, 1)),
                 image,
                 image_view,
                 desc,
@@ -207,7 +212,10 @@ fn create_swapchain(ctx: &Context, old_swapchain: Option<vk::SwapchainKHR>) -> S
             image_color_space: surface_format.color_space,
             image_format: surface_format.format,
             image_extent: surface_resolution.clone(),
-            image_usage: vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::TRANSFER_DST,
+            // Transfer src is needed for screenshots
+            image_usage: vk::ImageUsageFlags::COLOR_ATTACHMENT
+                | vk::ImageUsageFlags::TRANSFER_SRC
+                | vk::ImageUsageFlags::TRANSFER_DST,
             image_sharing_mode: vk::SharingMode::EXCLUSIVE,
             pre_transform: pre_transform,
             composite_alpha: vk::CompositeAlphaFlagsKHR::OPAQUE,
@@ -227,39 +235,6 @@ fn create_swapchain(ctx: &Context, old_swapchain: Option<vk::SwapchainKHR>) -> S
             height: surface_resolution.height,
         };
         let present_images = get_swapchain_images(ctx, swapchain, resolution);
-        for &image in &present_images {
-            let vkimage = ctx.images.get(image.handle);
-            let command_buffer = CommandBuffer::record(ctx, "SwapchainBarrier", |command_buffer| {
-                let present_barrier = vk::ImageMemoryBarrier {
-                    s_type: vk::StructureType::IMAGE_MEMORY_BARRIER,
-                    p_next: ptr::null(),
-                    src_access_mask: vk::AccessFlags::empty(),
-                    dst_access_mask: vk::AccessFlags::empty(),
-                    old_layout: vk::ImageLayout::UNDEFINED,
-                    new_layout: vk::ImageLayout::PRESENT_SRC_KHR,
-                    src_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
-                    dst_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
-                    image: vkimage.image,
-                    subresource_range: vk::ImageSubresourceRange {
-                        aspect_mask: vk::ImageAspectFlags::COLOR,
-                        base_mip_level: 0,
-                        level_count: 1,
-                        base_array_layer: 0,
-                        layer_count: 1,
-                    },
-                };
-                ctx.device.cmd_pipeline_barrier(
-                    command_buffer,
-                    vk::PipelineStageFlags::TRANSFER,
-                    vk::PipelineStageFlags::BOTTOM_OF_PIPE,
-                    vk::DependencyFlags::empty(),
-                    &[],
-                    &[],
-                    &[present_barrier],
-                );
-            });
-            ctx.present_queue.submit(ctx, &[], &[], &[], command_buffer);
-        }
         // for image in &present_images {
         //     let barrier = vk::ImageMemoryBarrier {
         //         s_type: vk::StructureType::IMAGE_MEMORY_BARRIER,
@@ -73,18 +73,18 @@ impl Triangle {
         let index_buffer = Buffer::from_slice(
             &ctx,
             Property::HostVisible,
-            BufferUsage::Index,
+            BufferUsage::INDEX,
             &index_buffer_data,
         )?;
         let storage_buffer = Buffer::from_slice(
             ctx,
             Property::HostVisible,
-            BufferUsage::Storage,
+            BufferUsage::STORAGE,
             &[[1.0f32, 0.0, 0.0, 1.0]],
         )?;
 
         let vertex_buffer =
-            Buffer::from_slice(&ctx, Property::HostVisible, BufferUsage::Vertex, &VERTICES)?;
+            Buffer::from_slice(&ctx, Property::HostVisible, BufferUsage::VERTEX, &VERTICES)?;
 
         let compute_pipeline = {
             let compute_shader = ShaderModule::load(ctx, "shader/triangle/comp.spv")?;
@@ -94,25 +94,17 @@ impl Triangle {
                     entry_name: "main".into(),
                 })
                 .layout::<Color>()
-                .create(ctx)
+                .create(ctx)?
         };
         let format = swapchain.format();
         let vertex_shader_module = ShaderModule::load(&ctx, "shader/triangle/vert.spv")?;
         let fragment_shader_module = ShaderModule::load(&ctx, "shader/triangle/frag.spv")?;
 
-        let color_desc = ImageDesc {
-            layout: ImageLayout::Color,
-            format,
-            resolution,
-        };
-        let color = Image::allocate(ctx, color_desc);
+        let color_desc = ImageDesc::new(resolution, ImageLayout::Color, format);
+        let color = Image::allocate(ctx, color_desc)?;
 
-        let depth_desc = ImageDesc {
-            layout: ImageLayout::Depth,
-            format: Format::D16_UNORM,
-            resolution,
-        };
-        let depth = Image::allocate(ctx, depth_desc);
+        let depth_desc = ImageDesc::new(resolution, ImageLayout::Depth, Format::D16_UNORM);
+        let depth = Image::allocate(ctx, depth_desc)?;
 
         let renderpass = Renderpass::builder()
             .color_attachment(
@@ -129,7 +121,7 @@ impl Triangle {
                     .build()
                     .unwrap(),
             )
-            .create(ctx);
+            .create(ctx)?;
 
         let graphics_pipeline = GraphicsPipeline::builder()
             .vertex_shader(ShaderStage {
@@ -143,8 +135,8 @@ impl Triangle {
             .render_target(renderpass)
             .layout::<Color>()
             .vertex::<Vertex>()
-            .create(ctx);
-        let framebuffer = ctx.create_framebuffer(renderpass, &[color, depth]);
+            .create(ctx)?;
+        let framebuffer = ctx.create_framebuffer(renderpass, &[color.into(), depth.into()])?;
         let triangle = Triangle {
             vertex_buffer,
             storage_buffer,
@@ -207,9 +199,9 @@ fn main() {
         let triangle = Triangle::new(&ctx).unwrap();
         let mut pool = Pool::new(&ctx);
         loop {
-            let mut command_list = CommandList::new();
+            let mut command_list = CommandList::new(&ctx);
             triangle.record_commands(&mut command_list);
-            ctx.submit_commands(&mut pool, &command_list);
+            ctx.submit_commands(&mut pool, &command_list).unwrap();
             pool.reset();
         }
     }
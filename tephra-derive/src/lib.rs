@@ -4,8 +4,11 @@ extern crate syn;
 #[macro_use]
 extern crate quote;
 extern crate proc_macro2;
-use proc_macro2::TokenStream;
-use syn::{Data, DataStruct, DeriveInput, Ident, Meta, NestedMeta};
+use proc_macro2::{Span, TokenStream};
+use syn::{
+    Attribute, Data, DataStruct, DeriveInput, Expr, ExprLit, Field, Ident, Lit, Meta, NestedMeta,
+    Type, TypeArray,
+};
 #[proc_macro_derive(Descriptor, attributes(descriptor))]
 pub fn derive_descriptor_info(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
     let input: DeriveInput = syn::parse(input).unwrap();
@@ -36,6 +39,40 @@ fn gen_descriptor_info(ident: &Ident, input: &DataStruct) -> TokenStream {
                         #path::DescriptorResource::Uniform(self.#field.buffer)
                     }
                 }
+                DescriptorType::UniformDynamic => {
+                    quote! {
+                        #path::DescriptorResource::UniformDynamic(self.#field.buffer)
+                    }
+                }
+                DescriptorType::StorageImage => {
+                    quote! {
+                        #path::DescriptorResource::StorageImage(self.#field.handle)
+                    }
+                }
+                DescriptorType::SampledImage => {
+                    quote! {
+                        #path::DescriptorResource::SampledImage(self.#field.handle)
+                    }
+                }
+                DescriptorType::Sampler => {
+                    quote! {
+                        #path::DescriptorResource::Sampler(self.#field)
+                    }
+                }
+                // Expects an `(Image, Sampler)` pair
+                DescriptorType::CombinedImageSampler => {
+                    quote! {
+                        #path::DescriptorResource::CombinedImageSampler(
+                            self.#field.0.handle,
+                            self.#field.1
+                        )
+                    }
+                }
+                DescriptorType::InputAttachment => {
+                    quote! {
+                        #path::DescriptorResource::InputAttachment(self.#field.handle)
+                    }
+                }
             };
             quote! {
                  #path::Binding {
@@ -49,6 +86,14 @@ fn gen_descriptor_info(ident: &Ident, input: &DataStruct) -> TokenStream {
         let ty = match desc.ty {
             DescriptorType::Storage => quote! {#path::DescriptorType::Storage},
             DescriptorType::Uniform => quote! {#path::DescriptorType::Uniform},
+            DescriptorType::UniformDynamic => quote! {#path::DescriptorType::UniformDynamic},
+            DescriptorType::StorageImage => quote! {#path::DescriptorType::StorageImage},
+            DescriptorType::SampledImage => quote! {#path::DescriptorType::SampledImage},
+            DescriptorType::Sampler => quote! {#path::DescriptorType::Sampler},
+            DescriptorType::CombinedImageSampler => {
+                quote! {#path::DescriptorType::CombinedImageSampler}
+            }
+            DescriptorType::InputAttachment => quote! {#path::DescriptorType::InputAttachment},
         };
         quote! {
             #path::Binding {
@@ -83,6 +128,12 @@ struct Descriptor {
 enum DescriptorType {
     Storage,
     Uniform,
+    UniformDynamic,
+    StorageImage,
+    SampledImage,
+    Sampler,
+    CombinedImageSampler,
+    InputAttachment,
 }
 impl DescriptorType {
     pub fn from_meta(meta: &Meta) -> Self {
@@ -98,6 +149,12 @@ impl DescriptorType {
                         match name.as_str() {
                             "Storage" => DescriptorType::Storage,
                             "Uniform" => DescriptorType::Uniform,
+                            "UniformDynamic" => DescriptorType::UniformDynamic,
+                            "StorageImage" => DescriptorType::StorageImage,
+                            "SampledImage" => DescriptorType::SampledImage,
+                            "Sampler" => DescriptorType::Sampler,
+                            "CombinedImageSampler" => DescriptorType::CombinedImageSampler,
+                            "InputAttachment" => DescriptorType::InputAttachment,
                             _ => panic!("Unknown type"),
                         }
                     }
@@ -170,3 +227,226 @@ fn gen_vertex_input(ident: &Ident, input: &DataStruct) -> TokenStream {
         }
     }
 }
+
+#[derive(Debug, Copy, Clone)]
+enum Layout {
+    Std140,
+    Std430,
+}
+impl Layout {
+    fn name(self) -> &'static str {
+        match self {
+            Layout::Std140 => "Std140",
+            Layout::Std430 => "Std430",
+        }
+    }
+}
+
+/// Arrays of scalars, vec2 and vec3 have a stride of 16 bytes in std140, so they
+/// don't match the Rust layout and fail to compile:
+///
+/// ```compile_fail
+/// #[macro_use]
+/// extern crate tephra_derive;
+/// extern crate tephra;
+///
+/// #[derive(Std140)]
+/// #[repr(C)]
+/// struct Weights {
+///     weights: [f32; 8],
+/// }
+/// # fn main() {}
+/// ```
+#[proc_macro_derive(Std140, attributes(padding))]
+pub fn derive_std140(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
+    let input: DeriveInput = syn::parse(input).unwrap();
+    gen_layout(&input, Layout::Std140).into()
+}
+
+#[proc_macro_derive(Std430, attributes(padding))]
+pub fn derive_std430(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
+    let input: DeriveInput = syn::parse(input).unwrap();
+    gen_layout(&input, Layout::Std430).into()
+}
+
+fn has_repr_c(attrs: &[Attribute]) -> bool {
+    attrs
+        .iter()
+        .filter_map(|attr| attr.interpret_meta())
+        .any(|meta| {
+            match meta {
+                Meta::List(list) => {
+                    list.ident == "repr" && list.nested.iter().any(|nested| {
+                        match nested {
+                            NestedMeta::Meta(Meta::Word(word)) => word == "C",
+                            _ => false,
+                        }
+                    })
+                }
+                _ => false,
+            }
+        })
+}
+
+fn is_padding(field: &Field) -> bool {
+    field
+        .attrs
+        .iter()
+        .filter_map(|attr| attr.interpret_meta())
+        .any(|meta| meta.name() == "padding")
+}
+
+/// Arrays of up to four scalars are vectors, every other array uses the array rules
+fn is_vector(array: &TypeArray) -> bool {
+    let is_scalar = match &*array.elem {
+        Type::Path(path) => {
+            path.qself.is_none()
+                && path.path.segments.len() == 1
+                && ["f32", "i32", "u32"]
+                    .iter()
+                    .any(|scalar| path.path.segments[0].ident == scalar)
+        }
+        _ => false,
+    };
+    let len = match &array.len {
+        Expr::Lit(ExprLit {
+            lit: Lit::Int(len), ..
+        }) => len.value(),
+        _ => return false,
+    };
+    is_scalar && len <= 4
+}
+
+/// Computes the offset of every field twice, once with the `repr(C)` rules and
+/// once with the GLSL rules, and fails to compile if they disagree.
+fn gen_layout(input: &DeriveInput, layout: Layout) -> TokenStream {
+    let ident = &input.ident;
+    let data = match &input.data {
+        Data::Struct(_struct) => _struct,
+        _ => panic!("Must be a struct"),
+    };
+    if !has_repr_c(&input.attrs) {
+        panic!("{} requires #[repr(C)] on {}", layout.name(), ident);
+    }
+    let path = quote! {tephra::layout};
+    let layout_trait = Ident::new(layout.name(), Span::call_site());
+    let layout_upper = layout.name().to_uppercase();
+
+    let mut items = Vec::new();
+    let mut aligns = Vec::new();
+    let mut offsets = Vec::new();
+    let mut prev_field: Option<(Ident, &Type)> = None;
+    let mut prev_end: Option<Ident> = None;
+    for (idx, field) in data.fields.iter().enumerate() {
+        let ty = &field.ty;
+        let field_name = field.ident.as_ref().expect("name");
+        let rust = Ident::new(&format!("RUST_OFFSET_{}", idx), Span::call_site());
+        let end = Ident::new(&format!("END_{}", idx), Span::call_site());
+        let rust_expr = match &prev_field {
+            Some((prev_rust, prev_ty)) => {
+                quote! {
+                    #path::align_up(#prev_rust + size_of::<#prev_ty>(), align_of::<#ty>())
+                }
+            }
+            None => quote! {0},
+        };
+        let prev_end_expr = match &prev_end {
+            Some(prev_end) => quote! {#prev_end},
+            None => quote! {0},
+        };
+        items.push(quote! {
+            const #rust: usize = #rust_expr;
+        });
+        if is_padding(field) {
+            // Padding only exists on the Rust side
+            items.push(quote! {
+                const #end: usize = #prev_end_expr;
+            });
+        } else {
+            let offset = Ident::new(&format!("OFFSET_{}", idx), Span::call_site());
+            let check = Ident::new(
+                &format!("FIELD_{}_IS_MISALIGNED_FOR_{}", field_name, layout_upper),
+                field_name.span(),
+            );
+            let align = Ident::new(&format!("ALIGN_{}", idx), Span::call_site());
+            let size = Ident::new(&format!("SIZE_{}", idx), Span::call_site());
+            match ty {
+                Type::Array(array) if !is_vector(array) => {
+                    let elem = &array.elem;
+                    let len = &array.len;
+                    let elem_align = quote! {<#elem as #path::#layout_trait>::ALIGN};
+                    // Array elements are rounded up to the alignment of a vec4 in std140
+                    let array_align = match layout {
+                        Layout::Std140 => quote! {#path::align_up(#elem_align, 16)},
+                        Layout::Std430 => elem_align,
+                    };
+                    let stride = Ident::new(&format!("STRIDE_{}", idx), Span::call_site());
+                    let stride_check = Ident::new(
+                        &format!(
+                            "FIELD_{}_HAS_A_DIFFERENT_ARRAY_STRIDE_IN_{}",
+                            field_name, layout_upper
+                        ),
+                        field_name.span(),
+                    );
+                    items.push(quote! {
+                        const #align: usize = #array_align;
+                        const #stride: usize = #path::align_up(
+                            <#elem as #path::#layout_trait>::SIZE,
+                            #align
+                        );
+                        const #size: usize = #stride * (#len);
+                    });
+                    items.push(quote_spanned! {field_name.span()=>
+                        const #stride_check: [(); 0] =
+                            [(); (#stride != size_of::<#elem>()) as usize];
+                    });
+                }
+                _ => {
+                    items.push(quote! {
+                        const #align: usize = <#ty as #path::#layout_trait>::ALIGN;
+                        const #size: usize = <#ty as #path::#layout_trait>::SIZE;
+                    });
+                }
+            }
+            items.push(quote! {
+                const #offset: usize = #path::align_up(#prev_end_expr, #align);
+                const #end: usize = #offset + #size;
+            });
+            items.push(quote_spanned! {field_name.span()=>
+                const #check: [(); 0] = [(); (#rust != #offset) as usize];
+            });
+            aligns.push(quote! {#align});
+            offsets.push(offset);
+        }
+        prev_field = Some((rust, ty));
+        prev_end = Some(end);
+    }
+    let max_align = aligns.iter().fold(quote! {1}, |acc, align| {
+        quote! {#path::max(#acc, #align)}
+    });
+    // Structs are rounded up to the alignment of a vec4 in std140
+    let struct_align = match layout {
+        Layout::Std140 => quote! {#path::align_up(#max_align, 16)},
+        Layout::Std430 => max_align,
+    };
+    let size_check = Ident::new(
+        &format!("SIZE_OF_{}_IS_NOT_A_MULTIPLE_OF_ITS_{}_ALIGNMENT", ident, layout_upper),
+        ident.span(),
+    );
+    quote! {
+        #[allow(non_upper_case_globals, non_snake_case, dead_code)]
+        const _: () = {
+            use std::mem::{align_of, size_of};
+            #(#items)*
+            const STRUCT_ALIGN: usize = #struct_align;
+            const #size_check: [(); 0] = [(); (size_of::<#ident>() % STRUCT_ALIGN != 0) as usize];
+            unsafe impl #path::#layout_trait for #ident {
+                const ALIGN: usize = STRUCT_ALIGN;
+                const SIZE: usize = size_of::<#ident>();
+                fn member_offsets() -> Vec<u32> {
+                    vec![#(#offsets as u32),*]
+                }
+            }
+        };
+    }
+}
@@ -0,0 +1,103 @@
+#[macro_use]
+extern crate tephra_derive;
+extern crate tephra;
+
+use tephra::layout::{BlockCheck, LayoutError, Std140, Std430};
+use tephra::reflect::BlockLayout;
+
+#[derive(Std140)]
+#[repr(C)]
+struct Light {
+    position: [f32; 3],
+    // A scalar fills the gap after a vec3
+    intensity: f32,
+    color: [f32; 4],
+}
+
+#[derive(Std140)]
+#[repr(C)]
+struct Padded {
+    scale: f32,
+    #[padding]
+    _padding: [f32; 3],
+    offset: [f32; 4],
+}
+
+#[derive(Std430)]
+#[repr(C)]
+struct Particle {
+    position: [f32; 2],
+    velocity: [f32; 2],
+    rotation: [[f32; 2]; 2],
+}
+
+#[derive(Std140)]
+#[repr(C)]
+struct Palette {
+    colors: [[f32; 4]; 3],
+    count: u32,
+    #[padding]
+    _padding: [u32; 3],
+}
+
+#[derive(Std430)]
+#[repr(C)]
+struct Weights {
+    weights: [f32; 5],
+    #[padding]
+    _padding: f32,
+    offsets: [[f32; 2]; 3],
+}
+
+#[test]
+fn std140_offsets() {
+    assert_eq!(<Light as Std140>::member_offsets(), vec![0, 12, 16]);
+    assert_eq!(<Light as Std140>::ALIGN, 16);
+    assert_eq!(<Light as Std140>::SIZE, 32);
+}
+
+#[test]
+fn std140_skips_padding() {
+    assert_eq!(<Padded as Std140>::member_offsets(), vec![0, 16]);
+    assert_eq!(<Padded as Std140>::SIZE, 32);
+}
+
+#[test]
+fn std430_offsets() {
+    assert_eq!(<Particle as Std430>::member_offsets(), vec![0, 8, 16]);
+    assert_eq!(<Particle as Std430>::ALIGN, 8);
+    assert_eq!(<Particle as Std430>::SIZE, 32);
+}
+
+#[test]
+fn array_offsets() {
+    assert_eq!(<Palette as Std140>::member_offsets(), vec![0, 48]);
+    assert_eq!(<Palette as Std140>::SIZE, 64);
+    assert_eq!(<Weights as Std430>::member_offsets(), vec![0, 24]);
+    assert_eq!(<Weights as Std430>::ALIGN, 8);
+    assert_eq!(<Weights as Std430>::SIZE, 48);
+}
+
+#[test]
+fn block_check_compares_offsets() {
+    let check = BlockCheck::std140::<Light>(0, 1);
+    let block = |offsets| {
+        BlockLayout {
+            set: 0,
+            binding: 1,
+            offsets,
+        }
+    };
+    assert!(check.check(&[block(vec![0, 12, 16])]).is_ok());
+    match check.check(&[block(vec![0, 16, 32])]) {
+        Err(LayoutError::OffsetMismatch { shader, rust, .. }) => {
+            assert_eq!(shader, vec![0, 16, 32]);
+            assert_eq!(rust, vec![0, 12, 16]);
+        }
+        result => panic!("Expected an offset mismatch, got {:?}", result),
+    }
+    match BlockCheck::std140::<Light>(1, 0).check(&[block(vec![0, 12, 16])]) {
+        Err(LayoutError::MissingBlock { set: 1, binding: 0 }) => (),
+        result => panic!("Expected a missing block, got {:?}", result),
+    }
+}
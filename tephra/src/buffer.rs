@@ -1,4 +1,5 @@
 use crate::context::Context;
+use bitflags::bitflags;
 use std::marker::PhantomData;
 use std::mem::size_of;
 
@@ -12,8 +13,33 @@ pub trait BufferApi {
         size: u64,
     ) -> Result<BufferHandle, BufferError>;
     fn destroy(&self, buffer: BufferHandle);
+    /// Destroys the buffer once every command list that was submitted before this
+    /// call has finished and every command list that exists right now has been
+    /// dropped. Until then the handle stays valid for those command lists.
+    fn destroy_deferred(&self, buffer: BufferHandle);
+    /// Copies `size` bytes on the device and waits until the copy has finished.
+    fn copy_buffer(
+        &self,
+        src: BufferHandle,
+        src_offset: u64,
+        dst: BufferHandle,
+        dst_offset: u64,
+        size: u64,
+    );
     unsafe fn map_memory(&self, buffer: BufferHandle) -> Result<*mut (), MappingError>;
     unsafe fn unmap_memory(&self, buffer: BufferHandle);
+    /// Makes host writes visible to the device. This is a no-op for coherent memory.
+    ///
+    /// # Safety
+    ///
+    /// The buffer has to be mapped.
+    unsafe fn flush(&self, buffer: BufferHandle) -> Result<(), MappingError>;
+    /// Makes device writes visible to the host. This is a no-op for coherent memory.
+    ///
+    /// # Safety
+    ///
+    /// The buffer has to be mapped.
+    unsafe fn invalidate(&self, buffer: BufferHandle) -> Result<(), MappingError>;
     unsafe fn size(&self, buffer: BufferHandle) -> u64;
 }
 
@@ -21,6 +47,8 @@ pub trait BufferApi {
 pub enum AllocationError {
     #[fail(display = "Unsupported memory type")]
     UnsupportedMemorytype,
+    #[fail(display = "Not enough space left in the allocation")]
+    OutOfSpace,
 }
 #[derive(Debug, Fail)]
 pub enum MappingError {
@@ -28,6 +56,8 @@ pub enum MappingError {
     OutOfRange,
     #[fail(display = "Failed to map memory")]
     Failed,
+    #[fail(display = "Failed to flush or invalidate mapped memory")]
+    SyncFailed,
 }
 
 #[derive(Debug, Fail)]
@@ -40,15 +70,38 @@ pub enum BufferError {
 
 pub enum HostVisible {}
 pub enum DeviceLocal {}
+pub enum Upload {}
+pub enum Readback {}
 
 pub trait BufferProperty {
     fn property() -> Property;
 }
 
-#[derive(Copy, Clone)]
+/// Describes what the memory of a resource is used for. The backend picks the
+/// best matching memory type and falls back to a more general one if the device
+/// doesn't offer it.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub enum Property {
     HostVisible,
     DeviceLocal,
+    /// CPU -> GPU transfers. Prefers device local memory that is host visible (BAR)
+    /// and falls back to coherent host memory.
+    Upload,
+    /// GPU -> CPU transfers. Prefers host cached memory.
+    Readback,
+    /// Transient attachments that never leave the tile memory. Prefers lazily
+    /// allocated memory and falls back to device local memory.
+    Transient,
+}
+
+impl Property {
+    /// Returns true if memory of this class can always be mapped
+    pub fn is_host_visible(self) -> bool {
+        match self {
+            Property::HostVisible | Property::Upload | Property::Readback => true,
+            Property::DeviceLocal | Property::Transient => false,
+        }
+    }
 }
 
 impl BufferProperty for HostVisible {
@@ -62,12 +115,37 @@ impl BufferProperty for DeviceLocal {
         Property::DeviceLocal
     }
 }
+
+impl BufferProperty for Upload {
+    fn property() -> Property {
+        Property::Upload
+    }
+}
+
+impl BufferProperty for Readback {
+    fn property() -> Property {
+        Property::Readback
+    }
+}
 #[derive(Copy, Clone)]
 pub struct Buffer<T> {
     pub _m: PhantomData<T>,
     pub buffer: BufferHandle,
 }
 
+/// The first `len` elements of a buffer
+#[derive(Copy, Clone)]
+pub struct BufferSlice<T> {
+    pub buffer: Buffer<T>,
+    pub len: u64,
+}
+
+impl<T> BufferSlice<T> {
+    pub fn size(&self) -> u64 {
+        self.len * size_of::<T>() as u64
+    }
+}
+
 impl<T: Copy> Buffer<T> {
     pub fn len(&self, ctx: &Context) -> u32 {
         unsafe { (ctx.size(self.buffer) / size_of::<T>() as u64) as u32 }
@@ -94,8 +172,28 @@ impl<T: Copy> Buffer<T> {
                 .map_err(BufferError::MappingError)?;
             let slice = unsafe { from_raw_parts_mut::<T>(mapping_ptr as *mut T, data.len()) };
             slice.copy_from_slice(data);
+            // Unmap before returning a failed flush
+            let flushed = ctx.flush(self.buffer);
             ctx.unmap_memory(self.buffer);
-            Ok(())
+            flushed.map_err(BufferError::MappingError)
+        }
+    }
+
+    /// Copies the content of the buffer back to the host. The buffer should
+    /// be allocated with [`Property::Readback`].
+    pub fn read(&self, ctx: &Context) -> Result<Vec<T>, BufferError> {
+        use std::slice::from_raw_parts;
+        unsafe {
+            let len = self.len(ctx) as usize;
+            let mapping_ptr = ctx
+                .map_memory(self.buffer)
+                .map_err(BufferError::MappingError)?;
+            // The memory has to be unmapped even if the invalidate failed
+            let data = ctx
+                .invalidate(self.buffer)
+                .map(|_| from_raw_parts::<T>(mapping_ptr as *const T, len).to_vec());
+            ctx.unmap_memory(self.buffer);
+            data.map_err(BufferError::MappingError)
         }
     }
 
@@ -113,16 +211,25 @@ impl<T: Copy> Buffer<T> {
                 .map_err(BufferError::MappingError)?;
             let slice = unsafe { from_raw_parts_mut::<T>(mapping_ptr as *mut T, data.len()) };
             slice.copy_from_slice(data);
+            let flushed = ctx.flush(buffer.buffer);
             ctx.unmap_memory(buffer.buffer);
+            if let Err(err) = flushed {
+                ctx.destroy(buffer.buffer);
+                return Err(BufferError::MappingError(err));
+            }
             Ok(buffer)
         }
     }
 }
 
-#[derive(Copy, Clone)]
-pub enum BufferUsage {
-    Vertex,
-    Index,
-    Uniform,
-    Storage,
+bitflags! {
+    pub struct BufferUsage: u32 {
+        const VERTEX = 1 << 0;
+        const INDEX = 1 << 1;
+        const UNIFORM = 1 << 2;
+        const STORAGE = 1 << 3;
+        const INDIRECT = 1 << 4;
+        const UNIFORM_TEXEL = 1 << 5;
+        const STORAGE_TEXEL = 1 << 6;
+    }
 }
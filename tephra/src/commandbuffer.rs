@@ -1,9 +1,16 @@
 use crate::{
-    buffer::{Buffer, BufferHandle},
+    buffer::{Buffer, BufferHandle, BufferSlice},
+    context::Context,
     descriptor::{DescriptorType, Pool},
-    image::ImageHandle,
-    pipeline::{ComputePipeline, GraphicsPipeline, GraphicsPipelineState},
-    renderpass::{Framebuffer, Renderpass, VertexInput, VertexInputData},
+    image::{
+        ClearColor, ClearDepthStencil, Image, ImageCopy, ImageError, ImageHandle, ImageResource,
+        ImageView,
+    },
+    pipeline::{ComputePipeline, DepthBias, DynamicState, GraphicsPipeline, GraphicsPipelineState},
+    renderpass::{ClearValue, Framebuffer, Renderpass, VertexInput, VertexInputData},
+    ring::RingSlice,
+    sampler::{Filter, Sampler},
+    Rect, Viewport,
 };
 use bitflags::bitflags;
 use smallvec::SmallVec;
@@ -53,16 +60,51 @@ impl DescriptorSetBuilder {
         self.space
     }
 }
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, Hash, Eq, PartialEq)]
 pub enum ShaderResource {
     Buffer(BufferHandle),
-    Image(ImageHandle),
+    /// A buffer that is only visible in `range` bytes from the start. Used by dynamic
+    /// descriptors where the offset is provided at bind time.
+    BufferRange(BufferHandle, u64),
+    Image(ImageResource),
+    Sampler(Sampler),
+    CombinedImageSampler(ImageResource, Sampler),
+}
+impl From<Image> for ShaderResource {
+    fn from(image: Image) -> ShaderResource {
+        ShaderResource::Image(image.into())
+    }
+}
+impl From<ImageView> for ShaderResource {
+    fn from(view: ImageView) -> ShaderResource {
+        ShaderResource::Image(view.into())
+    }
+}
+impl From<Sampler> for ShaderResource {
+    fn from(sampler: Sampler) -> ShaderResource {
+        ShaderResource::Sampler(sampler)
+    }
+}
+impl From<(Image, Sampler)> for ShaderResource {
+    fn from((image, sampler): (Image, Sampler)) -> ShaderResource {
+        ShaderResource::CombinedImageSampler(image.into(), sampler)
+    }
+}
+impl From<(ImageView, Sampler)> for ShaderResource {
+    fn from((view, sampler): (ImageView, Sampler)) -> ShaderResource {
+        ShaderResource::CombinedImageSampler(view.into(), sampler)
+    }
 }
 impl<T> From<Buffer<T>> for ShaderResource {
     fn from(buffer: Buffer<T>) -> ShaderResource {
         ShaderResource::Buffer(buffer.buffer)
     }
 }
+impl<T> From<BufferSlice<T>> for ShaderResource {
+    fn from(slice: BufferSlice<T>) -> ShaderResource {
+        ShaderResource::BufferRange(slice.buffer.buffer, slice.size())
+    }
+}
 
 #[derive(Copy, Clone, Debug, Hash, Eq, PartialEq)]
 pub enum Access {
@@ -86,6 +128,8 @@ pub type ShaderResources = StackVec<ShaderResource>;
 pub struct Descriptor {
     pub resources: ShaderResources,
     pub views: ShaderViews,
+    /// (binding, offset) pairs for dynamic descriptors
+    pub dynamic_offsets: StackVec<(u32, u32)>,
 }
 
 pub struct DescriptorBuilder {
@@ -111,6 +155,21 @@ impl DescriptorBuilder {
         self.descriptor.resources.push(shader_resource.into());
         self
     }
+    /// Binds a slice of a [`RingBuffer`](crate::ring::RingBuffer) as a dynamic uniform
+    /// buffer. Descriptors that only differ in their offsets share the same descriptor set.
+    pub fn with_dynamic<T>(mut self, slice: RingSlice<T>, binding: u32, access: Access) -> Self {
+        let view = ShaderView {
+            binding,
+            ty: DescriptorType::UniformDynamic,
+            access,
+        };
+        self.descriptor.views.push(view);
+        self.descriptor
+            .resources
+            .push(ShaderResource::BufferRange(slice.buffer, slice.range()));
+        self.descriptor.dynamic_offsets.push((binding, slice.offset));
+        self
+    }
 }
 impl Descriptor {
     pub fn builder() -> DescriptorBuilder {
@@ -118,6 +177,12 @@ impl Descriptor {
             descriptor: Descriptor::default(),
         }
     }
+    /// Dynamic offsets in the order that is expected at bind time
+    pub fn dynamic_offsets(&self) -> StackVec<u32> {
+        let mut offsets = self.dynamic_offsets.clone();
+        offsets.sort_by_key(|&(binding, _)| binding);
+        offsets.into_iter().map(|(_, offset)| offset).collect()
+    }
 }
 
 // TODO: Implement properly
@@ -129,16 +194,69 @@ pub struct PipelineInfo {
 pub struct CopyImage {
     pub src: ImageHandle,
     pub dst: ImageHandle,
+    pub regions: Vec<ImageCopy>,
 }
 
-pub struct DrawCommand {
-    pub graphics_pipeline: GraphicsPipeline,
+pub struct BlitImage {
+    pub src: ImageHandle,
+    pub dst: ImageHandle,
+    pub regions: Vec<ImageCopy>,
+    pub filter: Filter,
+}
+
+pub struct ClearColorImage {
+    pub image: ImageResource,
+    pub color: ClearColor,
+}
+
+pub struct ClearDepthStencilImage {
+    pub image: ImageResource,
+    pub value: ClearDepthStencil,
+}
+
+pub struct FillBuffer {
+    pub buffer: BufferHandle,
+    /// Offset and size in bytes, both multiples of 4
+    pub offset: u64,
+    pub size: Option<u64>,
+    pub data: u32,
+}
+
+/// A command that is recorded inside of a renderpass. Bound state stays bound until
+/// it is replaced or the renderpass ends.
+pub enum RenderCommand {
+    BindPipeline(GraphicsPipeline),
+    BindDescriptors(Box<DescriptorSet>),
+    BindVertexBuffer(BufferHandle),
+    BindIndexBuffer(BufferHandle),
+    Draw {
+        vertices: Range<u32>,
+        instances: Range<u32>,
+    },
+    DrawIndexed {
+        indices: Range<u32>,
+        instances: Range<u32>,
+    },
+    SetViewport(Viewport),
+    SetScissor(Rect),
+    SetLineWidth(f32),
+    SetDepthBias(DepthBias),
+    SetBlendConstants([f32; 4]),
+    SetDepthBounds { min: f32, max: f32 },
+    /// Stencil values apply to front and back faces
+    SetStencilCompareMask(u32),
+    SetStencilWriteMask(u32),
+    SetStencilReference(u32),
+    /// Moves on to the next subpass, which needs its own pipeline
+    NextSubpass,
+}
+
+pub struct RenderpassCommand {
     pub renderpass: Renderpass,
     pub framebuffer: Framebuffer,
-    pub vertex: BufferHandle,
-    pub index: Buffer<u32>,
-    pub shader_arguments: DescriptorSet,
-    pub range: Range<u32>,
+    /// One value per attachment index, the clear values of the renderpass if empty
+    pub clear_values: Vec<ClearValue>,
+    pub commands: Vec<RenderCommand>,
 }
 
 pub struct DispatchCommand {
@@ -175,13 +293,20 @@ pub struct Submit {
     pub commands: Vec<Command>,
 }
 
+/// Buffers that are destroyed with
+/// [`destroy_deferred`](crate::buffer::BufferApi::destroy_deferred) stay valid until
+/// every command list that existed at that point has been dropped
 pub struct CommandList {
     pub submits: Vec<Submit>,
+    ctx: Context,
+    epoch: u64,
 }
 impl CommandList {
-    pub fn new() -> Self {
+    pub fn new(ctx: &Context) -> Self {
         CommandList {
             submits: Vec::new(),
+            ctx: ctx.clone(),
+            epoch: ctx.begin_command_list(),
         }
     }
     pub fn record<'a, Q>(&'a mut self) -> RecordCommandList<'a, Q> {
@@ -192,6 +317,12 @@ impl CommandList {
         }
     }
 }
+
+impl Drop for CommandList {
+    fn drop(&mut self) {
+        self.ctx.end_command_list(self.epoch);
+    }
+}
 pub struct RecordCommandList<'a, Q> {
     command_list: &'a mut CommandList,
     commands: Vec<Command>,
@@ -209,12 +340,51 @@ where
         };
         self.command_list.submits.push(submit);
     }
+
+    pub fn copy_image(mut self, src: Image, dst: Image, regions: &[ImageCopy]) -> Self {
+        let cmd = CopyImage {
+            src: src.handle,
+            dst: dst.handle,
+            regions: regions.to_vec(),
+        };
+        self.commands.push(Command::CopyImage(Box::new(cmd)));
+        self
+    }
+
+    /// Fills the whole buffer with copies of `data`. Fills on transfer queues need
+    /// Vulkan 1.1 or `VK_KHR_maintenance1`.
+    pub fn fill_buffer<T>(self, buffer: Buffer<T>, data: u32) -> Self {
+        self.fill_buffer_range(buffer, 0, None, data)
+    }
+
+    /// Fills `size` bytes starting at `offset` with copies of `data`, or everything
+    /// after `offset` without a size. Both need to be a multiple of 4.
+    pub fn fill_buffer_range<T>(
+        mut self,
+        buffer: Buffer<T>,
+        offset: u64,
+        size: Option<u64>,
+        data: u32,
+    ) -> Self {
+        assert!(
+            offset.is_multiple_of(4) && size.is_none_or(|size| size.is_multiple_of(4)),
+            "Fills need a 4 byte aligned offset and size"
+        );
+        let cmd = FillBuffer {
+            buffer: buffer.buffer,
+            offset,
+            size,
+            data,
+        };
+        self.commands.push(Command::FillBuffer(Box::new(cmd)));
+        self
+    }
 }
 impl RecordCommandList<'_, Transfer> {}
 
-impl RecordCommandList<'_, Graphics> {
+impl<'a> RecordCommandList<'a, Graphics> {
     pub fn draw_indexed<Vertex>(
-        mut self,
+        self,
         graphics_pipeline: GraphicsPipeline,
         renderpass: Renderpass,
         framebuffer: Framebuffer,
@@ -226,20 +396,111 @@ impl RecordCommandList<'_, Graphics> {
     where
         Vertex: VertexInput,
     {
-        let cmd = DrawCommand {
+        self.draw_indexed_with_clears(
             graphics_pipeline,
             renderpass,
             framebuffer,
             shader_arguments,
-            vertex: vertex_buffer.buffer,
-            index: index_buffer,
+            vertex_buffer,
+            index_buffer,
             range,
+            &[],
+        )
+    }
+
+    /// Like `draw_indexed`, but clears with `clear_values` instead of the clear values
+    /// of the renderpass. There has to be one value for every attachment index.
+    #[allow(clippy::too_many_arguments)]
+    pub fn draw_indexed_with_clears<Vertex>(
+        self,
+        graphics_pipeline: GraphicsPipeline,
+        renderpass: Renderpass,
+        framebuffer: Framebuffer,
+        shader_arguments: DescriptorSet,
+        vertex_buffer: Buffer<Vertex>,
+        index_buffer: Buffer<u32>,
+        range: Range<u32>,
+        clear_values: &[ClearValue],
+    ) -> Self
+    where
+        Vertex: VertexInput,
+    {
+        self.begin_renderpass(renderpass, framebuffer, clear_values)
+            .bind_pipeline(graphics_pipeline)
+            .bind_descriptors(shader_arguments)
+            .bind_vertex_buffer(vertex_buffer)
+            .bind_index_buffer(index_buffer)
+            .draw_indexed(range, 0..1)
+            .end_renderpass()
+    }
+
+    /// Starts recording draws into `framebuffer`. An empty `clear_values` uses the
+    /// clear values of the renderpass.
+    pub fn begin_renderpass(
+        self,
+        renderpass: Renderpass,
+        framebuffer: Framebuffer,
+        clear_values: &[ClearValue],
+    ) -> RenderpassEncoder<'a> {
+        let cmd = RenderpassCommand {
+            renderpass,
+            framebuffer,
+            clear_values: clear_values.to_vec(),
+            commands: Vec::new(),
+        };
+        RenderpassEncoder { list: self, cmd }
+    }
+
+    /// Scales the source regions into the destination regions
+    pub fn blit_image(
+        mut self,
+        src: Image,
+        dst: Image,
+        regions: &[ImageCopy],
+        filter: Filter,
+    ) -> Self {
+        let cmd = BlitImage {
+            src: src.handle,
+            dst: dst.handle,
+            regions: regions.to_vec(),
+            filter,
+        };
+        self.commands.push(Command::BlitImage(Box::new(cmd)));
+        self
+    }
+
+    /// Fills every mip level of `image` from the level above it
+    pub fn generate_mips(mut self, image: Image) -> Self {
+        self.commands.push(Command::GenerateMips(image.handle));
+        self
+    }
+
+    /// Clears every subresource of a color image, or those of a view
+    pub fn clear_color_image<I: Into<ImageResource>>(self, image: I, color: ClearColor) -> Self {
+        self.push_clear_color(image.into(), color)
+    }
+
+    /// Clears every subresource of a depth or stencil image, or those of a view
+    pub fn clear_depth_stencil_image<I: Into<ImageResource>>(
+        mut self,
+        image: I,
+        value: ClearDepthStencil,
+    ) -> Self {
+        let cmd = ClearDepthStencilImage {
+            image: image.into(),
+            value,
         };
-        self.commands.push(Command::Draw(Box::new(cmd)));
+        self.commands
+            .push(Command::ClearDepthStencilImage(Box::new(cmd)));
         self
     }
 }
 impl RecordCommandList<'_, Compute> {
+    /// Compute queues can clear color images, but not depth or stencil images
+    pub fn clear_color_image<I: Into<ImageResource>>(self, image: I, color: ClearColor) -> Self {
+        self.push_clear_color(image.into(), color)
+    }
+
     pub fn dispatch(
         mut self,
         pipeline: ComputePipeline,
@@ -260,11 +521,173 @@ impl RecordCommandList<'_, Compute> {
     }
 }
 
+impl<Q> RecordCommandList<'_, Q> {
+    fn push_clear_color(mut self, image: ImageResource, color: ClearColor) -> Self {
+        let cmd = ClearColorImage { image, color };
+        self.commands.push(Command::ClearColorImage(Box::new(cmd)));
+        self
+    }
+}
+
+/// Records the commands of a single renderpass, created by
+/// [`begin_renderpass`](RecordCommandList::begin_renderpass)
+pub struct RenderpassEncoder<'a> {
+    list: RecordCommandList<'a, Graphics>,
+    cmd: RenderpassCommand,
+}
+
+impl<'a> RenderpassEncoder<'a> {
+    pub fn bind_pipeline(self, graphics_pipeline: GraphicsPipeline) -> Self {
+        self.push(RenderCommand::BindPipeline(graphics_pipeline))
+    }
+
+    /// Binds every set of `shader_arguments` with the layout of the bound pipeline
+    pub fn bind_descriptors(self, shader_arguments: DescriptorSet) -> Self {
+        self.push(RenderCommand::BindDescriptors(Box::new(shader_arguments)))
+    }
+
+    pub fn bind_vertex_buffer<Vertex>(self, vertex_buffer: Buffer<Vertex>) -> Self
+    where
+        Vertex: VertexInput,
+    {
+        self.push(RenderCommand::BindVertexBuffer(vertex_buffer.buffer))
+    }
+
+    pub fn bind_index_buffer(self, index_buffer: Buffer<u32>) -> Self {
+        self.push(RenderCommand::BindIndexBuffer(index_buffer.buffer))
+    }
+
+    /// Replaces the viewport, which covers the whole framebuffer by default
+    pub fn set_viewport(self, viewport: Viewport) -> Self {
+        self.push(RenderCommand::SetViewport(viewport))
+    }
+
+    /// Discards fragments outside of `scissor`, which covers the whole framebuffer
+    /// by default
+    pub fn set_scissor(self, scissor: Rect) -> Self {
+        self.push(RenderCommand::SetScissor(scissor))
+    }
+
+    /// Needs a pipeline with `DynamicState::LineWidth`. The setters below need their
+    /// matching dynamic state as well, and every dynamic state of a pipeline has to
+    /// be set before it draws.
+    pub fn set_line_width(self, line_width: f32) -> Self {
+        self.push(RenderCommand::SetLineWidth(line_width))
+    }
+
+    pub fn set_depth_bias(self, depth_bias: DepthBias) -> Self {
+        self.push(RenderCommand::SetDepthBias(depth_bias))
+    }
+
+    pub fn set_blend_constants(self, constants: [f32; 4]) -> Self {
+        self.push(RenderCommand::SetBlendConstants(constants))
+    }
+
+    pub fn set_depth_bounds(self, min: f32, max: f32) -> Self {
+        self.push(RenderCommand::SetDepthBounds { min, max })
+    }
+
+    pub fn set_stencil_compare_mask(self, mask: u32) -> Self {
+        self.push(RenderCommand::SetStencilCompareMask(mask))
+    }
+
+    pub fn set_stencil_write_mask(self, mask: u32) -> Self {
+        self.push(RenderCommand::SetStencilWriteMask(mask))
+    }
+
+    pub fn set_stencil_reference(self, reference: u32) -> Self {
+        self.push(RenderCommand::SetStencilReference(reference))
+    }
+
+    pub fn draw(self, vertices: Range<u32>, instances: Range<u32>) -> Self {
+        self.push(RenderCommand::Draw {
+            vertices,
+            instances,
+        })
+    }
+
+    /// Draws `indices` of the bound index buffer
+    pub fn draw_indexed(self, indices: Range<u32>, instances: Range<u32>) -> Self {
+        self.push(RenderCommand::DrawIndexed { indices, instances })
+    }
+
+    /// Starts the next subpass of the renderpass. Pipelines and descriptors have to
+    /// be bound again.
+    pub fn next_subpass(self) -> Self {
+        self.push(RenderCommand::NextSubpass)
+    }
+
+    pub fn end_renderpass(mut self) -> RecordCommandList<'a, Graphics> {
+        self.list
+            .commands
+            .push(Command::Renderpass(Box::new(self.cmd)));
+        self.list
+    }
+
+    fn push(mut self, command: RenderCommand) -> Self {
+        self.cmd.commands.push(command);
+        self
+    }
+}
+
 pub enum Command {
     CopyImage(Box<CopyImage>),
-    Draw(Box<DrawCommand>),
+    ClearColorImage(Box<ClearColorImage>),
+    ClearDepthStencilImage(Box<ClearDepthStencilImage>),
+    FillBuffer(Box<FillBuffer>),
+    BlitImage(Box<BlitImage>),
+    Renderpass(Box<RenderpassCommand>),
     Dispatch(Box<DispatchCommand>),
+    GenerateMips(ImageHandle),
+}
+
+/// Errors that are found while validating a command list. Nothing of the command
+/// list has been submitted when one is returned.
+#[derive(Debug, Fail)]
+pub enum SubmitError {
+    #[fail(display = "An image command is invalid: {}", _0)]
+    Image(ImageError),
+    #[fail(
+        display = "The pipeline was created for a renderpass that isn't compatible with the \
+                   renderpass it draws in"
+    )]
+    IncompatiblePipeline,
+    #[fail(
+        display = "The pipeline was created for subpass {}, but is bound in subpass {}",
+        pipeline, subpass
+    )]
+    WrongSubpass { pipeline: u32, subpass: usize },
+    #[fail(display = "Descriptors and draws need a bound pipeline")]
+    MissingPipeline,
+    #[fail(display = "Indexed draws need a bound index buffer")]
+    MissingIndexBuffer,
+    #[fail(display = "The renderpass only has {} subpasses", _0)]
+    TooManySubpasses(usize),
+    #[fail(display = "The renderpass ended after {} of its {} subpasses", recorded, count)]
+    MissingSubpasses { recorded: usize, count: usize },
+    #[fail(display = "The bound pipeline draws without setting {:?} first", _0)]
+    MissingDynamicState(DynamicState),
+    #[fail(display = "Transfer queues can only fill buffers with VK_KHR_maintenance1")]
+    UnsupportedTransferFill,
+    #[fail(display = "Line width {} needs the wideLines feature", _0)]
+    UnsupportedLineWidth(f32),
+    #[fail(display = "Expected {} clear values, got {}", expected, actual)]
+    ClearValueCount { expected: usize, actual: usize },
+    #[fail(display = "The clear value of attachment {} doesn't match its format", _0)]
+    ClearValueMismatch(u32),
 }
+
 pub trait SubmitApi {
-    unsafe fn submit_commands(&self, pool: &mut Pool, commands: &CommandList);
+    /// Registers a new command list and returns its epoch
+    fn begin_command_list(&self) -> u64;
+    /// The command list can't be submitted anymore
+    fn end_command_list(&self, epoch: u64);
+    /// # Safety
+    ///
+    /// Every resource that the commands use has to be alive.
+    unsafe fn submit_commands(
+        &self,
+        pool: &mut Pool,
+        commands: &CommandList,
+    ) -> Result<(), SubmitError>;
 }
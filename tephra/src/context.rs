@@ -9,18 +9,35 @@ use crate::{
     image::ImageApi,
     pipeline::PipelineApi,
     renderpass::{FramebufferApi, RenderpassApi},
+    sampler::SamplerApi,
     shader::ShaderApi,
     swapchain::CreateSwapchain,
 };
 
+/// Device limits that the frontend needs to respect
+#[derive(Debug, Copy, Clone)]
+pub struct Limits {
+    pub min_uniform_buffer_offset_alignment: u64,
+    pub non_coherent_atom_size: u64,
+    /// Masks of the supported sample counts, checked with `SampleCount::is_supported`
+    pub framebuffer_color_sample_counts: u32,
+    pub framebuffer_depth_sample_counts: u32,
+}
+
+pub trait LimitsApi {
+    fn limits(&self) -> Limits;
+}
+
 pub trait ContextApi: downcast::Downcast
 where
     Self: CreateSwapchain
+        + LimitsApi
         + ShaderApi
         + DescriptorApi
         + CreatePool
         + BufferApi
         + ImageApi
+        + SamplerApi
         + RenderpassApi
         + PipelineApi
         + SubmitApi
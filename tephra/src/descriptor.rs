@@ -1,7 +1,9 @@
 use crate::{
     buffer::BufferHandle,
-    commandbuffer::{Descriptor, ShaderView, ShaderViews},
+    commandbuffer::{Descriptor, ShaderResources, ShaderView, ShaderViews},
     context::Context,
+    image::ImageHandle,
+    sampler::Sampler,
 };
 
 use std::collections::HashMap;
@@ -81,6 +83,10 @@ impl LinearPoolAllocator {
 pub struct Pool {
     ctx: Context,
     allocators: HashMap<ShaderViews, LinearPoolAllocator>,
+    // Descriptor sets that were already written since the last reset. Dynamic
+    // offsets are not part of the key, so those sets can be rebound with a
+    // different offset.
+    written: HashMap<(ShaderViews, ShaderResources), DescriptorHandle>,
 }
 
 impl Pool {
@@ -88,10 +94,15 @@ impl Pool {
         Pool {
             ctx: ctx.clone(),
             allocators: HashMap::new(),
+            written: HashMap::new(),
         }
     }
 
     pub fn allocate(&mut self, data: &Descriptor) -> DescriptorHandle {
+        let key = (data.views.clone(), data.resources.clone());
+        if let Some(&handle) = self.written.get(&key) {
+            return handle;
+        }
         let ctx = self.ctx.clone();
         let allocator = self
             .allocators
@@ -99,10 +110,12 @@ impl Pool {
             .or_insert_with(|| LinearPoolAllocator::new(&ctx, data.views.clone()));
         let handle = allocator.create_descriptor();
         ctx.write(handle, &data);
+        self.written.insert(key, handle);
         handle
     }
 
     pub fn reset(&mut self) {
+        self.written.clear();
         for allocator in self.allocators.values_mut() {
             allocator.reset();
         }
@@ -116,21 +129,37 @@ pub trait DescriptorApi {
 #[derive(Debug, Copy, Clone)]
 pub struct DescriptorSizes {
     pub buffer: u32,
+    pub dynamic_buffer: u32,
     pub storage: u32,
     pub images: u32,
+    pub sampled_images: u32,
+    pub samplers: u32,
+    pub combined_image_samplers: u32,
+    pub input_attachments: u32,
 }
 
 impl DescriptorSizes {
     pub fn from_views(views: &[ShaderView]) -> Self {
         let sizes = DescriptorSizes {
             buffer: 0,
+            dynamic_buffer: 0,
             storage: 0,
             images: 0,
+            sampled_images: 0,
+            samplers: 0,
+            combined_image_samplers: 0,
+            input_attachments: 0,
         };
         views.iter().fold(sizes, |mut acc, elem| {
             match elem.ty {
                 DescriptorType::Uniform => acc.buffer += 1,
+                DescriptorType::UniformDynamic => acc.dynamic_buffer += 1,
                 DescriptorType::Storage => acc.storage += 1,
+                DescriptorType::StorageImage => acc.images += 1,
+                DescriptorType::SampledImage => acc.sampled_images += 1,
+                DescriptorType::Sampler => acc.samplers += 1,
+                DescriptorType::CombinedImageSampler => acc.combined_image_samplers += 1,
+                DescriptorType::InputAttachment => acc.input_attachments += 1,
             }
             acc
         })
@@ -156,11 +185,24 @@ impl DescriptorInfo for () {
 #[derive(Copy, Clone, Debug, Hash, Eq, PartialEq)]
 pub enum DescriptorType {
     Uniform,
+    UniformDynamic,
     Storage,
+    StorageImage,
+    SampledImage,
+    Sampler,
+    CombinedImageSampler,
+    /// An attachment of the current renderpass, only readable in fragment shaders
+    InputAttachment,
 }
 pub enum DescriptorResource {
     Uniform(BufferHandle),
+    UniformDynamic(BufferHandle),
     Storage(BufferHandle),
+    StorageImage(ImageHandle),
+    SampledImage(ImageHandle),
+    Sampler(Sampler),
+    CombinedImageSampler(ImageHandle, Sampler),
+    InputAttachment(ImageHandle),
 }
 #[derive(Debug)]
 pub struct Binding<T> {
@@ -362,7 +362,8 @@ impl Framegraph {
     pub fn compile(mut self) -> Framegraph<Compiled> {
         unsafe {
             for (id, image_desc) in &self.state.image_data {
-                let image = Image::allocate(&self.ctx, image_desc.clone());
+                let image = Image::allocate(&self.ctx, image_desc.clone())
+                    .expect("Unable to allocate image");
                 self.registry
                     .resources
                     .insert(*id, ResourceType::Image(image));
@@ -370,7 +371,7 @@ impl Framegraph {
             for (id, (renderpass, images)) in &self.state.framebuffer_data {
                 let images: Vec<_> = images
                     .iter()
-                    .map(|&image_resource| self.registry.get_image(image_resource))
+                    .map(|&image_resource| self.registry.get_image(image_resource).into())
                     .collect();
                 let framebuffer = self.ctx.create_framebuffer(*renderpass, &images);
                 self.registry
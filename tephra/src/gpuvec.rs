@@ -0,0 +1,156 @@
+use crate::{
+    buffer::{Buffer, BufferError, BufferSlice, BufferUsage, Property},
+    context::Context,
+};
+use std::mem::size_of;
+
+/// A growable array that lives in a [`Buffer`]. Growing allocates a new buffer and
+/// copies the old content on the device.
+///
+/// Command lists that were recorded or submitted before a reallocation still
+/// reference the old buffer. Old buffers are therefore destroyed with
+/// [`destroy_deferred`](crate::buffer::BufferApi::destroy_deferred), which waits until
+/// those command lists have finished and been dropped. Dropping the vector does the
+/// same.
+pub struct GpuVec<T> {
+    ctx: Context,
+    property: Property,
+    usage: BufferUsage,
+    buffer: Buffer<T>,
+    len: u64,
+    capacity: u64,
+}
+
+impl<T: Copy> GpuVec<T> {
+    pub fn new(ctx: &Context, property: Property, usage: BufferUsage) -> Result<Self, BufferError> {
+        Self::with_capacity(ctx, property, usage, 1)
+    }
+
+    pub fn with_capacity(
+        ctx: &Context,
+        property: Property,
+        usage: BufferUsage,
+        capacity: u64,
+    ) -> Result<Self, BufferError> {
+        // Vulkan doesn't allow empty buffers
+        let capacity = capacity.max(1);
+        let buffer = Buffer::allocate(ctx, property, usage, capacity)?;
+        Ok(GpuVec {
+            ctx: ctx.clone(),
+            property,
+            usage,
+            buffer,
+            len: 0,
+            capacity,
+        })
+    }
+
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn capacity(&self) -> u64 {
+        self.capacity
+    }
+
+    /// The current buffer. It changes when the vector grows.
+    pub fn buffer(&self) -> Buffer<T> {
+        self.buffer
+    }
+
+    /// The initialized part of the buffer, used for binding
+    pub fn as_slice(&self) -> BufferSlice<T> {
+        BufferSlice {
+            buffer: self.buffer,
+            len: self.len,
+        }
+    }
+
+    pub fn push(&mut self, value: T) -> Result<(), BufferError> {
+        self.extend_from_slice(&[value])
+    }
+
+    pub fn extend_from_slice(&mut self, data: &[T]) -> Result<(), BufferError> {
+        self.reserve(data.len() as u64)?;
+        let offset = self.len;
+        self.write(offset, data)?;
+        self.len += data.len() as u64;
+        Ok(())
+    }
+
+    /// Shortens the vector. The capacity stays the same.
+    pub fn truncate(&mut self, len: u64) {
+        self.len = self.len.min(len);
+    }
+
+    pub fn clear(&mut self) {
+        self.len = 0;
+    }
+
+    /// Makes sure that at least `additional` more elements fit without reallocating
+    pub fn reserve(&mut self, additional: u64) -> Result<(), BufferError> {
+        let required = self.len + additional;
+        if required <= self.capacity {
+            return Ok(());
+        }
+        let new_capacity = required.max(self.capacity * 2);
+        let new_buffer = Buffer::allocate(&self.ctx, self.property, self.usage, new_capacity)?;
+        if self.len > 0 {
+            self.ctx.copy_buffer(
+                self.buffer.buffer,
+                0,
+                new_buffer.buffer,
+                0,
+                self.len * size_of::<T>() as u64,
+            );
+        }
+        let old_buffer = std::mem::replace(&mut self.buffer, new_buffer);
+        self.ctx.destroy_deferred(old_buffer.buffer);
+        self.capacity = new_capacity;
+        Ok(())
+    }
+
+    fn write(&self, offset: u64, data: &[T]) -> Result<(), BufferError> {
+        if data.is_empty() {
+            return Ok(());
+        }
+        if self.property.is_host_visible() {
+            use std::slice::from_raw_parts_mut;
+            unsafe {
+                let mapping_ptr = self
+                    .ctx
+                    .map_memory(self.buffer.buffer)
+                    .map_err(BufferError::MappingError)?;
+                let start = (mapping_ptr as *mut T).add(offset as usize);
+                let slice = from_raw_parts_mut::<T>(start, data.len());
+                slice.copy_from_slice(data);
+                let flushed = self.ctx.flush(self.buffer.buffer);
+                self.ctx.unmap_memory(self.buffer.buffer);
+                flushed.map_err(BufferError::MappingError)?;
+            }
+        } else {
+            let staging =
+                Buffer::from_slice(&self.ctx, Property::Upload, BufferUsage::empty(), data)?;
+            let size = size_of::<T>() as u64;
+            self.ctx.copy_buffer(
+                staging.buffer,
+                0,
+                self.buffer.buffer,
+                offset * size,
+                data.len() as u64 * size,
+            );
+            self.ctx.destroy(staging.buffer);
+        }
+        Ok(())
+    }
+}
+
+impl<T> Drop for GpuVec<T> {
+    fn drop(&mut self) {
+        self.ctx.destroy_deferred(self.buffer.buffer);
+    }
+}
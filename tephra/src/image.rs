@@ -1,20 +1,235 @@
-use crate::buffer::Buffer;
+use crate::buffer::{
+    AllocationError, Buffer, BufferError, BufferHandle, BufferUsage, Property,
+};
 use crate::context::Context;
 use crate::downcast::Downcast;
 use crate::new_typed_handle;
+use crate::sampler::Filter;
+use ::image::{DynamicImage, GrayImage, RgbaImage};
+use bitflags::bitflags;
 use derive_builder::Builder;
+use std::path::Path;
 
 new_typed_handle! {
     ImageHandle
 }
+new_typed_handle!(ImageView);
 
 //use renderpass::{Pass, Renderpass};
 #[derive(Debug, Copy, Clone)]
 pub enum ImageLayout {
     Color,
     Depth,
+    /// Read only in shaders, the image is filled with a transfer
+    Sampled,
+    /// Read and written by shaders, stays in the general layout
+    Storage,
 }
-#[derive(Debug, Copy, Clone)]
+
+bitflags! {
+    pub struct ImageUsage: u32 {
+        const TRANSFER_SRC = 1 << 0;
+        const TRANSFER_DST = 1 << 1;
+        const SAMPLED = 1 << 2;
+        const STORAGE = 1 << 3;
+        const COLOR_ATTACHMENT = 1 << 4;
+        const DEPTH_STENCIL_ATTACHMENT = 1 << 5;
+        const INPUT_ATTACHMENT = 1 << 6;
+        /// The content never leaves the tile memory
+        const TRANSIENT_ATTACHMENT = 1 << 7;
+    }
+}
+
+impl ImageUsage {
+    /// The memory class images with this usage are allocated from. Transient
+    /// attachments prefer lazily allocated memory.
+    pub fn property(self) -> Property {
+        if self.contains(ImageUsage::TRANSIENT_ATTACHMENT) {
+            Property::Transient
+        } else {
+            Property::DeviceLocal
+        }
+    }
+
+    /// The usage images had before the usage could be chosen explicitly
+    pub fn for_layout(layout: ImageLayout) -> Self {
+        let usage = match layout {
+            ImageLayout::Color => ImageUsage::COLOR_ATTACHMENT,
+            ImageLayout::Depth => ImageUsage::DEPTH_STENCIL_ATTACHMENT,
+            ImageLayout::Sampled => ImageUsage::SAMPLED,
+            ImageLayout::Storage => ImageUsage::STORAGE | ImageUsage::SAMPLED,
+        };
+        usage | ImageUsage::TRANSFER_SRC | ImageUsage::TRANSFER_DST
+    }
+}
+
+/// How the pixel data of a loaded image should be interpreted
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ColorSpace {
+    Srgb,
+    Linear,
+}
+
+impl ColorSpace {
+    pub fn rgba8_format(self) -> Format {
+        match self {
+            ColorSpace::Srgb => Format::R8G8B8A8_SRGB,
+            ColorSpace::Linear => Format::R8G8B8A8_UNORM,
+        }
+    }
+}
+
+#[derive(Debug, Fail)]
+pub enum ImageError {
+    #[fail(display = "Failed to decode image: {}", _0)]
+    Decode(::image::ImageError),
+    #[fail(display = "Expected {} bytes of pixel data, got {}", expected, actual)]
+    SizeMismatch { expected: usize, actual: usize },
+    #[fail(display = "Staging buffer failed: {}", _0)]
+    BufferError(BufferError),
+    #[fail(display = "Allocation failed with: {}", _0)]
+    AllocationError(AllocationError),
+    #[fail(display = "Format {:?} doesn't support the usage {:?}", format, usage)]
+    UnsupportedUsage { format: Format, usage: ImageUsage },
+    #[fail(display = "Format {:?} doesn't support {:?}", format, samples)]
+    UnsupportedSampleCount { format: Format, samples: SampleCount },
+    #[fail(display = "Multisampled images can't have mip levels")]
+    MultisampledMips,
+    #[fail(display = "Format {:?} can't be converted to RGBA8", _0)]
+    UnsupportedReadback(Format),
+    #[fail(display = "Multisampled images have to be resolved before they are read back")]
+    MultisampledReadback,
+    #[fail(display = "Failed to write image: {}", _0)]
+    Io(std::io::Error),
+    #[fail(
+        display = "Cube maps need square faces and six layers per cube, got {:?} and {} layers",
+        resolution, layers
+    )]
+    InvalidCube { resolution: Resolution, layers: u32 },
+    #[fail(display = "1D images need a height of 1, got {}", _0)]
+    Invalid1D(u32),
+    #[fail(display = "The device doesn't support cube map arrays")]
+    UnsupportedCubeArray,
+    #[fail(display = "Failed to read image: {}", _0)]
+    Read(std::io::Error),
+    #[fail(display = "Invalid texture container: {}", _0)]
+    InvalidContainer(&'static str),
+    #[fail(display = "The texture container uses an unsupported format: {}", _0)]
+    UnsupportedContainerFormat(String),
+    #[fail(display = "Format {:?} can't be sampled on this device", _0)]
+    UnsupportedFormat(Format),
+    #[fail(
+        display = "The view covers mips {:?} and layers {:?}, the image has {} mips and {} layers",
+        mips, layers, mip_levels, image_layers
+    )]
+    InvalidViewRange {
+        mips: std::ops::Range<u32>,
+        layers: std::ops::Range<u32>,
+        mip_levels: u32,
+        image_layers: u32,
+    },
+    #[fail(display = "Copies need the same extent and layer count, use a blit to scale images")]
+    CopyExtentMismatch,
+    #[fail(display = "{:?} can't be copied to {:?}", src, dst)]
+    IncompatibleCopyFormats { src: Format, dst: Format },
+    #[fail(display = "{:?} can't be blitted to {:?}", src, dst)]
+    UnsupportedBlit { src: Format, dst: Format },
+    #[fail(display = "{:?} doesn't support linear filtering", _0)]
+    UnsupportedLinearFilter(Format),
+    #[fail(display = "The texel size of {:?} is unknown, it can't be uploaded", _0)]
+    UnknownTexelSize(Format),
+    #[fail(display = "Multisampled images can't be blitted, resolve them instead")]
+    MultisampledBlit,
+    #[fail(display = "Mip level {} doesn't exist, the image has {} mips", mip_level, mip_levels)]
+    InvalidMipLevel { mip_level: u32, mip_levels: u32 },
+    #[fail(
+        display = "The region covers layers {:?}, the image has {} layers",
+        layers, image_layers
+    )]
+    InvalidLayerRange {
+        layers: std::ops::Range<u32>,
+        image_layers: u32,
+    },
+    #[fail(
+        display = "The region at {:?} with the extent {:?} exceeds the mip extent {:?}",
+        offset, extent, mip_extent
+    )]
+    RegionOutOfBounds {
+        offset: (i32, i32, i32),
+        extent: (u32, u32, u32),
+        mip_extent: (u32, u32, u32),
+    },
+    #[fail(display = "A view of {:?} can't reinterpret it as {:?}", image, view)]
+    IncompatibleViewFormat { image: Format, view: Format },
+    #[fail(display = "Views with a different format need an image with a mutable format")]
+    ImmutableFormat,
+    #[fail(display = "Format {:?} doesn't have the aspects {:?}", format, aspect)]
+    InvalidViewAspect { format: Format, aspect: ImageAspect },
+    #[fail(display = "Expected {} layers, got {}", expected, actual)]
+    LayerCountMismatch { expected: u32, actual: u32 },
+    #[fail(display = "Mips of {:?} can neither be blitted nor computed", _0)]
+    UnsupportedMipGeneration(Format),
+    #[fail(display = "Layer {} is {:?}, expected {:?}", layer, actual, expected)]
+    LayerResolutionMismatch {
+        layer: usize,
+        expected: Resolution,
+        actual: Resolution,
+    },
+}
+
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, Hash)]
+pub enum SampleCount {
+    #[default]
+    S1,
+    S2,
+    S4,
+    S8,
+    S16,
+    S32,
+    S64,
+}
+
+impl SampleCount {
+    pub fn count(self) -> u32 {
+        match self {
+            SampleCount::S1 => 1,
+            SampleCount::S2 => 2,
+            SampleCount::S4 => 4,
+            SampleCount::S8 => 8,
+            SampleCount::S16 => 16,
+            SampleCount::S32 => 32,
+            SampleCount::S64 => 64,
+        }
+    }
+
+    /// `supported` is a mask of sample counts, as reported in [`Limits`](crate::context::Limits)
+    pub fn is_supported(self, supported: u32) -> bool {
+        supported & self.count() != 0
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ImageDimension {
+    D1,
+    D2,
+    D3,
+    /// 2D faces in the order +X, -X, +Y, -Y, +Z, -Z. `layers` has to be a multiple
+    /// of six, more than six layers create a cube map array.
+    Cube,
+}
+
+impl ImageDimension {
+    /// Number of layers needed for `count` layers, slices or cubes
+    fn layers(self, count: u32) -> u32 {
+        match self {
+            ImageDimension::D1 | ImageDimension::D2 => count,
+            ImageDimension::D3 => 1,
+            ImageDimension::Cube => count * 6,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub struct Resolution {
     pub width: u32,
     pub height: u32,
@@ -23,10 +238,63 @@ pub struct Resolution {
 pub trait CreateImage {}
 
 pub trait ImageApi {
-    fn allocate_image(&self, desc: ImageDesc) -> ImageHandle;
-    fn from_buffer(&self, buffer: Buffer<u8>) -> ImageHandle;
+    fn allocate_image(&self, desc: ImageDesc) -> Result<ImageHandle, ImageError>;
+    /// Allocates an image and fills it with the tightly packed pixels in `buffer`.
+    /// The image is transitioned into the layout of its next use automatically.
+    fn from_buffer(
+        &self,
+        desc: ImageDesc,
+        buffer: Buffer<u8>,
+    ) -> Result<ImageHandle, ImageError>;
+    /// Like `from_buffer`, but every region describes where the layers of one mip
+    /// level start in `buffer`. Rows and layers inside a region are tightly packed.
+    #[allow(clippy::wrong_self_convention)]
+    fn from_buffer_regions(
+        &self,
+        desc: ImageDesc,
+        buffer: Buffer<u8>,
+        regions: &[BufferRegion],
+    ) -> Result<ImageHandle, ImageError>;
+    /// The usages that images with `format` support, empty if the format is unsupported
+    fn format_usage(&self, format: Format) -> ImageUsage;
     fn desc(&self, handle: ImageHandle) -> ImageDesc;
-    fn copy_image(&self, src: ImageHandle, dst: ImageHandle);
+    fn create_image_view(&self, desc: &ImageViewDesc) -> Result<ImageView, ImageError>;
+    fn destroy_image_view(&self, view: ImageView);
+    /// Copies the first mip level of every layer and waits until it has finished.
+    /// Images with different resolutions are scaled with a linear blit.
+    fn copy_image(&self, src: ImageHandle, dst: ImageHandle) -> Result<(), ImageError>;
+    /// Copies every region and waits until it has finished
+    fn copy_image_regions(
+        &self,
+        src: ImageHandle,
+        dst: ImageHandle,
+        regions: &[ImageCopy],
+    ) -> Result<(), ImageError>;
+    /// Scales the source regions into the destination regions and waits until it
+    /// has finished. Both formats need blit support, depth and stencil formats can
+    /// only be blitted to themselves with `Filter::Nearest`.
+    fn blit_image(
+        &self,
+        src: ImageHandle,
+        dst: ImageHandle,
+        regions: &[ImageCopy],
+        filter: Filter,
+    ) -> Result<(), ImageError>;
+    /// Fills every mip level from the level above it and waits until it has finished.
+    /// Fails if the format can neither be blitted nor written by a compute shader.
+    fn generate_mips(&self, image: ImageHandle) -> Result<(), ImageError>;
+    /// Copies the first mip level and layer into `buffer` and waits until it has
+    /// finished. Depth stencil images only copy the depth aspect.
+    fn copy_image_to_buffer(&self, image: ImageHandle, buffer: BufferHandle);
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct BufferRegion {
+    /// Offset in bytes into the buffer
+    pub offset: u64,
+    pub mip_level: u32,
+    pub base_layer: u32,
+    pub layer_count: u32,
 }
 
 #[derive(Copy, Clone)]
@@ -34,21 +302,600 @@ pub struct Image {
     pub handle: ImageHandle,
 }
 
+/// A box inside one mip level of a range of layers
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct ImageRegion {
+    pub mip_level: u32,
+    pub base_layer: u32,
+    pub layer_count: u32,
+    pub offset: (i32, i32, i32),
+    pub extent: (u32, u32, u32),
+}
+
+impl ImageRegion {
+    /// The whole mip level of every layer
+    pub fn mip(desc: &ImageDesc, mip_level: u32) -> Self {
+        ImageRegion {
+            mip_level,
+            base_layer: 0,
+            layer_count: desc.layers,
+            offset: (0, 0, 0),
+            extent: desc.mip_extent(mip_level),
+        }
+    }
+
+    pub fn with_layers(mut self, base_layer: u32, layer_count: u32) -> Self {
+        self.base_layer = base_layer;
+        self.layer_count = layer_count;
+        self
+    }
+
+    pub fn with_rect(mut self, offset: (i32, i32, i32), extent: (u32, u32, u32)) -> Self {
+        self.offset = offset;
+        self.extent = extent;
+        self
+    }
+}
+
+/// Copies need the same extent and layer count in both regions, blits scale the
+/// source extent to the destination extent
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct ImageCopy {
+    pub src: ImageRegion,
+    pub dst: ImageRegion,
+}
+
+/// The value a color image is cleared to. The variant has to match the numeric type
+/// of the format.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum ClearColor {
+    Float([f32; 4]),
+    Int([i32; 4]),
+    Uint([u32; 4]),
+}
+
+impl Default for ClearColor {
+    fn default() -> Self {
+        ClearColor::Float([0.0; 4])
+    }
+}
+
+impl From<[f32; 4]> for ClearColor {
+    fn from(color: [f32; 4]) -> ClearColor {
+        ClearColor::Float(color)
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct ClearDepthStencil {
+    pub depth: f32,
+    pub stencil: u32,
+}
+
+impl Default for ClearDepthStencil {
+    fn default() -> Self {
+        ClearDepthStencil {
+            depth: 1.0,
+            stencil: 0,
+        }
+    }
+}
+
+bitflags! {
+    pub struct ImageAspect: u32 {
+        const COLOR = 1 << 0;
+        const DEPTH = 1 << 1;
+        const STENCIL = 1 << 2;
+    }
+}
+
+impl ImageAspect {
+    /// Every aspect that images with `format` have, see [`Format::aspect_mask`]
+    pub fn of_format(format: Format) -> Self {
+        format.aspect_mask()
+    }
+}
+
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, Hash)]
+pub enum Swizzle {
+    #[default]
+    Identity,
+    Zero,
+    One,
+    R,
+    G,
+    B,
+    A,
+}
+
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, Hash)]
+pub struct ComponentMapping {
+    pub r: Swizzle,
+    pub g: Swizzle,
+    pub b: Swizzle,
+    pub a: Swizzle,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ImageViewType {
+    D1,
+    D1Array,
+    D2,
+    D2Array,
+    D3,
+    Cube,
+    CubeArray,
+}
+
+#[derive(Debug, Clone, Builder)]
+#[builder(pattern = "owned")]
+pub struct ImageViewDesc {
+    pub image: ImageHandle,
+    /// Defaults to the format of the image. A different format needs an image with
+    /// `mutable_format` and the same block size, like the UNORM and SRGB variants.
+    #[builder(default)]
+    pub format: Option<Format>,
+    /// Defaults to the type that matches the image and the number of layers
+    #[builder(default)]
+    pub view_type: Option<ImageViewType>,
+    /// Defaults to every aspect of the format
+    #[builder(default)]
+    pub aspect: Option<ImageAspect>,
+    #[builder(default)]
+    pub base_mip_level: u32,
+    /// Defaults to every mip level from `base_mip_level` on
+    #[builder(default)]
+    pub mip_levels: Option<u32>,
+    #[builder(default)]
+    pub base_layer: u32,
+    /// Defaults to every layer from `base_layer` on
+    #[builder(default)]
+    pub layers: Option<u32>,
+    #[builder(default)]
+    pub components: ComponentMapping,
+}
+
+impl ImageViewDescBuilder {
+    pub fn create(self, ctx: &Context) -> Result<ImageView, ImageError> {
+        let desc = self.build().expect("An image is required");
+        ctx.create_image_view(&desc)
+    }
+}
+
+/// Either a whole image through its default view or a view of it
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum ImageResource {
+    Image(ImageHandle),
+    View(ImageView),
+}
+
+impl From<Image> for ImageResource {
+    fn from(image: Image) -> ImageResource {
+        ImageResource::Image(image.handle)
+    }
+}
+
+impl From<ImageView> for ImageResource {
+    fn from(view: ImageView) -> ImageResource {
+        ImageResource::View(view)
+    }
+}
+
 #[derive(Debug, Clone, Builder)]
 #[builder(pattern = "owned")]
 pub struct ImageDesc {
     pub resolution: Resolution,
     pub layout: ImageLayout,
     pub format: Format,
+    /// Defaults to the usage implied by `layout`
+    #[builder(default = "ImageUsage::for_layout(self.layout.unwrap_or(ImageLayout::Color))")]
+    pub usage: ImageUsage,
+    #[builder(default = "ImageDimension::D2")]
+    pub dimension: ImageDimension,
+    /// Only used by 3D images
+    #[builder(default = "1")]
+    pub depth: u32,
+    #[builder(default = "1")]
+    pub mip_levels: u32,
+    #[builder(default = "1")]
+    pub layers: u32,
+    #[builder(default)]
+    pub samples: SampleCount,
+    /// Allows views with a different but compatible format
+    #[builder(default)]
+    pub mutable_format: bool,
+}
+
+impl ImageDesc {
+    /// A single 2D image without mips
+    pub fn new(resolution: Resolution, layout: ImageLayout, format: Format) -> Self {
+        ImageDesc {
+            resolution,
+            layout,
+            format,
+            usage: ImageUsage::for_layout(layout),
+            dimension: ImageDimension::D2,
+            depth: 1,
+            mip_levels: 1,
+            layers: 1,
+            samples: SampleCount::S1,
+            mutable_format: false,
+        }
+    }
+
+    pub fn with_mutable_format(mut self) -> Self {
+        self.mutable_format = true;
+        self
+    }
+
+    /// `count` is the number of layers, the depth of 3D images or the number of cubes
+    pub fn with_dimension(mut self, dimension: ImageDimension, count: u32) -> Self {
+        self.dimension = dimension;
+        self.layers = dimension.layers(count);
+        self.depth = if dimension == ImageDimension::D3 {
+            count
+        } else {
+            1
+        };
+        self
+    }
+
+    pub fn with_samples(mut self, samples: SampleCount) -> Self {
+        self.samples = samples;
+        self
+    }
+
+    pub fn with_usage(mut self, usage: ImageUsage) -> Self {
+        self.usage = usage;
+        self
+    }
+
+    /// Width, height and depth of a mip level
+    pub fn mip_extent(&self, mip_level: u32) -> (u32, u32, u32) {
+        let depth = match self.dimension {
+            ImageDimension::D3 => self.depth,
+            _ => 1,
+        };
+        (
+            (self.resolution.width >> mip_level).max(1),
+            (self.resolution.height >> mip_level).max(1),
+            (depth >> mip_level).max(1),
+        )
+    }
+
+    /// Uses as many mip levels as needed to get down to 1x1
+    pub fn with_full_mip_chain(mut self) -> Self {
+        self.mip_levels = max_mip_levels(self.resolution, self.depth);
+        self
+    }
+
+    /// Checks that the resolution and layers fit the dimension
+    pub fn validate(&self) -> Result<(), ImageError> {
+        match self.dimension {
+            ImageDimension::D1 if self.resolution.height != 1 => {
+                Err(ImageError::Invalid1D(self.resolution.height))
+            }
+            ImageDimension::Cube
+                if self.resolution.width != self.resolution.height
+                    || !self.layers.is_multiple_of(6) =>
+            {
+                Err(ImageError::InvalidCube {
+                    resolution: self.resolution,
+                    layers: self.layers,
+                })
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+pub fn max_mip_levels(resolution: Resolution, depth: u32) -> u32 {
+    let largest = resolution.width.max(resolution.height).max(depth).max(1);
+    32 - largest.leading_zeros()
 }
 
 impl Image {
-    pub fn allocate(ctx: &Context, desc: ImageDesc) -> Image {
-        let handle = ctx.allocate_image(desc);
-        Image { handle }
+    /// Starts a view of this image, see [`ImageViewDesc`]
+    pub fn view(&self) -> ImageViewDescBuilder {
+        ImageViewDescBuilder::default().image(self.handle)
+    }
+
+    pub fn allocate(ctx: &Context, desc: ImageDesc) -> Result<Image, ImageError> {
+        let handle = ctx.allocate_image(desc)?;
+        Ok(Image { handle })
+    }
+
+    /// Decodes an image file and uploads it as a sampled RGBA8 image
+    pub fn from_file<P: AsRef<Path>>(
+        ctx: &Context,
+        path: P,
+        color_space: ColorSpace,
+    ) -> Result<Image, ImageError> {
+        Image::from_files(ctx, &[path], ImageDimension::D2, color_space)
+    }
+
+    /// Decodes one file per layer, per depth slice of 3D images or per cube face
+    /// and uploads them as a single sampled RGBA8 image. All files need the same
+    /// resolution.
+    pub fn from_files<P: AsRef<Path>>(
+        ctx: &Context,
+        paths: &[P],
+        dimension: ImageDimension,
+        color_space: ColorSpace,
+    ) -> Result<Image, ImageError> {
+        let mut resolution = None;
+        let mut pixels = Vec::new();
+        for (layer, path) in paths.iter().enumerate() {
+            let rgba = ::image::open(path)
+                .map_err(ImageError::Decode)?
+                .to_rgba();
+            let (width, height) = rgba.dimensions();
+            let actual = Resolution { width, height };
+            let expected = *resolution.get_or_insert(actual);
+            if actual != expected {
+                return Err(ImageError::LayerResolutionMismatch {
+                    layer,
+                    expected,
+                    actual,
+                });
+            }
+            pixels.extend_from_slice(&rgba.into_raw());
+        }
+        let resolution = resolution.ok_or(ImageError::LayerCountMismatch {
+            expected: 1,
+            actual: 0,
+        })?;
+        let count = match dimension {
+            ImageDimension::Cube => {
+                if !paths.len().is_multiple_of(6) {
+                    return Err(ImageError::InvalidCube {
+                        resolution,
+                        layers: paths.len() as u32,
+                    });
+                }
+                paths.len() as u32 / 6
+            }
+            _ => paths.len() as u32,
+        };
+        Image::from_packed_rgba8(ctx, resolution, dimension, count, &pixels, color_space)
+    }
+
+    /// Uploads tightly packed RGBA8 pixels as a sampled image with a full mip chain
+    pub fn from_rgba8(
+        ctx: &Context,
+        width: u32,
+        height: u32,
+        pixels: &[u8],
+        color_space: ColorSpace,
+    ) -> Result<Image, ImageError> {
+        Image::from_packed_rgba8(
+            ctx,
+            Resolution { width, height },
+            ImageDimension::D2,
+            1,
+            pixels,
+            color_space,
+        )
+    }
+
+    /// Uploads a single buffer with all layers, depth slices or cube faces stored
+    /// one after another. `count` is the number of layers, the depth of 3D images
+    /// or the number of cubes.
+    pub fn from_packed_rgba8(
+        ctx: &Context,
+        resolution: Resolution,
+        dimension: ImageDimension,
+        count: u32,
+        pixels: &[u8],
+        color_space: ColorSpace,
+    ) -> Result<Image, ImageError> {
+        let desc = ImageDesc::new(resolution, ImageLayout::Sampled, color_space.rgba8_format())
+            .with_dimension(dimension, count)
+            .with_full_mip_chain();
+        let expected = resolution.width as usize
+            * resolution.height as usize
+            * (desc.layers * desc.depth) as usize
+            * 4;
+        if pixels.len() != expected {
+            return Err(ImageError::SizeMismatch {
+                expected,
+                actual: pixels.len(),
+            });
+        }
+        let staging = Buffer::from_slice(ctx, Property::Upload, BufferUsage::empty(), pixels)
+            .map_err(ImageError::BufferError)?;
+        let handle = ctx.from_buffer(desc, staging);
+        ctx.destroy(staging.buffer);
+        let handle = handle?;
+        ctx.generate_mips(handle)?;
+        Ok(Image { handle })
+    }
+
+    /// Reads back the first mip level and layer as tightly packed RGBA8 pixels.
+    /// BGRA images are swizzled, sRGB values are kept as they are and depth images
+    /// become grayscale. Multisampled images have to be resolved first.
+    pub fn read_pixels(&self, ctx: &Context) -> Result<Vec<u8>, ImageError> {
+        self.read_rgba8(ctx).map(RgbaImage::into_raw)
+    }
+
+    /// Saves the first mip level and layer, see [`Image::read_pixels`]
+    pub fn save_png<P: AsRef<Path>>(&self, ctx: &Context, path: P) -> Result<(), ImageError> {
+        self.read_rgba8(ctx)?.save(path).map_err(ImageError::Io)
+    }
+
+    fn read_rgba8(&self, ctx: &Context) -> Result<RgbaImage, ImageError> {
+        let desc = ctx.desc(self.handle);
+        if desc.samples != SampleCount::S1 {
+            return Err(ImageError::MultisampledReadback);
+        }
+        let texel_size = readback_texel_size(desc.format)
+            .ok_or(ImageError::UnsupportedReadback(desc.format))?;
+        let texels = desc.resolution.width as u64 * desc.resolution.height as u64;
+        let buffer = Buffer::<u8>::allocate(
+            ctx,
+            Property::Readback,
+            BufferUsage::empty(),
+            texels * texel_size,
+        )
+        .map_err(ImageError::BufferError)?;
+        ctx.copy_image_to_buffer(self.handle, buffer.buffer);
+        let data = buffer.read(ctx);
+        ctx.destroy(buffer.buffer);
+        let data = data.map_err(ImageError::BufferError)?;
+        Ok(to_rgba8(desc.format, desc.resolution, data))
+    }
+}
+
+/// Size of one texel in the readback buffer, `None` if the format can't be converted
+fn readback_texel_size(format: Format) -> Option<u64> {
+    match format {
+        Format::R8G8B8A8_UNORM
+        | Format::R8G8B8A8_SRGB
+        | Format::B8G8R8A8_UNORM
+        | Format::B8G8R8A8_SRGB
+        | Format::X8_D24_UNORM_PACK32
+        | Format::D24_UNORM_S8_UINT
+        | Format::D32_SFLOAT
+        | Format::D32_SFLOAT_S8_UINT => Some(4),
+        Format::D16_UNORM | Format::D16_UNORM_S8_UINT => Some(2),
+        _ => None,
+    }
+}
+
+fn to_rgba8(format: Format, resolution: Resolution, data: Vec<u8>) -> RgbaImage {
+    let Resolution { width, height } = resolution;
+    let image = match format {
+        Format::R8G8B8A8_UNORM | Format::R8G8B8A8_SRGB => {
+            DynamicImage::ImageRgba8(rgba_image(width, height, data))
+        }
+        // The image crate has no BGRA pixel type
+        Format::B8G8R8A8_UNORM | Format::B8G8R8A8_SRGB => {
+            let mut image = rgba_image(width, height, data);
+            for pixel in image.pixels_mut() {
+                pixel.data.swap(0, 2);
+            }
+            DynamicImage::ImageRgba8(image)
+        }
+        _ => {
+            let depth = depth_to_luma8(format, &data);
+            let image =
+                GrayImage::from_raw(width, height, depth).expect("Pixels match the resolution");
+            DynamicImage::ImageLuma8(image)
+        }
+    };
+    image.to_rgba()
+}
+
+fn rgba_image(width: u32, height: u32, data: Vec<u8>) -> RgbaImage {
+    RgbaImage::from_raw(width, height, data).expect("Pixels match the resolution")
+}
+
+/// Keeps the most significant byte of every depth value
+fn depth_to_luma8(format: Format, data: &[u8]) -> Vec<u8> {
+    match format {
+        Format::D16_UNORM | Format::D16_UNORM_S8_UINT => data.chunks(2).map(|d| d[1]).collect(),
+        // The depth aspect of 24 bit formats is copied into the lower 24 bits
+        Format::X8_D24_UNORM_PACK32 | Format::D24_UNORM_S8_UINT => {
+            data.chunks(4).map(|d| d[2]).collect()
+        }
+        Format::D32_SFLOAT | Format::D32_SFLOAT_S8_UINT => {
+            data.chunks(4)
+                .map(|d| {
+                    let depth = f32::from_bits(u32::from_le_bytes([d[0], d[1], d[2], d[3]]));
+                    (depth.clamp(0.0, 1.0) * 255.0) as u8
+                })
+                .collect()
+        }
+        _ => unreachable!("Checked by readback_texel_size"),
     }
 }
 
+/// Block extent, bytes per block and number of components
+struct FormatInfo {
+    block_extent: (u32, u32),
+    block_size: u32,
+    components: u32,
+}
+
+/// Metadata of every core format, `None` for undefined and extension formats
+fn format_info(format: Format) -> Option<FormatInfo> {
+    const ASTC: [(u32, u32); 14] = [
+        (4, 4),
+        (5, 4),
+        (5, 5),
+        (6, 5),
+        (6, 6),
+        (8, 5),
+        (8, 6),
+        (8, 8),
+        (10, 5),
+        (10, 6),
+        (10, 8),
+        (10, 10),
+        (12, 10),
+        (12, 12),
+    ];
+    let texel = |block_size, components| (1, 1, block_size, components);
+    let (width, height, block_size, components) = match format.as_raw() {
+        1 => texel(1, 2),
+        2 | 3 | 6..=8 => texel(2, 4),
+        4 | 5 => texel(2, 3),
+        // 8 bit formats come in groups of UNORM, SNORM, USCALED, SSCALED, UINT, SINT, SRGB
+        9..=15 => texel(1, 1),
+        16..=22 => texel(2, 2),
+        23..=36 => texel(3, 3),
+        37..=57 => texel(4, 4),
+        58..=69 => texel(4, 4),
+        // 16 bit formats replace SRGB with SFLOAT
+        70..=76 => texel(2, 1),
+        77..=83 => texel(4, 2),
+        84..=90 => texel(6, 3),
+        91..=97 => texel(8, 4),
+        // 32 and 64 bit formats only come as UINT, SINT and SFLOAT
+        98..=100 => texel(4, 1),
+        101..=103 => texel(8, 2),
+        104..=106 => texel(12, 3),
+        107..=109 => texel(16, 4),
+        110..=112 => texel(8, 1),
+        113..=115 => texel(16, 2),
+        116..=118 => texel(24, 3),
+        119..=121 => texel(32, 4),
+        122 | 123 => texel(4, 3),
+        // Depth and stencil
+        124 => texel(2, 1),
+        125 | 126 => texel(4, 1),
+        127 => texel(1, 1),
+        128 => texel(3, 2),
+        129 => texel(4, 2),
+        130 => texel(5, 2),
+        // BC
+        131 | 132 => (4, 4, 8, 3),
+        133 | 134 => (4, 4, 8, 4),
+        135..=138 => (4, 4, 16, 4),
+        139 | 140 => (4, 4, 8, 1),
+        141 | 142 => (4, 4, 16, 2),
+        143 | 144 => (4, 4, 16, 3),
+        145 | 146 => (4, 4, 16, 4),
+        // ETC2 and EAC
+        147 | 148 => (4, 4, 8, 3),
+        149 | 150 => (4, 4, 8, 4),
+        151 | 152 => (4, 4, 16, 4),
+        153 | 154 => (4, 4, 8, 1),
+        155 | 156 => (4, 4, 16, 2),
+        // ASTC formats come in UNORM and SRGB pairs, ordered by block extent
+        raw @ 157..=184 => {
+            let (width, height) = ASTC[(raw - 157) as usize / 2];
+            (width, height, 16, 4)
+        }
+        _ => return None,
+    };
+    Some(FormatInfo {
+        block_extent: (width, height),
+        block_size,
+        components,
+    })
+}
+
 pub struct RenderTargetInfo<'a> {
     pub image_views: Vec<&'a Image>,
 }
@@ -83,6 +930,129 @@ impl Format {
         self.0
     }
 }
+impl Format {
+    /// Bytes per texel, or per block for compressed formats
+    pub fn block_size(self) -> Option<u32> {
+        format_info(self).map(|info| info.block_size)
+    }
+
+    /// Width and height of a block in texels, 1x1 for uncompressed formats
+    pub fn block_extent(self) -> Option<(u32, u32)> {
+        format_info(self).map(|info| info.block_extent)
+    }
+
+    /// Number of color, depth and stencil components
+    pub fn component_count(self) -> Option<u32> {
+        format_info(self).map(|info| info.components)
+    }
+
+    pub fn is_compressed(self) -> bool {
+        self.block_extent().is_some_and(|extent| extent != (1, 1))
+    }
+
+    pub fn has_depth(self) -> bool {
+        self.aspect_mask().contains(ImageAspect::DEPTH)
+    }
+
+    pub fn has_stencil(self) -> bool {
+        self.aspect_mask().contains(ImageAspect::STENCIL)
+    }
+
+    pub fn is_srgb(self) -> bool {
+        self.to_linear().is_some()
+    }
+
+    /// Every aspect that images with this format have
+    pub fn aspect_mask(self) -> ImageAspect {
+        match self {
+            Format::D16_UNORM | Format::X8_D24_UNORM_PACK32 | Format::D32_SFLOAT => {
+                ImageAspect::DEPTH
+            }
+            Format::S8_UINT => ImageAspect::STENCIL,
+            Format::D16_UNORM_S8_UINT | Format::D24_UNORM_S8_UINT | Format::D32_SFLOAT_S8_UINT => {
+                ImageAspect::DEPTH | ImageAspect::STENCIL
+            }
+            _ => ImageAspect::COLOR,
+        }
+    }
+
+    /// The sRGB counterpart of a UNORM format
+    pub fn to_srgb(self) -> Option<Format> {
+        let raw = self.as_raw();
+        let srgb = match raw {
+            // The SRGB variant follows six formats after UNORM
+            9 | 16 | 23 | 30 | 37 | 44 | 51 => raw + 6,
+            // BC1 to BC3, BC7 and ETC2 place it right after UNORM
+            131 | 133 | 135 | 137 | 145 | 147 | 149 | 151 => raw + 1,
+            157..=184 if raw % 2 == 1 => raw + 1,
+            _ => return None,
+        };
+        Some(Format(srgb))
+    }
+
+    /// The UNORM counterpart of an sRGB format
+    pub fn to_linear(self) -> Option<Format> {
+        let raw = self.as_raw();
+        let linear = match raw {
+            15 | 22 | 29 | 36 | 43 | 50 | 57 => raw - 6,
+            132 | 134 | 136 | 138 | 146 | 148 | 150 | 152 => raw - 1,
+            157..=184 if raw % 2 == 0 => raw - 1,
+            _ => return None,
+        };
+        Some(Format(linear))
+    }
+
+    /// Views can reinterpret an image with a format of the same compatibility
+    /// class, for example the UNORM and SRGB variants of a format
+    pub fn is_view_compatible(self, other: Format) -> bool {
+        if self == other {
+            return true;
+        }
+        match (self.compatibility_class(), other.compatibility_class()) {
+            (Some(a), Some(b)) => a == b,
+            _ => false,
+        }
+    }
+
+    /// Copies only need the same texel or block size, but depth and stencil
+    /// formats have to match exactly
+    pub fn is_copy_compatible(self, other: Format) -> bool {
+        if self == other {
+            return true;
+        }
+        if self.aspect_mask() != ImageAspect::COLOR || other.aspect_mask() != ImageAspect::COLOR {
+            return false;
+        }
+        match (self.block_size(), other.block_size()) {
+            (Some(a), Some(b)) => a == b,
+            _ => false,
+        }
+    }
+
+    fn compatibility_class(self) -> Option<CompatibilityClass> {
+        let info = format_info(self)?;
+        let class = if self.aspect_mask() != ImageAspect::COLOR {
+            CompatibilityClass::DepthStencil(self)
+        } else if self.is_compressed() {
+            // Every compressed class is a pair of UNORM and SRGB or SNORM formats
+            CompatibilityClass::Compressed((self.as_raw() - 131) / 2)
+        } else {
+            CompatibilityClass::Color(info.block_size)
+        };
+        Some(class)
+    }
+}
+
+/// Formats of the same class can be reinterpreted by views
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum CompatibilityClass {
+    /// Uncompressed color formats with the same texel size
+    Color(u32),
+    /// Depth and stencil formats are only compatible with themselves
+    DepthStencil(Format),
+    /// Compressed formats with the same encoding and block extent
+    Compressed(i32),
+}
 impl Format {
     pub const UNDEFINED: Self = Format(0);
     pub const R4G4_UNORM_PACK8: Self = Format(1);
@@ -270,3 +1240,96 @@ impl Format {
     pub const ASTC_12X12_UNORM_BLOCK: Self = Format(183);
     pub const ASTC_12X12_SRGB_BLOCK: Self = Format(184);
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn view_compatibility() {
+        assert!(Format::R8G8B8A8_UNORM.is_view_compatible(Format::R8G8B8A8_SRGB));
+        assert!(Format::R8G8B8A8_UNORM.is_view_compatible(Format::R32_UINT));
+        assert!(Format::B8G8R8A8_SRGB.is_view_compatible(Format::R16G16_SFLOAT));
+        assert!(!Format::R8G8B8A8_UNORM.is_view_compatible(Format::R8G8_UNORM));
+        assert!(!Format::R8G8B8A8_UNORM.is_view_compatible(Format::UNDEFINED));
+    }
+
+    #[test]
+    fn depth_stencil_views_need_the_same_format() {
+        assert!(Format::D32_SFLOAT.is_view_compatible(Format::D32_SFLOAT));
+        assert!(!Format::D32_SFLOAT.is_view_compatible(Format::R32_SFLOAT));
+        assert!(!Format::X8_D24_UNORM_PACK32.is_view_compatible(Format::D24_UNORM_S8_UINT));
+    }
+
+    #[test]
+    fn compressed_view_compatibility() {
+        assert!(Format::BC1_RGBA_UNORM_BLOCK.is_view_compatible(Format::BC1_RGBA_SRGB_BLOCK));
+        assert!(Format::BC4_UNORM_BLOCK.is_view_compatible(Format::BC4_SNORM_BLOCK));
+        assert!(!Format::BC1_RGB_UNORM_BLOCK.is_view_compatible(Format::BC1_RGBA_UNORM_BLOCK));
+        assert!(!Format::BC4_UNORM_BLOCK.is_view_compatible(Format::BC1_RGB_UNORM_BLOCK));
+        assert!(!Format::ASTC_4X4_UNORM_BLOCK.is_view_compatible(Format::ASTC_5X4_UNORM_BLOCK));
+        assert!(!Format::BC1_RGBA_UNORM_BLOCK.is_view_compatible(Format::R32G32_UINT));
+    }
+
+    #[test]
+    fn copy_compatibility() {
+        assert!(Format::R8G8B8A8_UNORM.is_copy_compatible(Format::R32_SFLOAT));
+        assert!(Format::BC1_RGB_UNORM_BLOCK.is_copy_compatible(Format::R32G32_UINT));
+        assert!(Format::D16_UNORM.is_copy_compatible(Format::D16_UNORM));
+        assert!(!Format::D32_SFLOAT.is_copy_compatible(Format::R32_SFLOAT));
+        assert!(!Format::R8G8B8A8_UNORM.is_copy_compatible(Format::R8_UNORM));
+    }
+
+    #[test]
+    fn format_metadata() {
+        assert_eq!(Format::UNDEFINED.block_size(), None);
+        assert_eq!(Format::R8G8B8_SRGB.block_size(), Some(3));
+        assert_eq!(Format::R16G16B16A16_SFLOAT.block_size(), Some(8));
+        assert_eq!(Format::R32G32B32_SFLOAT.component_count(), Some(3));
+        assert_eq!(Format::D32_SFLOAT_S8_UINT.block_size(), Some(5));
+        assert_eq!(Format::D24_UNORM_S8_UINT.component_count(), Some(2));
+        assert_eq!(Format::BC1_RGB_UNORM_BLOCK.block_size(), Some(8));
+        assert_eq!(Format::BC7_SRGB_BLOCK.block_size(), Some(16));
+        assert_eq!(Format::EAC_R11G11_SNORM_BLOCK.component_count(), Some(2));
+        assert_eq!(Format::ASTC_4X4_UNORM_BLOCK.block_extent(), Some((4, 4)));
+        assert_eq!(Format::ASTC_10X8_SRGB_BLOCK.block_extent(), Some((10, 8)));
+        assert_eq!(Format::ASTC_12X12_SRGB_BLOCK.block_extent(), Some((12, 12)));
+        assert!(Format::ETC2_R8G8B8_UNORM_BLOCK.is_compressed());
+        assert!(!Format::R8G8B8A8_UNORM.is_compressed());
+    }
+
+    #[test]
+    fn srgb_round_trips() {
+        let pairs = [
+            (Format::R8_UNORM, Format::R8_SRGB),
+            (Format::B8G8R8A8_UNORM, Format::B8G8R8A8_SRGB),
+            (Format::A8B8G8R8_UNORM_PACK32, Format::A8B8G8R8_SRGB_PACK32),
+            (Format::BC1_RGB_UNORM_BLOCK, Format::BC1_RGB_SRGB_BLOCK),
+            (Format::BC7_UNORM_BLOCK, Format::BC7_SRGB_BLOCK),
+            (Format::ETC2_R8G8B8A8_UNORM_BLOCK, Format::ETC2_R8G8B8A8_SRGB_BLOCK),
+            (Format::ASTC_12X12_UNORM_BLOCK, Format::ASTC_12X12_SRGB_BLOCK),
+        ];
+        for &(unorm, srgb) in pairs.iter() {
+            assert_eq!(unorm.to_srgb(), Some(srgb));
+            assert_eq!(srgb.to_linear(), Some(unorm));
+            assert!(srgb.is_srgb());
+            assert!(!unorm.is_srgb());
+        }
+        assert_eq!(Format::R8_SNORM.to_srgb(), None);
+        assert_eq!(Format::BC4_UNORM_BLOCK.to_srgb(), None);
+        assert_eq!(Format::R32_SFLOAT.to_linear(), None);
+    }
+
+    #[test]
+    fn aspect_masks() {
+        assert_eq!(Format::R8G8B8A8_UNORM.aspect_mask(), ImageAspect::COLOR);
+        assert_eq!(Format::D16_UNORM.aspect_mask(), ImageAspect::DEPTH);
+        assert_eq!(Format::S8_UINT.aspect_mask(), ImageAspect::STENCIL);
+        assert_eq!(
+            Format::D24_UNORM_S8_UINT.aspect_mask(),
+            ImageAspect::DEPTH | ImageAspect::STENCIL
+        );
+        assert!(Format::D32_SFLOAT_S8_UINT.has_stencil());
+        assert!(!Format::X8_D24_UNORM_PACK32.has_stencil());
+    }
+}
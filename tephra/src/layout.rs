@@ -0,0 +1,134 @@
+use crate::reflect::BlockLayout;
+
+pub const fn align_up(value: usize, alignment: usize) -> usize {
+    value.div_ceil(alignment) * alignment
+}
+
+pub const fn max(a: usize, b: usize) -> usize {
+    [a, b][(a < b) as usize]
+}
+
+/// Types with a known `std140` layout. `#[derive(Std140)]` checks at compile time
+/// that every field of a `#[repr(C)]` struct sits at the offset GLSL expects. Gaps
+/// have to be filled with fields marked `#[padding]`.
+///
+/// # Safety
+///
+/// `ALIGN`, `SIZE` and the member offsets have to match the layout of the type in
+/// memory, buffers are copied to the GPU as they are.
+pub unsafe trait Std140 {
+    const ALIGN: usize;
+    const SIZE: usize;
+    /// Offsets of the block members, padding fields are skipped
+    fn member_offsets() -> Vec<u32> {
+        Vec::new()
+    }
+}
+
+/// Types with a known `std430` layout, see [`Std140`]
+///
+/// # Safety
+///
+/// The same as for [`Std140`]
+pub unsafe trait Std430 {
+    const ALIGN: usize;
+    const SIZE: usize;
+    /// Offsets of the block members, padding fields are skipped
+    fn member_offsets() -> Vec<u32> {
+        Vec::new()
+    }
+}
+
+macro_rules! impl_layout {
+    ($layout: ident, $ty: ty, $align: expr, $size: expr) => {
+        unsafe impl $layout for $ty {
+            const ALIGN: usize = $align;
+            const SIZE: usize = $size;
+        }
+    };
+}
+
+macro_rules! impl_vectors {
+    ($scalar: ty) => {
+        impl_layout!(Std140, $scalar, 4, 4);
+        impl_layout!(Std140, [$scalar; 2], 8, 8);
+        impl_layout!(Std140, [$scalar; 3], 16, 12);
+        impl_layout!(Std140, [$scalar; 4], 16, 16);
+        impl_layout!(Std430, $scalar, 4, 4);
+        impl_layout!(Std430, [$scalar; 2], 8, 8);
+        impl_layout!(Std430, [$scalar; 3], 16, 12);
+        impl_layout!(Std430, [$scalar; 4], 16, 16);
+    };
+}
+
+impl_vectors!(f32);
+impl_vectors!(i32);
+impl_vectors!(u32);
+
+// Matrices are stored as columns. In std140 every column is aligned to a vec4,
+// so only mat4 matches the Rust layout.
+impl_layout!(Std140, [[f32; 4]; 4], 16, 64);
+impl_layout!(Std430, [[f32; 4]; 4], 16, 64);
+impl_layout!(Std430, [[f32; 2]; 2], 8, 16);
+
+/// Expected member offsets of a block, checked against the shader at pipeline
+/// creation.
+#[derive(Debug, Clone)]
+pub struct BlockCheck {
+    pub set: u32,
+    pub binding: u32,
+    pub offsets: Vec<u32>,
+}
+
+impl BlockCheck {
+    pub fn std140<T: Std140>(set: u32, binding: u32) -> Self {
+        BlockCheck {
+            set,
+            binding,
+            offsets: T::member_offsets(),
+        }
+    }
+
+    pub fn std430<T: Std430>(set: u32, binding: u32) -> Self {
+        BlockCheck {
+            set,
+            binding,
+            offsets: T::member_offsets(),
+        }
+    }
+
+    pub fn check(&self, blocks: &[BlockLayout]) -> Result<(), LayoutError> {
+        let block = blocks
+            .iter()
+            .find(|block| block.set == self.set && block.binding == self.binding)
+            .ok_or(LayoutError::MissingBlock {
+                set: self.set,
+                binding: self.binding,
+            })?;
+        if block.offsets != self.offsets {
+            return Err(LayoutError::OffsetMismatch {
+                set: self.set,
+                binding: self.binding,
+                shader: block.offsets.clone(),
+                rust: self.offsets.clone(),
+            });
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Fail)]
+pub enum LayoutError {
+    #[fail(display = "No block found at set {} binding {}", set, binding)]
+    MissingBlock { set: u32, binding: u32 },
+    #[fail(
+        display = "Block at set {} binding {} has the offsets {:?}, the Rust type has {:?}",
+        set, binding, shader, rust
+    )]
+    OffsetMismatch {
+        set: u32,
+        binding: u32,
+        shader: Vec<u32>,
+        rust: Vec<u32>,
+    },
+}
@@ -1,3 +1,5 @@
+// failure_derive wraps the impls of `#[derive(Fail)]` in a const item
+#![allow(non_local_definitions)]
 #[macro_use]
 pub extern crate ash;
 pub extern crate failure;
@@ -12,14 +14,19 @@ pub mod buffer;
 pub mod commandbuffer;
 pub mod context;
 pub mod descriptor;
+pub mod gpuvec;
 //pub mod framegraph;
 pub mod image;
+pub mod layout;
 pub mod passes;
 pub mod pipeline;
 pub mod reflect;
 pub mod renderpass;
+pub mod ring;
+pub mod sampler;
 pub mod shader;
 pub mod swapchain;
+pub mod texture;
 pub use failure::Error;
 use parking_lot::RwLock;
 #[derive(Copy, Clone, Default, Debug)]
@@ -28,6 +35,12 @@ pub struct Viewport {
     pub dimensions: (f32, f32),
     pub depth_range: (f32, f32),
 }
+/// A rectangle in pixels, used for scissors and render areas
+#[derive(Copy, Clone, Default, Debug, PartialEq, Eq)]
+pub struct Rect {
+    pub offset: (i32, i32),
+    pub extent: (u32, u32),
+}
 use generational_arena::{Arena, Index};
 
 pub trait TypedHandle {
@@ -68,6 +81,10 @@ where
             _marker: std::marker::PhantomData,
         }
     }
+    pub fn remove(&self, key: H) -> Option<T> {
+        self.map.write().remove(key.to_index())
+    }
+
     pub fn is_valid(&self, key: H) -> bool {
         self.map.read().get(key.to_index()).is_some()
     }
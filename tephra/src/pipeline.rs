@@ -1,17 +1,56 @@
 use crate::{
     context::Context,
     descriptor::{Binding, DescriptorInfo, DescriptorType},
+    image::SampleCount,
+    layout::{BlockCheck, LayoutError, Std140, Std430},
+    reflect::block_layouts,
     renderpass::{Renderpass, VertexInput, VertexInputData},
     shader::ShaderModule,
 };
+use bitflags::bitflags;
+use serde::{Deserialize, Serialize};
 
 crate::new_typed_handle!(GraphicsPipeline);
 crate::new_typed_handle!(ComputePipeline);
 
 pub trait PipelineApi {
-    unsafe fn create_graphics_pipeline(&self, state: &GraphicsPipelineState) -> GraphicsPipeline;
+    unsafe fn create_graphics_pipeline(
+        &self,
+        state: &GraphicsPipelineState,
+    ) -> Result<GraphicsPipeline, PipelineError>;
     unsafe fn create_compute_pipeline(&self, state: &ComputePipelineState) -> ComputePipeline;
 }
+#[derive(Debug, Fail)]
+pub enum PipelineError {
+    #[fail(display = "The device doesn't support {:?} for pipelines", _0)]
+    UnsupportedSampleCount(SampleCount),
+    #[fail(
+        display = "The render target has {} subpasses, there is no subpass {}",
+        count, subpass
+    )]
+    UnknownSubpass { subpass: u32, count: usize },
+    #[fail(
+        display = "The pipeline uses {:?}, but the attachments of its subpass use {:?}",
+        pipeline, attachments
+    )]
+    SampleCountMismatch {
+        pipeline: SampleCount,
+        attachments: SampleCount,
+    },
+    #[fail(
+        display = "{} blend states for a subpass with {} color attachments",
+        blend_states, color_attachments
+    )]
+    BlendStateCountMismatch {
+        color_attachments: usize,
+        blend_states: usize,
+    },
+    #[fail(display = "A shader block doesn't match its Rust type: {}", _0)]
+    Layout(LayoutError),
+    #[fail(display = "The pipeline needs the {} feature, which the device doesn't support", _0)]
+    UnsupportedFeature(&'static str),
+}
+
 pub struct ComputePipelineState {
     pub compute_shader: ShaderStage,
     pub layout: Vec<Binding<DescriptorType>>,
@@ -20,6 +59,7 @@ pub struct ComputePipelineState {
 pub struct ComputePipelineStateBuilder {
     pub compute_shader: Option<ShaderStage>,
     pub layout: Option<Vec<Binding<DescriptorType>>>,
+    pub block_checks: Vec<BlockCheck>,
 }
 impl ComputePipeline {
     pub fn builder() -> ComputePipelineStateBuilder {
@@ -40,8 +80,20 @@ impl ComputePipelineStateBuilder {
             layout,
         })
     }
-    pub unsafe fn create(self, ctx: &Context) -> ComputePipeline {
-        ctx.create_compute_pipeline(&self.build().unwrap())
+    pub unsafe fn create(self, ctx: &Context) -> Result<ComputePipeline, PipelineError> {
+        if let Some(shader) = self.compute_shader.as_ref() {
+            check_blocks(ctx, &[shader], &self.block_checks).map_err(PipelineError::Layout)?;
+        }
+        Ok(ctx.create_compute_pipeline(&self.build().unwrap()))
+    }
+    /// Verifies at creation that the block at `set` and `binding` matches `T`
+    pub fn check_std140<T: Std140>(mut self, set: u32, binding: u32) -> Self {
+        self.block_checks.push(BlockCheck::std140::<T>(set, binding));
+        self
+    }
+    pub fn check_std430<T: Std430>(mut self, set: u32, binding: u32) -> Self {
+        self.block_checks.push(BlockCheck::std430::<T>(set, binding));
+        self
     }
     pub fn compute_shader(mut self, shader: ShaderStage) -> Self {
         self.compute_shader = Some(shader);
@@ -58,22 +110,50 @@ pub struct ShaderStage {
     pub shader_module: ShaderModule,
     pub entry_name: String,
 }
+
+/// Compares the member offsets of the blocks in `stages` with the Rust layouts
+pub fn check_blocks(
+    ctx: &Context,
+    stages: &[&ShaderStage],
+    checks: &[BlockCheck],
+) -> Result<(), LayoutError> {
+    if checks.is_empty() {
+        return Ok(());
+    }
+    let blocks: Vec<_> = stages
+        .iter()
+        .flat_map(|stage| block_layouts(&ctx.spirv(stage.shader_module)))
+        .collect();
+    for check in checks {
+        check.check(&blocks)?;
+    }
+    Ok(())
+}
 pub type Stride = u32;
 pub struct GraphicsPipelineState {
     pub vertex_shader: ShaderStage,
     pub fragment_shader: ShaderStage,
     pub render_target: Renderpass,
+    /// The subpass of `render_target` that the pipeline draws in
+    pub subpass: u32,
     pub layout: Vec<Binding<DescriptorType>>,
     // TODO: Default to SoA not AoS
     pub vertex_input: (Stride, Vec<VertexInputData>),
+    /// Has to match the sample count of the attachments in `render_target`
+    pub samples: SampleCount,
+    pub fixed_function: FixedFunctionState,
 }
 #[derive(Default)]
 pub struct GraphicsPipelineStateBuilder {
     pub vertex_shader: Option<ShaderStage>,
     pub fragment_shader: Option<ShaderStage>,
     pub render_target: Option<Renderpass>,
+    pub subpass: u32,
     pub layout: Option<Vec<Binding<DescriptorType>>>,
     pub vertex_input: Option<(Stride, Vec<VertexInputData>)>,
+    pub samples: SampleCount,
+    pub fixed_function: FixedFunctionState,
+    pub block_checks: Vec<BlockCheck>,
 }
 impl GraphicsPipelineStateBuilder {
     pub fn build(self) -> Option<GraphicsPipelineState> {
@@ -86,14 +166,22 @@ impl GraphicsPipelineStateBuilder {
             vertex_shader,
             fragment_shader,
             render_target,
+            subpass: self.subpass,
             layout,
             vertex_input,
+            samples: self.samples,
+            fixed_function: self.fixed_function,
         })
     }
     pub fn render_target(mut self, target: Renderpass) -> Self {
         self.render_target = Some(target);
         self
     }
+    /// Draws in `subpass` of the render target instead of the first one
+    pub fn subpass(mut self, subpass: u32) -> Self {
+        self.subpass = subpass;
+        self
+    }
     pub fn vertex_shader(mut self, shader: ShaderStage) -> Self {
         self.vertex_shader = Some(shader);
         self
@@ -102,10 +190,62 @@ impl GraphicsPipelineStateBuilder {
         self.fragment_shader = Some(shader);
         self
     }
-    pub unsafe fn create(self, ctx: &Context) -> GraphicsPipeline {
+    pub fn samples(mut self, samples: SampleCount) -> Self {
+        self.samples = samples;
+        self
+    }
+    /// Replaces every fixed function state at once, for example with a deserialized one
+    pub fn fixed_function(mut self, state: FixedFunctionState) -> Self {
+        self.fixed_function = state;
+        self
+    }
+    pub fn input_assembly(mut self, state: InputAssemblyState) -> Self {
+        self.fixed_function.input_assembly = state;
+        self
+    }
+    pub fn rasterizer(mut self, state: RasterizerState) -> Self {
+        self.fixed_function.rasterizer = state;
+        self
+    }
+    pub fn depth_stencil(mut self, state: DepthStencilState) -> Self {
+        self.fixed_function.depth_stencil = state;
+        self
+    }
+    pub fn color_blend(mut self, state: ColorBlendState) -> Self {
+        self.fixed_function.color_blend = state;
+        self
+    }
+    pub fn dynamic_states(mut self, states: &[DynamicState]) -> Self {
+        self.fixed_function.dynamic_states = states.to_vec();
+        self
+    }
+    pub unsafe fn create(self, ctx: &Context) -> Result<GraphicsPipeline, PipelineError> {
+        let checks = self.block_checks.clone();
         let state = self.build().unwrap();
+        let limits = ctx.limits();
+        if !state
+            .samples
+            .is_supported(limits.framebuffer_color_sample_counts)
+        {
+            return Err(PipelineError::UnsupportedSampleCount(state.samples));
+        }
+        check_blocks(
+            ctx,
+            &[&state.vertex_shader, &state.fragment_shader],
+            &checks,
+        )
+        .map_err(PipelineError::Layout)?;
         ctx.create_graphics_pipeline(&state)
     }
+    /// Verifies at creation that the block at `set` and `binding` matches `T`
+    pub fn check_std140<T: Std140>(mut self, set: u32, binding: u32) -> Self {
+        self.block_checks.push(BlockCheck::std140::<T>(set, binding));
+        self
+    }
+    pub fn check_std430<T: Std430>(mut self, set: u32, binding: u32) -> Self {
+        self.block_checks.push(BlockCheck::std430::<T>(set, binding));
+        self
+    }
     pub fn layout<D: DescriptorInfo>(mut self) -> Self {
         self.layout = Some(D::layout());
         self
@@ -115,3 +255,300 @@ impl GraphicsPipelineStateBuilder {
         self
     }
 }
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum PrimitiveTopology {
+    PointList,
+    LineList,
+    LineStrip,
+    TriangleList,
+    TriangleStrip,
+    TriangleFan,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct InputAssemblyState {
+    pub topology: PrimitiveTopology,
+    /// Restarts strips and fans at the index `u32::max_value()`
+    pub primitive_restart: bool,
+}
+
+impl Default for InputAssemblyState {
+    fn default() -> Self {
+        InputAssemblyState {
+            topology: PrimitiveTopology::TriangleList,
+            primitive_restart: false,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum PolygonMode {
+    Fill,
+    /// Wireframe
+    Line,
+    Point,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum CullMode {
+    None,
+    Front,
+    Back,
+    FrontAndBack,
+}
+
+/// The winding order of front facing triangles
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum FrontFace {
+    CounterClockwise,
+    Clockwise,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Default, Serialize, Deserialize)]
+pub struct DepthBias {
+    pub constant_factor: f32,
+    pub clamp: f32,
+    pub slope_factor: f32,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RasterizerState {
+    /// Anything but `Fill` needs the `fillModeNonSolid` feature
+    pub polygon_mode: PolygonMode,
+    pub cull_mode: CullMode,
+    pub front_face: FrontFace,
+    /// Anything but 1.0 needs the `wideLines` feature
+    pub line_width: f32,
+    /// Clamps the depth of fragments instead of clipping them, needs the `depthClamp`
+    /// feature
+    pub depth_clamp: bool,
+    pub depth_bias: Option<DepthBias>,
+}
+
+impl Default for RasterizerState {
+    fn default() -> Self {
+        RasterizerState {
+            polygon_mode: PolygonMode::Fill,
+            cull_mode: CullMode::None,
+            front_face: FrontFace::CounterClockwise,
+            line_width: 1.0,
+            depth_clamp: false,
+            depth_bias: None,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum CompareOp {
+    Never,
+    Less,
+    Equal,
+    LessOrEqual,
+    Greater,
+    NotEqual,
+    GreaterOrEqual,
+    Always,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum StencilOp {
+    Keep,
+    Zero,
+    Replace,
+    IncrementAndClamp,
+    DecrementAndClamp,
+    Invert,
+    IncrementAndWrap,
+    DecrementAndWrap,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct StencilFaceState {
+    pub fail_op: StencilOp,
+    pub pass_op: StencilOp,
+    pub depth_fail_op: StencilOp,
+    pub compare_op: CompareOp,
+    pub compare_mask: u32,
+    pub write_mask: u32,
+    pub reference: u32,
+}
+
+impl Default for StencilFaceState {
+    fn default() -> Self {
+        StencilFaceState {
+            fail_op: StencilOp::Keep,
+            pass_op: StencilOp::Keep,
+            depth_fail_op: StencilOp::Keep,
+            compare_op: CompareOp::Always,
+            compare_mask: 0xff,
+            write_mask: 0xff,
+            reference: 0,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DepthStencilState {
+    pub depth_test: bool,
+    pub depth_write: bool,
+    pub depth_compare: CompareOp,
+    pub stencil_test: bool,
+    pub front: StencilFaceState,
+    pub back: StencilFaceState,
+    /// Discards fragments whose depth attachment value is outside of `(min, max)`,
+    /// needs the `depthBounds` feature
+    pub depth_bounds: Option<(f32, f32)>,
+}
+
+impl Default for DepthStencilState {
+    fn default() -> Self {
+        DepthStencilState {
+            depth_test: true,
+            depth_write: true,
+            depth_compare: CompareOp::LessOrEqual,
+            stencil_test: false,
+            front: StencilFaceState::default(),
+            back: StencilFaceState::default(),
+            depth_bounds: None,
+        }
+    }
+}
+
+impl DepthStencilState {
+    /// Neither tests nor writes depth, for overlays that are drawn on top
+    pub fn disabled() -> Self {
+        DepthStencilState {
+            depth_test: false,
+            depth_write: false,
+            ..Default::default()
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum BlendFactor {
+    Zero,
+    One,
+    SrcColor,
+    OneMinusSrcColor,
+    DstColor,
+    OneMinusDstColor,
+    SrcAlpha,
+    OneMinusSrcAlpha,
+    DstAlpha,
+    OneMinusDstAlpha,
+    ConstantColor,
+    OneMinusConstantColor,
+    ConstantAlpha,
+    OneMinusConstantAlpha,
+    SrcAlphaSaturate,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum BlendOp {
+    Add,
+    Subtract,
+    ReverseSubtract,
+    Min,
+    Max,
+}
+
+/// Combines the fragment color (src) with the attachment color (dst)
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Blend {
+    pub src_color: BlendFactor,
+    pub dst_color: BlendFactor,
+    pub color_op: BlendOp,
+    pub src_alpha: BlendFactor,
+    pub dst_alpha: BlendFactor,
+    pub alpha_op: BlendOp,
+}
+
+impl Blend {
+    /// Classic alpha blending with a non premultiplied fragment color
+    pub fn alpha() -> Self {
+        Blend {
+            src_color: BlendFactor::SrcAlpha,
+            dst_color: BlendFactor::OneMinusSrcAlpha,
+            color_op: BlendOp::Add,
+            src_alpha: BlendFactor::One,
+            dst_alpha: BlendFactor::OneMinusSrcAlpha,
+            alpha_op: BlendOp::Add,
+        }
+    }
+
+    pub fn additive() -> Self {
+        Blend {
+            src_color: BlendFactor::One,
+            dst_color: BlendFactor::One,
+            color_op: BlendOp::Add,
+            src_alpha: BlendFactor::One,
+            dst_alpha: BlendFactor::One,
+            alpha_op: BlendOp::Add,
+        }
+    }
+}
+
+bitflags! {
+    #[derive(Serialize, Deserialize)]
+    pub struct ColorWriteMask: u32 {
+        const R = 1 << 0;
+        const G = 1 << 1;
+        const B = 1 << 2;
+        const A = 1 << 3;
+    }
+}
+
+impl Default for ColorWriteMask {
+    fn default() -> Self {
+        ColorWriteMask::all()
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ColorBlendAttachment {
+    /// Overwrites the attachment without blending if `None`
+    pub blend: Option<Blend>,
+    pub write_mask: ColorWriteMask,
+}
+
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ColorBlendState {
+    /// One state per color attachment of the subpass, missing ones use the default
+    pub attachments: Vec<ColorBlendAttachment>,
+    pub constants: [f32; 4],
+}
+
+/// States that are set while recording instead of being baked into the pipeline.
+/// The viewport and scissor are always dynamic and default to the whole framebuffer.
+/// Every other dynamic state has to be set in the renderpass before the first draw.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum DynamicState {
+    LineWidth,
+    DepthBias,
+    BlendConstants,
+    DepthBounds,
+    StencilCompareMask,
+    StencilWriteMask,
+    StencilReference,
+}
+
+/// Everything about a graphics pipeline that isn't a shader or a resource, and can
+/// be stored alongside other assets
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct FixedFunctionState {
+    pub input_assembly: InputAssemblyState,
+    pub rasterizer: RasterizerState,
+    pub depth_stencil: DepthStencilState,
+    pub color_blend: ColorBlendState,
+    /// Dynamic states besides the viewport and scissor, which are always dynamic
+    pub dynamic_states: Vec<DynamicState>,
+}
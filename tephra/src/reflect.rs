@@ -28,13 +28,17 @@ fn filter_ids<'a>(
         .filter_map(move |&id| find_result_id(module, id))
 }
 
-pub fn reflect(bytes: &[u8]) {
+fn load_module(bytes: &[u8]) -> Module {
     let mut loader = Loader::new();
     {
         let p = Parser::new(&bytes, &mut loader);
         p.parse().unwrap();
     }
-    let module = loader.module();
+    loader.module()
+}
+
+pub fn reflect(bytes: &[u8]) {
+    let module = load_module(bytes);
     let entry_points: Vec<_> = module
         .entry_points
         .iter()
@@ -143,3 +147,69 @@ impl EntryPoint {
         })
     }
 }
+
+/// Member offsets of a `uniform` or `buffer` block
+#[derive(Debug, Clone)]
+pub struct BlockLayout {
+    pub set: u32,
+    pub binding: u32,
+    pub offsets: Vec<u32>,
+}
+
+fn decoration(module: &Module, id: u32, decoration: spirv::Decoration) -> Option<u32> {
+    module
+        .annotations
+        .iter()
+        .filter(|inst| inst.class.opcode == spirv::Op::Decorate)
+        .filter(|inst| *extract!(&inst.operands[0], Operand::IdRef) == id)
+        .filter(|inst| *extract!(&inst.operands[1], Operand::Decoration) == decoration)
+        .map(|inst| *extract!(&inst.operands[2], Operand::LiteralInt32))
+        .next()
+}
+
+fn member_offsets(module: &Module, struct_id: u32) -> Vec<u32> {
+    let mut offsets: Vec<(u32, u32)> = module
+        .annotations
+        .iter()
+        .filter(|inst| inst.class.opcode == spirv::Op::MemberDecorate)
+        .filter(|inst| *extract!(&inst.operands[0], Operand::IdRef) == struct_id)
+        .filter(|inst| {
+            *extract!(&inst.operands[2], Operand::Decoration) == spirv::Decoration::Offset
+        })
+        .map(|inst| {
+            let member = *extract!(&inst.operands[1], Operand::LiteralInt32);
+            let offset = *extract!(&inst.operands[3], Operand::LiteralInt32);
+            (member, offset)
+        })
+        .collect();
+    offsets.sort_by_key(|&(member, _)| member);
+    offsets.into_iter().map(|(_, offset)| offset).collect()
+}
+
+/// Finds all `uniform` and `buffer` blocks together with the offsets of their members
+pub fn block_layouts(bytes: &[u8]) -> Vec<BlockLayout> {
+    let module = load_module(bytes);
+    module
+        .types_global_values
+        .iter()
+        .filter(|inst| inst.class.opcode == spirv::Op::Variable)
+        .filter(|inst| {
+            matches!(
+                *extract!(&inst.operands[0], Operand::StorageClass),
+                spirv::StorageClass::Uniform | spirv::StorageClass::StorageBuffer
+            )
+        })
+        .filter_map(|inst| {
+            let id = inst.result_id?;
+            let set = decoration(&module, id, spirv::Decoration::DescriptorSet)?;
+            let binding = decoration(&module, id, spirv::Decoration::Binding)?;
+            let pointer = find_result_id(&module, inst.result_type?)?;
+            let struct_id = *extract!(&pointer.operands[1], Operand::IdRef);
+            Some(BlockLayout {
+                set,
+                binding,
+                offsets: member_offsets(&module, struct_id),
+            })
+        })
+        .collect()
+}
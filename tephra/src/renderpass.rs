@@ -1,30 +1,165 @@
 use crate::context::Context;
-use crate::image::{Format, Image};
+use crate::image::{
+    ClearColor, ClearDepthStencil, Format, ImageResource, ImageUsage, SampleCount,
+};
 use derive_builder::Builder;
-use smallvec::SmallVec;
+use smallvec::{smallvec, SmallVec};
 use std::mem::size_of;
 crate::new_typed_handle!(Renderpass);
 crate::new_typed_handle!(Framebuffer);
 
 pub trait FramebufferApi {
-    unsafe fn create_framebuffer(&self, renderpass: Renderpass, images: &[Image]) -> Framebuffer;
+    /// Attachments are either whole images or views of a single mip level, in the
+    /// order of the attachment indices of `renderpass`
+    unsafe fn create_framebuffer(
+        &self,
+        renderpass: Renderpass,
+        attachments: &[ImageResource],
+    ) -> Result<Framebuffer, FramebufferError>;
+}
+
+#[derive(Debug, Fail)]
+pub enum FramebufferError {
+    #[fail(
+        display = "The renderpass has {} attachments, but the framebuffer has {}",
+        expected, actual
+    )]
+    AttachmentCountMismatch { expected: usize, actual: usize },
+    #[fail(
+        display = "Attachment {} has the format {:?}, but the renderpass expects {:?}",
+        index, actual, expected
+    )]
+    FormatMismatch {
+        index: u32,
+        expected: Format,
+        actual: Format,
+    },
+    #[fail(
+        display = "Attachment {} has {:?}, but the renderpass expects {:?}",
+        index, actual, expected
+    )]
+    SampleCountMismatch {
+        index: u32,
+        expected: SampleCount,
+        actual: SampleCount,
+    },
+    #[fail(
+        display = "Attachment {} is {:?} pixels, but the first attachment is {:?}",
+        index, actual, expected
+    )]
+    ExtentMismatch {
+        index: u32,
+        expected: (u32, u32),
+        actual: (u32, u32),
+    },
+    #[fail(display = "Attachment {} covers more than one mip level", _0)]
+    MultipleMipLevels(u32),
+    #[fail(display = "The image of attachment {} lacks the {:?} usage", index, usage)]
+    MissingUsage { index: u32, usage: ImageUsage },
 }
 
 impl Renderpass {
     pub fn builder() -> RenderpassBuilder {
         RenderpassBuilder {
             state: RenderpassState {
-                color_attachments: Attachments::new(),
-                depth_attachment: None,
+                attachments: Attachments::new(),
+                subpasses: smallvec![SubpassState::default()],
+                dependencies: Vec::new(),
             },
         }
     }
 }
 
-#[derive(Builder)]
+/// What happens to the content of an attachment when the renderpass begins
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum LoadOp {
+    Clear,
+    Load,
+    DontCare,
+}
+
+/// Whether the content of an attachment is kept when the renderpass ends
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum StoreOp {
+    Store,
+    DontCare,
+}
+
+/// Layouts an attachment can be in before and after a renderpass. The attachment
+/// and shader read layouts depend on whether the format is a color format.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum AttachmentLayout {
+    /// The previous content is discarded, only valid as the initial layout
+    Undefined,
+    Attachment,
+    ShaderRead,
+    General,
+    TransferSrc,
+    TransferDst,
+    Present,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum ClearValue {
+    Color(ClearColor),
+    DepthStencil(ClearDepthStencil),
+}
+
+impl ClearValue {
+    /// Black for color formats and a depth of 1.0 otherwise
+    pub fn default_for(format: Format) -> Self {
+        if format.has_depth() || format.has_stencil() {
+            ClearValue::DepthStencil(ClearDepthStencil::default())
+        } else {
+            ClearValue::Color(ClearColor::default())
+        }
+    }
+
+    /// Whether the value can clear an attachment with `format`
+    pub fn matches(&self, format: Format) -> bool {
+        let depth_stencil = format.has_depth() || format.has_stencil();
+        match self {
+            ClearValue::Color(_) => !depth_stencil,
+            ClearValue::DepthStencil(_) => depth_stencil,
+        }
+    }
+}
+
+impl From<ClearColor> for ClearValue {
+    fn from(color: ClearColor) -> ClearValue {
+        ClearValue::Color(color)
+    }
+}
+
+impl From<ClearDepthStencil> for ClearValue {
+    fn from(value: ClearDepthStencil) -> ClearValue {
+        ClearValue::DepthStencil(value)
+    }
+}
+
+#[derive(Builder, Clone)]
 pub struct Attachment {
     pub format: Format,
     pub index: u32,
+    #[builder(default)]
+    pub samples: SampleCount,
+    #[builder(default = "LoadOp::Clear")]
+    pub load_op: LoadOp,
+    #[builder(default = "StoreOp::Store")]
+    pub store_op: StoreOp,
+    #[builder(default = "LoadOp::DontCare")]
+    pub stencil_load_op: LoadOp,
+    #[builder(default = "StoreOp::DontCare")]
+    pub stencil_store_op: StoreOp,
+    /// Defaults to the attachment layout when the content is loaded and to
+    /// undefined otherwise
+    #[builder(default = "self.default_initial_layout()")]
+    pub initial_layout: AttachmentLayout,
+    #[builder(default = "AttachmentLayout::Attachment")]
+    pub final_layout: AttachmentLayout,
+    /// Used by clear load ops unless the draw provides its own clear values
+    #[builder(default = "self.default_clear_value()")]
+    pub clear_value: ClearValue,
 }
 
 impl Attachment {
@@ -33,26 +168,339 @@ impl Attachment {
     }
 }
 
+impl AttachmentBuilder {
+    fn default_initial_layout(&self) -> AttachmentLayout {
+        let load = self.load_op == Some(LoadOp::Load) || self.stencil_load_op == Some(LoadOp::Load);
+        if load {
+            AttachmentLayout::Attachment
+        } else {
+            AttachmentLayout::Undefined
+        }
+    }
+
+    fn default_clear_value(&self) -> ClearValue {
+        self.format
+            .map(ClearValue::default_for)
+            .unwrap_or(ClearValue::Color(ClearColor::default()))
+    }
+}
+
 pub type Attachments = SmallVec<[Attachment; 10]>;
+pub type AttachmentRefs = SmallVec<[u32; 8]>;
+
+/// The attachments that one subpass uses, referenced by their index
+#[derive(Debug, Default, Clone)]
+pub struct SubpassState {
+    pub color_attachments: AttachmentRefs,
+    /// Either empty or one single sampled attachment for every color attachment
+    pub resolve_attachments: AttachmentRefs,
+    pub depth_attachment: Option<u32>,
+    /// Attachments that earlier subpasses rendered to, read through
+    /// `DescriptorType::InputAttachment` descriptors
+    pub input_attachments: AttachmentRefs,
+    /// Attachments that this subpass doesn't use, but whose content later subpasses need
+    pub preserve_attachments: AttachmentRefs,
+}
+
+impl SubpassState {
+    /// Every attachment the subpass renders to or reads from
+    pub fn used_attachments(&self) -> impl Iterator<Item = u32> + '_ {
+        self.color_attachments
+            .iter()
+            .chain(self.resolve_attachments.iter())
+            .chain(self.depth_attachment.as_ref())
+            .chain(self.input_attachments.iter())
+            .cloned()
+    }
+
+    fn rendered_attachments(&self) -> impl Iterator<Item = u32> + '_ {
+        self.color_attachments
+            .iter()
+            .chain(self.depth_attachment.as_ref())
+            .cloned()
+    }
+}
+
+/// Makes subpass `dst` wait for the attachment writes of subpass `src`
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct SubpassDependency {
+    pub src: u32,
+    pub dst: u32,
+    /// Only waits for the writes to the same pixel, which allows tiled GPUs to keep
+    /// the attachments on chip. Enough for reading input attachments.
+    pub by_region: bool,
+}
+
+impl SubpassDependency {
+    pub fn new(src: u32, dst: u32) -> Self {
+        SubpassDependency {
+            src,
+            dst,
+            by_region: false,
+        }
+    }
+
+    pub fn by_region(src: u32, dst: u32) -> Self {
+        SubpassDependency {
+            src,
+            dst,
+            by_region: true,
+        }
+    }
+}
+
 pub struct RenderpassState {
-    pub color_attachments: Attachments,
-    pub depth_attachment: Option<Attachment>,
+    /// Every attachment, including resolve attachments, in no particular order
+    pub attachments: Attachments,
+    /// At least one subpass, recorded in order
+    pub subpasses: SmallVec<[SubpassState; 4]>,
+    pub dependencies: Vec<SubpassDependency>,
+}
+
+impl RenderpassState {
+    pub fn attachment(&self, index: u32) -> Option<&Attachment> {
+        self.attachments
+            .iter()
+            .find(|attachment| attachment.index == index)
+    }
+
+    /// The sample count that pipelines and framebuffers have to match
+    pub fn samples(&self) -> SampleCount {
+        self.subpasses
+            .iter()
+            .flat_map(SubpassState::rendered_attachments)
+            .filter_map(|index| self.attachment(index))
+            .map(|attachment| attachment.samples)
+            .next()
+            .unwrap_or_default()
+    }
+
+    pub fn validate(&self, ctx: &Context) -> Result<(), RenderpassError> {
+        for (position, attachment) in self.attachments.iter().enumerate() {
+            let duplicate = self.attachments[..position]
+                .iter()
+                .any(|other| other.index == attachment.index);
+            if duplicate {
+                return Err(RenderpassError::DuplicateAttachment(attachment.index));
+            }
+            // Framebuffers provide the images in the order of the indices
+            if attachment.index as usize >= self.attachments.len() {
+                return Err(RenderpassError::IndexOutOfRange {
+                    index: attachment.index,
+                    count: self.attachments.len(),
+                });
+            }
+            let loads = attachment.load_op == LoadOp::Load
+                || attachment.stencil_load_op == LoadOp::Load;
+            if loads && attachment.initial_layout == AttachmentLayout::Undefined {
+                return Err(RenderpassError::LoadFromUndefined(attachment.index));
+            }
+            if attachment.final_layout == AttachmentLayout::Undefined {
+                return Err(RenderpassError::UndefinedFinalLayout(attachment.index));
+            }
+            if !attachment.clear_value.matches(attachment.format) {
+                return Err(RenderpassError::ClearValueMismatch(attachment.index));
+            }
+        }
+        for (subpass, state) in self.subpasses.iter().enumerate() {
+            self.validate_subpass(ctx, subpass as u32, state)?;
+        }
+        let subpass_count = self.subpasses.len() as u32;
+        for dependency in &self.dependencies {
+            if dependency.src > dependency.dst || dependency.dst >= subpass_count {
+                return Err(RenderpassError::InvalidDependency {
+                    src: dependency.src,
+                    dst: dependency.dst,
+                });
+            }
+        }
+        Ok(())
+    }
+
+    fn validate_subpass(
+        &self,
+        ctx: &Context,
+        subpass: u32,
+        state: &SubpassState,
+    ) -> Result<(), RenderpassError> {
+        for index in state.used_attachments().chain(state.preserve_attachments.iter().cloned()) {
+            if self.attachment(index).is_none() {
+                return Err(RenderpassError::UnknownAttachment { subpass, index });
+            }
+        }
+        if let Some(&index) = state
+            .input_attachments
+            .iter()
+            .find(|index| state.rendered_attachments().any(|used| used == **index))
+        {
+            return Err(RenderpassError::InputAttachmentWritten { subpass, index });
+        }
+        if let Some(&index) = state
+            .preserve_attachments
+            .iter()
+            .find(|index| state.used_attachments().any(|used| used == **index))
+        {
+            return Err(RenderpassError::PreservedAttachmentUsed { subpass, index });
+        }
+        let limits = ctx.limits();
+        let samples = self.samples();
+        for &index in &state.color_attachments {
+            let attachment = self.attachment(index).unwrap();
+            if attachment.samples != samples {
+                return Err(RenderpassError::MixedSampleCounts);
+            }
+            if !attachment
+                .samples
+                .is_supported(limits.framebuffer_color_sample_counts)
+            {
+                return Err(RenderpassError::UnsupportedSampleCount(attachment.samples));
+            }
+        }
+        if let Some(index) = state.depth_attachment {
+            let depth_attachment = self.attachment(index).unwrap();
+            if depth_attachment.samples != samples {
+                return Err(RenderpassError::MixedSampleCounts);
+            }
+            if !depth_attachment
+                .samples
+                .is_supported(limits.framebuffer_depth_sample_counts)
+            {
+                return Err(RenderpassError::UnsupportedSampleCount(
+                    depth_attachment.samples,
+                ));
+            }
+        }
+        if state.resolve_attachments.is_empty() {
+            return Ok(());
+        }
+        if state.resolve_attachments.len() != state.color_attachments.len() {
+            return Err(RenderpassError::ResolveCountMismatch {
+                color: state.color_attachments.len(),
+                resolve: state.resolve_attachments.len(),
+            });
+        }
+        if samples == SampleCount::S1 {
+            return Err(RenderpassError::ResolveSingleSampled);
+        }
+        if let Some(&resolve) = state
+            .resolve_attachments
+            .iter()
+            .find(|&&index| self.attachment(index).unwrap().samples != SampleCount::S1)
+        {
+            return Err(RenderpassError::MultisampledResolve(resolve));
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Fail)]
+pub enum RenderpassError {
+    #[fail(display = "The device doesn't support {:?} for attachments", _0)]
+    UnsupportedSampleCount(SampleCount),
+    #[fail(display = "All attachments of a renderpass need the same sample count")]
+    MixedSampleCounts,
+    #[fail(
+        display = "{} resolve attachments for {} color attachments",
+        resolve, color
+    )]
+    ResolveCountMismatch { color: usize, resolve: usize },
+    #[fail(display = "Only multisampled attachments can be resolved")]
+    ResolveSingleSampled,
+    #[fail(display = "Resolve attachment {} must be single sampled", _0)]
+    MultisampledResolve(u32),
+    #[fail(display = "Attachment {} loads its content from an undefined layout", _0)]
+    LoadFromUndefined(u32),
+    #[fail(display = "Attachment {} can't end in the undefined layout", _0)]
+    UndefinedFinalLayout(u32),
+    #[fail(display = "The clear value of attachment {} doesn't match its format", _0)]
+    ClearValueMismatch(u32),
+    #[fail(display = "Attachment {} was added more than once", _0)]
+    DuplicateAttachment(u32),
+    #[fail(
+        display = "Attachment index {} is out of range, {} attachments need the indices 0..{}",
+        index, count, count
+    )]
+    IndexOutOfRange { index: u32, count: usize },
+    #[fail(display = "Subpass {} uses attachment {}, which was never added", subpass, index)]
+    UnknownAttachment { subpass: u32, index: u32 },
+    #[fail(
+        display = "Subpass {} reads attachment {} as an input attachment while rendering to it",
+        subpass, index
+    )]
+    InputAttachmentWritten { subpass: u32, index: u32 },
+    #[fail(
+        display = "Subpass {} preserves attachment {}, which it already uses",
+        subpass, index
+    )]
+    PreservedAttachmentUsed { subpass: u32, index: u32 },
+    #[fail(
+        display = "Subpass {} can't depend on subpass {}, dependencies have to point forward",
+        dst, src
+    )]
+    InvalidDependency { src: u32, dst: u32 },
 }
 pub struct RenderpassBuilder {
     state: RenderpassState,
 }
+/// Attachments are added to the current subpass, which is the first one until
+/// [`next_subpass`](RenderpassBuilder::next_subpass) starts another one. Later subpasses
+/// refer to attachments that were already added by their index.
 impl RenderpassBuilder {
     pub fn color_attachment(mut self, attachment: Attachment) -> Self {
-        self.state.color_attachments.push(attachment);
+        self.current().color_attachments.push(attachment.index);
+        self.state.attachments.push(attachment);
+        self
+    }
+    /// Resolves the multisampled color attachment that was added at the same position
+    pub fn resolve_attachment(mut self, attachment: Attachment) -> Self {
+        self.current().resolve_attachments.push(attachment.index);
+        self.state.attachments.push(attachment);
         self
     }
     pub fn with_depth_attachment(mut self, attachment: Attachment) -> Self {
-        self.state.depth_attachment = Some(attachment);
+        self.current().depth_attachment = Some(attachment.index);
+        self.state.attachments.push(attachment);
+        self
+    }
+    /// Renders to an attachment that an earlier subpass added
+    pub fn use_color_attachment(mut self, index: u32) -> Self {
+        self.current().color_attachments.push(index);
+        self
+    }
+    /// Tests against a depth attachment that an earlier subpass added
+    pub fn use_depth_attachment(mut self, index: u32) -> Self {
+        self.current().depth_attachment = Some(index);
         self
     }
+    /// Reads what an earlier subpass rendered into attachment `index` at the same pixel
+    pub fn input_attachment(mut self, index: u32) -> Self {
+        self.current().input_attachments.push(index);
+        self
+    }
+    /// Keeps the content of attachment `index` alive through the current subpass
+    pub fn preserve_attachment(mut self, index: u32) -> Self {
+        self.current().preserve_attachments.push(index);
+        self
+    }
+    pub fn next_subpass(mut self) -> Self {
+        self.state.subpasses.push(SubpassState::default());
+        self
+    }
+    pub fn dependency(mut self, dependency: SubpassDependency) -> Self {
+        self.state.dependencies.push(dependency);
+        self
+    }
+
+    fn current(&mut self) -> &mut SubpassState {
+        self.state
+            .subpasses
+            .last_mut()
+            .expect("A renderpass has at least one subpass")
+    }
 
-    pub unsafe fn create(self, ctx: &Context) -> Renderpass {
-        ctx.create_renderpass(&self.state)
+    pub unsafe fn create(self, ctx: &Context) -> Result<Renderpass, RenderpassError> {
+        self.state.validate(ctx)?;
+        Ok(ctx.create_renderpass(&self.state))
     }
 }
 
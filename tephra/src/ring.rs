@@ -0,0 +1,189 @@
+use crate::{
+    buffer::{AllocationError, Buffer, BufferError, BufferHandle, BufferUsage, Property},
+    context::Context,
+};
+use std::marker::PhantomData;
+use std::mem::size_of;
+
+/// A sub allocation inside a [`RingBuffer`]. It is bound as a
+/// `DescriptorType::UniformDynamic` and `offset` is used as the dynamic offset.
+#[derive(Debug)]
+pub struct RingSlice<T> {
+    pub buffer: BufferHandle,
+    pub offset: u32,
+    pub _m: PhantomData<T>,
+}
+
+impl<T> Copy for RingSlice<T> {}
+impl<T> Clone for RingSlice<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T> RingSlice<T> {
+    pub fn range(&self) -> u64 {
+        size_of::<T>() as u64
+    }
+}
+
+/// Offsets of the allocations inside a [`RingBuffer`], kept apart from the
+/// mapping.
+#[derive(Debug)]
+struct RingCursor {
+    alignment: u64,
+    frame_size: u64,
+    frame_count: u32,
+    frame: u32,
+    head: u64,
+}
+
+fn align_up(value: u64, alignment: u64) -> u64 {
+    value.div_ceil(alignment) * alignment
+}
+
+impl RingCursor {
+    fn new(alignment: u64, frame_size: u64, frame_count: u32) -> Self {
+        assert!(frame_count > 0, "RingBuffer needs at least one frame");
+        let alignment = alignment.max(1);
+        RingCursor {
+            alignment,
+            frame_size: align_up(frame_size, alignment),
+            frame_count,
+            frame: 0,
+            head: 0,
+        }
+    }
+
+    fn size(&self) -> u64 {
+        self.frame_size * self.frame_count as u64
+    }
+
+    /// Returns the offset of `size` bytes in the buffer, or `None` if the region
+    /// of the current frame is full
+    fn allocate(&mut self, size: u64) -> Option<u64> {
+        let local_offset = align_up(self.head, self.alignment);
+        if local_offset + size > self.frame_size {
+            return None;
+        }
+        self.head = local_offset + size;
+        Some(self.frame as u64 * self.frame_size + local_offset)
+    }
+
+    fn next_frame(&mut self) {
+        self.frame = (self.frame + 1) % self.frame_count;
+        self.head = 0;
+    }
+}
+
+/// Sub allocates transient uniform data from one persistently mapped buffer.
+/// The buffer is split into one region per frame in flight, the region of the
+/// current frame is reused after [`RingBuffer::next_frame`] wrapped around.
+pub struct RingBuffer {
+    ctx: Context,
+    buffer: Buffer<u8>,
+    mapping: *mut u8,
+    cursor: RingCursor,
+}
+
+impl RingBuffer {
+    pub fn new(ctx: &Context, frame_size: u64, frame_count: u32) -> Result<Self, BufferError> {
+        let alignment = ctx.limits().min_uniform_buffer_offset_alignment;
+        let cursor = RingCursor::new(alignment, frame_size, frame_count);
+        let buffer = Buffer::allocate(ctx, Property::Upload, BufferUsage::UNIFORM, cursor.size())?;
+        let mapping = unsafe {
+            ctx.map_memory(buffer.buffer)
+                .map_err(BufferError::MappingError)?
+        };
+        Ok(RingBuffer {
+            ctx: ctx.clone(),
+            buffer,
+            mapping: mapping as *mut u8,
+            cursor,
+        })
+    }
+
+    pub fn buffer(&self) -> BufferHandle {
+        self.buffer.buffer
+    }
+
+    /// Copies `data` into the region of the current frame. The returned
+    /// offset is aligned to `minUniformBufferOffsetAlignment`.
+    pub fn allocate<T: Copy>(&mut self, data: &T) -> Result<RingSlice<T>, BufferError> {
+        let offset = self
+            .cursor
+            .allocate(size_of::<T>() as u64)
+            .ok_or(BufferError::AllocationError(AllocationError::OutOfSpace))?;
+        unsafe {
+            let ptr = self.mapping.add(offset as usize) as *mut T;
+            ptr.write_unaligned(*data);
+        }
+        Ok(RingSlice {
+            buffer: self.buffer.buffer,
+            offset: offset as u32,
+            _m: PhantomData,
+        })
+    }
+
+    /// Makes the writes of the current frame visible to the device. Needs to be
+    /// called before the command list that uses the slices is submitted.
+    pub fn flush(&self) -> Result<(), BufferError> {
+        unsafe {
+            self.ctx
+                .flush(self.buffer.buffer)
+                .map_err(BufferError::MappingError)
+        }
+    }
+
+    /// Moves on to the region of the next frame. The caller has to make sure
+    /// that the command lists that used this region `frame_count` frames ago
+    /// have finished.
+    pub fn next_frame(&mut self) {
+        self.cursor.next_frame();
+    }
+}
+
+impl Drop for RingBuffer {
+    fn drop(&mut self) {
+        unsafe {
+            self.ctx.unmap_memory(self.buffer.buffer);
+        }
+        self.ctx.destroy(self.buffer.buffer);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn offsets_are_aligned_to_the_device_limit() {
+        let mut cursor = RingCursor::new(256, 1000, 2);
+        assert_eq!(cursor.frame_size, 1024);
+        assert_eq!(cursor.size(), 2048);
+        assert_eq!(cursor.allocate(4), Some(0));
+        assert_eq!(cursor.allocate(64), Some(256));
+        assert_eq!(cursor.allocate(256), Some(512));
+        assert_eq!(cursor.allocate(256), Some(768));
+        // The frame is full, the next frame isn't touched
+        assert_eq!(cursor.allocate(1), None);
+    }
+
+    #[test]
+    fn frames_wrap_around() {
+        let mut cursor = RingCursor::new(64, 128, 3);
+        for frame in 0..7 {
+            let base = (frame % 3) * 128;
+            assert_eq!(cursor.allocate(16), Some(base));
+            assert_eq!(cursor.allocate(16), Some(base + 64));
+            cursor.next_frame();
+        }
+    }
+
+    #[test]
+    fn zero_alignment_is_treated_as_one() {
+        let mut cursor = RingCursor::new(0, 10, 1);
+        assert_eq!(cursor.allocate(3), Some(0));
+        assert_eq!(cursor.allocate(3), Some(3));
+    }
+}
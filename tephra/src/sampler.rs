@@ -0,0 +1,138 @@
+use crate::context::Context;
+use derive_builder::Builder;
+use std::hash::{Hash, Hasher};
+
+crate::new_typed_handle!(Sampler);
+
+pub trait SamplerApi {
+    /// Samplers are deduplicated, creating a sampler with the same description
+    /// twice returns the same handle.
+    fn create_sampler(&self, desc: &SamplerDesc) -> Result<Sampler, SamplerError>;
+}
+
+#[derive(Debug, Fail)]
+pub enum SamplerError {
+    #[fail(display = "The device doesn't support the address mode {:?}", _0)]
+    UnsupportedAddressMode(AddressMode),
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum Filter {
+    Nearest,
+    Linear,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum MipmapMode {
+    Nearest,
+    Linear,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum AddressMode {
+    Repeat,
+    MirroredRepeat,
+    ClampToEdge,
+    ClampToBorder,
+    /// Needs `VK_KHR_sampler_mirror_clamp_to_edge`, samplers using it fail to be
+    /// created without it
+    MirrorClampToEdge,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum BorderColor {
+    TransparentBlack,
+    OpaqueBlack,
+    OpaqueWhite,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum CompareOp {
+    Never,
+    Less,
+    Equal,
+    LessOrEqual,
+    Greater,
+    NotEqual,
+    GreaterOrEqual,
+    Always,
+}
+
+#[derive(Debug, Copy, Clone, Builder)]
+#[builder(pattern = "owned")]
+pub struct SamplerDesc {
+    #[builder(default = "Filter::Linear")]
+    pub mag_filter: Filter,
+    #[builder(default = "Filter::Linear")]
+    pub min_filter: Filter,
+    #[builder(default = "MipmapMode::Linear")]
+    pub mipmap_mode: MipmapMode,
+    #[builder(default = "AddressMode::Repeat")]
+    pub address_mode_u: AddressMode,
+    #[builder(default = "AddressMode::Repeat")]
+    pub address_mode_v: AddressMode,
+    #[builder(default = "AddressMode::Repeat")]
+    pub address_mode_w: AddressMode,
+    /// Disabled with `None`. Clamped to the device limit and ignored if the
+    /// device doesn't support anisotropic filtering.
+    #[builder(default)]
+    pub max_anisotropy: Option<f32>,
+    /// Turns the sampler into a comparison sampler for shadow maps
+    #[builder(default)]
+    pub compare_op: Option<CompareOp>,
+    #[builder(default = "0.0")]
+    pub mip_lod_bias: f32,
+    #[builder(default = "0.0")]
+    pub min_lod: f32,
+    /// The default samples all mip levels
+    #[builder(default = "1000.0")]
+    pub max_lod: f32,
+    #[builder(default = "BorderColor::TransparentBlack")]
+    pub border_color: BorderColor,
+}
+
+impl SamplerDesc {
+    pub fn builder() -> SamplerDescBuilder {
+        SamplerDescBuilder::default()
+    }
+
+    pub fn create(&self, ctx: &Context) -> Result<Sampler, SamplerError> {
+        ctx.create_sampler(self)
+    }
+
+    // Floats are compared by their bits, so that the description can be used
+    // as a key for deduplication.
+    fn key(&self) -> impl Eq + Hash {
+        (
+            (self.mag_filter, self.min_filter, self.mipmap_mode),
+            (self.address_mode_u, self.address_mode_v, self.address_mode_w),
+            self.max_anisotropy.map(f32::to_bits),
+            self.compare_op,
+            (
+                self.mip_lod_bias.to_bits(),
+                self.min_lod.to_bits(),
+                self.max_lod.to_bits(),
+            ),
+            self.border_color,
+        )
+    }
+}
+
+impl Default for SamplerDesc {
+    fn default() -> Self {
+        SamplerDesc::builder().build().unwrap()
+    }
+}
+
+impl PartialEq for SamplerDesc {
+    fn eq(&self, other: &Self) -> bool {
+        self.key() == other.key()
+    }
+}
+impl Eq for SamplerDesc {}
+
+impl Hash for SamplerDesc {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.key().hash(state);
+    }
+}
@@ -7,6 +7,8 @@ crate::new_typed_handle!(ShaderModule);
 
 pub trait ShaderApi {
     unsafe fn create_shader(&self, bytes: &[u8]) -> Result<ShaderModule, ShaderError>;
+    /// The SPIR-V code the module was created from
+    fn spirv(&self, module: ShaderModule) -> Vec<u8>;
 }
 
 pub enum ShaderType {
@@ -0,0 +1,422 @@
+//! Loaders for the KTX2 and DDS texture containers. Both upload every stored mip
+//! level, layer and cube face without decoding compressed formats.
+use crate::buffer::{Buffer, BufferUsage, Property};
+use crate::context::Context;
+use crate::image::{
+    max_mip_levels, BufferRegion, Format, Image, ImageDesc, ImageDimension, ImageError,
+    ImageLayout, ImageUsage, Resolution,
+};
+use std::path::Path;
+
+impl Image {
+    /// Loads a KTX2 file. Supercompressed files and files without a Vulkan format
+    /// are rejected. Files without mip levels only upload the base level.
+    pub fn from_ktx2<P: AsRef<Path>>(ctx: &Context, path: P) -> Result<Image, ImageError> {
+        let bytes = std::fs::read(path).map_err(ImageError::Read)?;
+        parse_ktx2(&bytes)?.upload(ctx)
+    }
+
+    /// Loads a DDS file, either with a DX10 header or with one of the legacy
+    /// BC or RGBA8 pixel formats
+    pub fn from_dds<P: AsRef<Path>>(ctx: &Context, path: P) -> Result<Image, ImageError> {
+        let bytes = std::fs::read(path).map_err(ImageError::Read)?;
+        parse_dds(&bytes)?.upload(ctx)
+    }
+}
+
+struct Texture<'a> {
+    desc: ImageDesc,
+    /// The staged bytes, the offsets of the regions are relative to them
+    data: &'a [u8],
+    regions: Vec<BufferRegion>,
+}
+
+impl<'a> Texture<'a> {
+    fn upload(self, ctx: &Context) -> Result<Image, ImageError> {
+        let format = self.desc.format;
+        if !ctx.format_usage(format).contains(ImageUsage::SAMPLED) {
+            return Err(ImageError::UnsupportedFormat(format));
+        }
+        let staging = Buffer::from_slice(ctx, Property::Upload, BufferUsage::empty(), self.data)
+            .map_err(ImageError::BufferError)?;
+        let handle = ctx.from_buffer_regions(self.desc, staging, &self.regions);
+        ctx.destroy(staging.buffer);
+        Ok(Image { handle: handle? })
+    }
+}
+
+fn u32_at(bytes: &[u8], offset: usize) -> Result<u32, ImageError> {
+    bytes
+        .get(offset..offset + 4)
+        .map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+        .ok_or(ImageError::InvalidContainer("Unexpected end of file"))
+}
+
+fn u64_at(bytes: &[u8], offset: usize) -> Result<u64, ImageError> {
+    let low = u32_at(bytes, offset)? as u64;
+    let high = u32_at(bytes, offset + 4)? as u64;
+    Ok(high << 32 | low)
+}
+
+/// Size in bytes of one layer of a mip level, `None` for formats without metadata
+fn level_size(desc: &ImageDesc, level: u32) -> Option<u64> {
+    let (block_width, block_height) = desc.format.block_extent()?;
+    let block_bytes = desc.format.block_size()?;
+    let width = (desc.resolution.width >> level).max(1);
+    let height = (desc.resolution.height >> level).max(1);
+    let depth = (desc.depth >> level).max(1);
+    let blocks_x = width.div_ceil(block_width);
+    let blocks_y = height.div_ceil(block_height);
+    Some(blocks_x as u64 * blocks_y as u64 * depth as u64 * block_bytes as u64)
+}
+
+/// Rejects containers that claim more mip levels than their extent has
+fn check_mip_levels(desc: &ImageDesc, error: &'static str) -> Result<(), ImageError> {
+    if desc.mip_levels > max_mip_levels(desc.resolution, desc.depth) {
+        return Err(ImageError::InvalidContainer(error));
+    }
+    Ok(())
+}
+
+const KTX2_IDENTIFIER: [u8; 12] = [
+    0xAB, b'K', b'T', b'X', b' ', b'2', b'0', 0xBB, b'\r', b'\n', 0x1A, b'\n',
+];
+/// Size of the identifier, the header and the index before the level index
+const KTX2_LEVEL_INDEX: usize = 80;
+
+fn parse_ktx2(bytes: &[u8]) -> Result<Texture<'_>, ImageError> {
+    if !bytes.starts_with(&KTX2_IDENTIFIER) {
+        return Err(ImageError::InvalidContainer("Missing the KTX2 identifier"));
+    }
+    let vk_format = u32_at(bytes, 12)?;
+    let width = u32_at(bytes, 20)?;
+    let height = u32_at(bytes, 24)?;
+    let depth = u32_at(bytes, 28)?;
+    let layer_count = u32_at(bytes, 32)?;
+    let face_count = u32_at(bytes, 36)?;
+    let level_count = u32_at(bytes, 40)?;
+    let supercompression = u32_at(bytes, 44)?;
+    if vk_format == 0 {
+        return Err(ImageError::UnsupportedContainerFormat(
+            "KTX2 without a Vulkan format".into(),
+        ));
+    }
+    if supercompression != 0 {
+        return Err(ImageError::UnsupportedContainerFormat(format!(
+            "KTX2 supercompression scheme {}",
+            supercompression
+        )));
+    }
+    let dimension = match (height, depth, face_count) {
+        (_, _, 6) => ImageDimension::Cube,
+        (0, _, 1) => ImageDimension::D1,
+        (_, 0, 1) => ImageDimension::D2,
+        (_, _, 1) if layer_count <= 1 => ImageDimension::D3,
+        _ => return Err(ImageError::InvalidContainer("Invalid KTX2 dimensions")),
+    };
+    let count = match dimension {
+        ImageDimension::D3 => depth,
+        _ => layer_count.max(1),
+    };
+    let format = Format::from_raw(vk_format as i32);
+    let resolution = Resolution {
+        width,
+        height: height.max(1),
+    };
+    let mut desc = ImageDesc::new(resolution, ImageLayout::Sampled, format)
+        .with_dimension(dimension, count);
+    desc.mip_levels = level_count.max(1);
+    check_mip_levels(&desc, "KTX2 has more levels than its extent")?;
+    let regions = (0..desc.mip_levels)
+        .map(|level| {
+            let entry = KTX2_LEVEL_INDEX + level as usize * 24;
+            let offset = u64_at(bytes, entry)?;
+            let length = u64_at(bytes, entry + 8)?;
+            if offset.checked_add(length).is_none_or(|end| end > bytes.len() as u64) {
+                return Err(ImageError::InvalidContainer("KTX2 level is out of bounds"));
+            }
+            // Formats without metadata are only checked against the file size
+            if let Some(size) = level_size(&desc, level) {
+                if length < size * desc.layers as u64 {
+                    return Err(ImageError::InvalidContainer("KTX2 level is truncated"));
+                }
+            }
+            Ok(BufferRegion {
+                offset,
+                mip_level: level,
+                base_layer: 0,
+                layer_count: desc.layers,
+            })
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    // Levels are aligned relative to the start of the file, so the whole file is staged
+    Ok(Texture {
+        desc,
+        data: bytes,
+        regions,
+    })
+}
+
+const DDS_HEADER_SIZE: u32 = 124;
+const DDPF_FOURCC: u32 = 0x4;
+const DDPF_RGB: u32 = 0x40;
+const DDSCAPS2_CUBEMAP: u32 = 0x200;
+const DDSCAPS2_VOLUME: u32 = 0x20_0000;
+const DDS_RESOURCE_MISC_TEXTURECUBE: u32 = 0x4;
+const D3D10_RESOURCE_DIMENSION_TEXTURE1D: u32 = 2;
+const D3D10_RESOURCE_DIMENSION_TEXTURE3D: u32 = 4;
+
+fn parse_dds(bytes: &[u8]) -> Result<Texture<'_>, ImageError> {
+    if !bytes.starts_with(b"DDS ") || u32_at(bytes, 4)? != DDS_HEADER_SIZE {
+        return Err(ImageError::InvalidContainer("Missing the DDS magic"));
+    }
+    let height = u32_at(bytes, 12)?;
+    let width = u32_at(bytes, 16)?;
+    let depth = u32_at(bytes, 24)?.max(1);
+    let mip_levels = u32_at(bytes, 28)?.max(1);
+    // Also makes sure that the whole header is there
+    let caps2 = u32_at(bytes, 112)?;
+    let pixel_flags = u32_at(bytes, 80)?;
+    let four_cc = &bytes[84..88];
+    let is_dx10 = pixel_flags & DDPF_FOURCC != 0 && four_cc == b"DX10";
+    let (format, dimension, count, data_start) = if is_dx10 {
+        let dxgi_format = u32_at(bytes, 128)?;
+        let resource_dimension = u32_at(bytes, 132)?;
+        let misc_flags = u32_at(bytes, 136)?;
+        let array_size = u32_at(bytes, 140)?.max(1);
+        let format = from_dxgi(dxgi_format).ok_or_else(|| {
+            ImageError::UnsupportedContainerFormat(format!("DXGI format {}", dxgi_format))
+        })?;
+        let (dimension, count) = match resource_dimension {
+            D3D10_RESOURCE_DIMENSION_TEXTURE1D => (ImageDimension::D1, array_size),
+            D3D10_RESOURCE_DIMENSION_TEXTURE3D => (ImageDimension::D3, depth),
+            _ if misc_flags & DDS_RESOURCE_MISC_TEXTURECUBE != 0 => {
+                (ImageDimension::Cube, array_size)
+            }
+            _ => (ImageDimension::D2, array_size),
+        };
+        (format, dimension, count, 148)
+    } else {
+        let format = from_legacy_pixel_format(bytes, pixel_flags, four_cc)?;
+        // Legacy cube maps always store all six faces
+        let (dimension, count) = if caps2 & DDSCAPS2_CUBEMAP != 0 {
+            (ImageDimension::Cube, 1)
+        } else if caps2 & DDSCAPS2_VOLUME != 0 {
+            (ImageDimension::D3, depth)
+        } else {
+            (ImageDimension::D2, 1)
+        };
+        (format, dimension, count, 128)
+    };
+    let resolution = Resolution {
+        width,
+        height: height.max(1),
+    };
+    let mut desc = ImageDesc::new(resolution, ImageLayout::Sampled, format)
+        .with_dimension(dimension, count);
+    desc.mip_levels = mip_levels;
+    check_mip_levels(&desc, "DDS has more mip levels than its extent")?;
+    let data = bytes
+        .get(data_start..)
+        .ok_or(ImageError::InvalidContainer("DDS pixel data is truncated"))?;
+    // DDS stores each layer with all of its mip levels before the next layer
+    let mut regions = Vec::new();
+    let mut offset = 0;
+    for layer in 0..desc.layers {
+        for level in 0..desc.mip_levels {
+            regions.push(BufferRegion {
+                offset,
+                mip_level: level,
+                base_layer: layer,
+                layer_count: 1,
+            });
+            offset += level_size(&desc, level).expect("All DDS formats have metadata");
+            // Stops early for headers with absurd layer counts
+            if offset > data.len() as u64 {
+                return Err(ImageError::InvalidContainer("DDS pixel data is truncated"));
+            }
+        }
+    }
+    Ok(Texture {
+        desc,
+        data,
+        regions,
+    })
+}
+
+fn from_legacy_pixel_format(
+    bytes: &[u8],
+    pixel_flags: u32,
+    four_cc: &[u8],
+) -> Result<Format, ImageError> {
+    if pixel_flags & DDPF_FOURCC != 0 {
+        let format = match four_cc {
+            b"DXT1" => Format::BC1_RGBA_UNORM_BLOCK,
+            b"DXT2" | b"DXT3" => Format::BC2_UNORM_BLOCK,
+            b"DXT4" | b"DXT5" => Format::BC3_UNORM_BLOCK,
+            b"ATI1" | b"BC4U" => Format::BC4_UNORM_BLOCK,
+            b"BC4S" => Format::BC4_SNORM_BLOCK,
+            b"ATI2" | b"BC5U" => Format::BC5_UNORM_BLOCK,
+            b"BC5S" => Format::BC5_SNORM_BLOCK,
+            // D3DFMT_A16B16G16R16F and D3DFMT_A32B32G32R32F
+            &[113, 0, 0, 0] => Format::R16G16B16A16_SFLOAT,
+            &[116, 0, 0, 0] => Format::R32G32B32A32_SFLOAT,
+            _ => {
+                return Err(ImageError::UnsupportedContainerFormat(format!(
+                    "DDS FourCC {}",
+                    String::from_utf8_lossy(four_cc)
+                )));
+            }
+        };
+        return Ok(format);
+    }
+    let bit_count = u32_at(bytes, 88)?;
+    let red_mask = u32_at(bytes, 92)?;
+    match (pixel_flags & DDPF_RGB != 0, bit_count, red_mask) {
+        (true, 32, 0x0000_00FF) => Ok(Format::R8G8B8A8_UNORM),
+        (true, 32, 0x00FF_0000) => Ok(Format::B8G8R8A8_UNORM),
+        _ => Err(ImageError::UnsupportedContainerFormat(format!(
+            "DDS pixel format with {} bits and red mask {:#x}",
+            bit_count, red_mask
+        ))),
+    }
+}
+
+fn from_dxgi(dxgi_format: u32) -> Option<Format> {
+    let format = match dxgi_format {
+        2 => Format::R32G32B32A32_SFLOAT,
+        10 => Format::R16G16B16A16_SFLOAT,
+        11 => Format::R16G16B16A16_UNORM,
+        24 => Format::A2B10G10R10_UNORM_PACK32,
+        26 => Format::B10G11R11_UFLOAT_PACK32,
+        28 => Format::R8G8B8A8_UNORM,
+        29 => Format::R8G8B8A8_SRGB,
+        34 => Format::R16G16_SFLOAT,
+        41 => Format::R32_SFLOAT,
+        49 => Format::R8G8_UNORM,
+        54 => Format::R16_SFLOAT,
+        56 => Format::R16_UNORM,
+        61 => Format::R8_UNORM,
+        71 => Format::BC1_RGBA_UNORM_BLOCK,
+        72 => Format::BC1_RGBA_SRGB_BLOCK,
+        74 => Format::BC2_UNORM_BLOCK,
+        75 => Format::BC2_SRGB_BLOCK,
+        77 => Format::BC3_UNORM_BLOCK,
+        78 => Format::BC3_SRGB_BLOCK,
+        80 => Format::BC4_UNORM_BLOCK,
+        81 => Format::BC4_SNORM_BLOCK,
+        83 => Format::BC5_UNORM_BLOCK,
+        84 => Format::BC5_SNORM_BLOCK,
+        87 => Format::B8G8R8A8_UNORM,
+        91 => Format::B8G8R8A8_SRGB,
+        95 => Format::BC6H_UFLOAT_BLOCK,
+        96 => Format::BC6H_SFLOAT_BLOCK,
+        98 => Format::BC7_UNORM_BLOCK,
+        99 => Format::BC7_SRGB_BLOCK,
+        _ => return None,
+    };
+    Some(format)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn put_u32(bytes: &mut [u8], offset: usize, value: u32) {
+        bytes[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+    }
+
+    fn put_u64(bytes: &mut [u8], offset: usize, value: u64) {
+        bytes[offset..offset + 8].copy_from_slice(&value.to_le_bytes());
+    }
+
+    fn is_invalid(result: Result<Texture<'_>, ImageError>) -> bool {
+        matches!(result, Err(ImageError::InvalidContainer(_)))
+    }
+
+    /// A 4x4 R8G8B8A8_UNORM file with tightly packed levels after the level index
+    fn ktx2(level_count: u32) -> Vec<u8> {
+        let index_end = KTX2_LEVEL_INDEX + level_count as usize * 24;
+        let sizes = [64u64, 16, 4];
+        let mut bytes = vec![0; index_end + sizes.iter().sum::<u64>() as usize];
+        bytes[..12].copy_from_slice(&KTX2_IDENTIFIER);
+        put_u32(&mut bytes, 12, Format::R8G8B8A8_UNORM.as_raw() as u32);
+        put_u32(&mut bytes, 20, 4);
+        put_u32(&mut bytes, 24, 4);
+        put_u32(&mut bytes, 36, 1);
+        put_u32(&mut bytes, 40, level_count);
+        let mut offset = index_end as u64;
+        for level in 0..level_count as usize {
+            let size = sizes.get(level).cloned().unwrap_or(4);
+            put_u64(&mut bytes, KTX2_LEVEL_INDEX + level * 24, offset);
+            put_u64(&mut bytes, KTX2_LEVEL_INDEX + level * 24 + 8, size);
+            offset += size;
+        }
+        bytes
+    }
+
+    /// An 8x8 DXT1 file without a DX10 header
+    fn dds(mip_levels: u32, data_len: usize) -> Vec<u8> {
+        let mut bytes = vec![0; 128 + data_len];
+        bytes[..4].copy_from_slice(b"DDS ");
+        put_u32(&mut bytes, 4, DDS_HEADER_SIZE);
+        put_u32(&mut bytes, 12, 8);
+        put_u32(&mut bytes, 16, 8);
+        put_u32(&mut bytes, 28, mip_levels);
+        put_u32(&mut bytes, 80, DDPF_FOURCC);
+        bytes[84..88].copy_from_slice(b"DXT1");
+        bytes
+    }
+
+    #[test]
+    fn ktx2_levels() {
+        let bytes = ktx2(3);
+        let texture = parse_ktx2(&bytes).unwrap_or_else(|_| panic!("Valid KTX2"));
+        assert_eq!(texture.desc.format, Format::R8G8B8A8_UNORM);
+        assert_eq!(texture.desc.dimension, ImageDimension::D2);
+        assert_eq!(texture.desc.mip_levels, 3);
+        let offsets: Vec<_> = texture.regions.iter().map(|region| region.offset).collect();
+        assert_eq!(offsets, vec![152, 216, 232]);
+    }
+
+    #[test]
+    fn ktx2_rejects_invalid_headers() {
+        assert!(is_invalid(parse_ktx2(&ktx2(4))));
+        let mut truncated = ktx2(3);
+        put_u64(&mut truncated, KTX2_LEVEL_INDEX + 8, 32);
+        assert!(is_invalid(parse_ktx2(&truncated)));
+        let mut out_of_bounds = ktx2(3);
+        put_u64(&mut out_of_bounds, KTX2_LEVEL_INDEX, u64::MAX);
+        assert!(is_invalid(parse_ktx2(&out_of_bounds)));
+        let mut supercompressed = ktx2(3);
+        put_u32(&mut supercompressed, 44, 1);
+        match parse_ktx2(&supercompressed) {
+            Err(ImageError::UnsupportedContainerFormat(_)) => (),
+            _ => panic!("Supercompression is unsupported"),
+        }
+    }
+
+    #[test]
+    fn dds_levels() {
+        let bytes = dds(4, 56);
+        let texture = parse_dds(&bytes).unwrap_or_else(|_| panic!("Valid DDS"));
+        assert_eq!(texture.desc.format, Format::BC1_RGBA_UNORM_BLOCK);
+        assert_eq!(texture.desc.mip_levels, 4);
+        assert_eq!(texture.data.len(), 56);
+        let offsets: Vec<_> = texture.regions.iter().map(|region| region.offset).collect();
+        assert_eq!(offsets, vec![0, 32, 40, 48]);
+    }
+
+    #[test]
+    fn dds_rejects_invalid_headers() {
+        assert!(is_invalid(parse_dds(&dds(5, 64))));
+        assert!(is_invalid(parse_dds(&dds(4, 55))));
+        assert!(is_invalid(parse_dds(&dds(1, 0)[..100])));
+        let mut unknown = dds(1, 32);
+        unknown[84..88].copy_from_slice(b"ABCD");
+        match parse_dds(&unknown) {
+            Err(ImageError::UnsupportedContainerFormat(_)) => (),
+            _ => panic!("Unknown FourCC codes are unsupported"),
+        }
+    }
+}